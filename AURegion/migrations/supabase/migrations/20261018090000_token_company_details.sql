@@ -0,0 +1,18 @@
+-- Resolve company details from a session token by delegating to the username/password
+-- variant, so both always return the same shape. Only active, unexpired tokens resolve;
+-- the stored credentials are stripped from the result so they never leave the database.
+CREATE OR REPLACE FUNCTION public.get_company_details_by_owner(
+    p_token TEXT
+) RETURNS JSON AS $$
+    SELECT (
+        public.get_company_details_by_owner(l.username, l.password_hash)::jsonb
+            - 'username'
+            - 'password'
+    )::json
+    FROM public.tokens t
+    JOIN public.login l ON l.id = t.login_id
+    WHERE t.token = p_token
+    AND t.expires_at > NOW()
+    AND t.is_active = TRUE
+    LIMIT 1;
+$$ LANGUAGE SQL;
@@ -0,0 +1,15 @@
+-- Per-person PINs used to switch the acting operator on a shared till. Kept next to
+-- public.login rather than in it, since stylists who only use the till have no login.
+CREATE TABLE public.login_pin (
+    person_id UUID PRIMARY KEY REFERENCES public.people(id) ON DELETE CASCADE,
+    pin_hash VARCHAR(255) NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW(),
+    updated_at TIMESTAMP WITH TIME ZONE DEFAULT NOW()
+);
+
+-- The person operating the till when the payment was taken. NULL for payments recorded
+-- before operators were tracked.
+ALTER TABLE public.payments
+    ADD COLUMN operator_id UUID REFERENCES public.people(id);
+
+CREATE INDEX idx_payments_operator ON public.payments(operator_id);
@@ -0,0 +1,6 @@
+-- Failed sign-in tracking for brute-force protection. The counter restarts once the last
+-- failure is old enough; locked_until holds the current temporary lockout, if any.
+ALTER TABLE public.login
+    ADD COLUMN failed_attempts INTEGER NOT NULL DEFAULT 0,
+    ADD COLUMN last_failed_at TIMESTAMP WITH TIME ZONE,
+    ADD COLUMN locked_until TIMESTAMP WITH TIME ZONE;
@@ -0,0 +1,124 @@
+-- public.tokens now also holds one-time password reset codes. Tag every row with what it
+-- is for so reset codes never pass as session tokens and session rotation leaves them alone.
+ALTER TABLE public.tokens
+    ADD COLUMN purpose VARCHAR(20) NOT NULL DEFAULT 'session'
+    CHECK (purpose IN ('session', 'password_reset'));
+
+CREATE INDEX IF NOT EXISTS idx_tokens_login_purpose ON public.tokens(login_id, purpose);
+
+CREATE OR REPLACE FUNCTION public.manage_user_token(
+    p_username TEXT,
+    p_password_hash TEXT,
+    p_token TEXT DEFAULT NULL
+) RETURNS JSON AS $$
+DECLARE
+    v_login_id UUID;
+    v_token TEXT;
+    v_expires_at TIMESTAMP WITH TIME ZONE;
+    v_exists BOOLEAN;
+BEGIN
+    -- First verify the username and password
+    SELECT id INTO v_login_id
+    FROM public.login
+    WHERE username = p_username AND password_hash = p_password_hash;
+    
+    IF v_login_id IS NULL THEN
+        RETURN json_build_object('success', FALSE, 'message', 'Invalid credentials');
+    END IF;
+    
+    -- Set token expiration (365 days from now)
+    v_expires_at := NOW() + INTERVAL '365 days';
+    
+    -- Check if p_token was provided and is valid
+    IF p_token IS NOT NULL THEN
+        SELECT EXISTS (
+            SELECT 1 FROM public.tokens 
+            WHERE token = p_token 
+            AND login_id = v_login_id
+            AND purpose = 'session'
+            AND expires_at > NOW()
+            AND is_active = TRUE
+        ) INTO v_exists;
+        
+        IF v_exists THEN
+            -- Update the existing token's expiration
+            UPDATE public.tokens
+            SET expires_at = v_expires_at
+            WHERE token = p_token AND login_id = v_login_id AND purpose = 'session';
+            
+            RETURN json_build_object(
+                'success', TRUE,
+                'token', p_token,
+                'expires_at', v_expires_at
+            );
+        END IF;
+    END IF;
+    
+    -- Generate a new token (UUID as string)
+    v_token := uuid_generate_v4()::TEXT;
+    
+    -- Check if user already has an active token
+    SELECT EXISTS (
+        SELECT 1 FROM public.tokens 
+        WHERE login_id = v_login_id
+        AND purpose = 'session'
+        AND expires_at > NOW()
+        AND is_active = TRUE
+    ) INTO v_exists;
+    
+    IF v_exists THEN
+        -- Update the existing active token
+        UPDATE public.tokens
+        SET token = v_token,
+            expires_at = v_expires_at
+        WHERE login_id = v_login_id
+        AND purpose = 'session'
+        AND expires_at > NOW()
+        AND is_active = TRUE;
+    ELSE
+        -- Insert a new token
+        INSERT INTO public.tokens (login_id, token, expires_at)
+        VALUES (v_login_id, v_token, v_expires_at);
+    END IF;
+    
+    RETURN json_build_object(
+        'success', TRUE,
+        'token', v_token,
+        'expires_at', v_expires_at
+    );
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE OR REPLACE FUNCTION public.get_company_details_by_token(
+    p_token TEXT,
+    p_company_id UUID
+) RETURNS JSON AS $$
+    SELECT public.get_company_details(r.company_id)
+    FROM public.tokens t
+    JOIN public.login l ON l.id = t.login_id
+    JOIN public.role r ON r.person_id = l.person_id
+    WHERE t.token = p_token
+    AND t.purpose = 'session'
+    AND t.expires_at > NOW()
+    AND t.is_active = TRUE
+    AND r.company_id = p_company_id
+    AND r.role_name IN ('owner', 'admin', 'staff')
+    LIMIT 1;
+$$ LANGUAGE SQL;
+
+CREATE OR REPLACE FUNCTION public.get_company_details_by_owner(
+    p_token TEXT
+) RETURNS JSON AS $$
+    SELECT (
+        public.get_company_details_by_owner(l.username, l.password_hash)::jsonb
+            - 'username'
+            - 'password'
+    )::json
+    FROM public.tokens t
+    JOIN public.login l ON l.id = t.login_id
+    WHERE t.token = p_token
+    AND t.purpose = 'session'
+    AND t.expires_at > NOW()
+    AND t.is_active = TRUE
+    LIMIT 1;
+$$ LANGUAGE SQL;
@@ -0,0 +1,22 @@
+-- TOTP second factor for logins. The shared secret is encrypted by the app before it is
+-- stored, so the database alone is not enough to mint codes. Enrolment stays disabled
+-- until the first code has been confirmed.
+CREATE TABLE public.login_mfa (
+    login_id UUID PRIMARY KEY REFERENCES public.login(id) ON DELETE CASCADE,
+    secret_ciphertext TEXT NOT NULL,
+    enabled BOOLEAN NOT NULL DEFAULT FALSE,
+    -- Last TOTP time step accepted, so a code can't be replayed
+    last_used_step BIGINT,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW(),
+    confirmed_at TIMESTAMP WITH TIME ZONE
+);
+
+-- Single-use recovery codes, stored hashed
+CREATE TABLE public.login_recovery_code (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    login_id UUID NOT NULL REFERENCES public.login(id) ON DELETE CASCADE,
+    code_hash TEXT NOT NULL,
+    used_at TIMESTAMP WITH TIME ZONE,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW(),
+    UNIQUE (login_id, code_hash)
+);
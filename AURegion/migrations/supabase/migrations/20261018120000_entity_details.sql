@@ -0,0 +1,176 @@
+-- Single entities in the same shape as get_company_details, so the app can re-fetch only
+-- what a change touched and patch its cached state instead of reloading the whole company.
+
+-- A customer as it appears under roles.customer and in bookings
+CREATE OR REPLACE FUNCTION public.customer_details(
+    p_person_id UUID
+) RETURNS JSON AS $$
+    SELECT json_build_object(
+        'id', p.id,
+        'personal_information', (
+            SELECT json_build_object(
+                'first_name', pi.first_name,
+                'last_name', pi.last_name,
+                'date_of_birth', pi.date_of_birth,
+                'gender', pi.gender
+            )
+            FROM public.personal_information pi
+            WHERE pi.person_id = p.id
+        ),
+        'address', (
+            SELECT json_build_object(
+                'street', a.street,
+                'city', a.city,
+                'state', a.state,
+                'postal_code', a.postal_code,
+                'country', a.country
+            )
+            FROM public.address a
+            WHERE a.person_id = p.id
+            ORDER BY a.created_at DESC
+            LIMIT 1
+        ),
+        'notes', (
+            SELECT n.text
+            FROM public.notes n
+            WHERE n.belong_to = 'people'
+            AND n.belong_id = p.id
+            ORDER BY n.created_at DESC
+            LIMIT 1
+        ),
+        'profile_image', (
+            SELECT json_build_object(
+                'id', m.id,
+                'type', m.type,
+                'path', m.path
+            )
+            FROM public.media m
+            WHERE m.belong_to = 'people'
+            AND m.belong_id = p.id
+            AND m.type = 'profile'
+            ORDER BY m.created_at DESC
+            LIMIT 1
+        ),
+        'contact_method', (
+            SELECT json_agg(
+                json_build_object(
+                    'id', cm.id,
+                    'type', cm.type,
+                    'value', cm.value,
+                    'is_primary', cm.is_primary
+                )
+            )
+            FROM public.contact_method cm
+            WHERE cm.person_id = p.id
+        )
+    )
+    FROM public.people p
+    WHERE p.id = p_person_id;
+$$ LANGUAGE SQL STABLE;
+
+-- A customer of the company, NULL when the person isn't one (or no longer exists)
+CREATE OR REPLACE FUNCTION public.get_company_customer(
+    p_company_id UUID,
+    p_person_id UUID
+) RETURNS JSON AS $$
+    SELECT public.customer_details(r.person_id)
+    FROM public.role r
+    WHERE r.company_id = p_company_id
+    AND r.person_id = p_person_id
+    AND r.role_name = 'customer'
+    LIMIT 1;
+$$ LANGUAGE SQL STABLE;
+
+-- A booking of the company, NULL when the company has no such booking
+CREATE OR REPLACE FUNCTION public.get_company_booking(
+    p_company_id UUID,
+    p_booking_id UUID
+) RETURNS JSON AS $$
+    SELECT json_build_object(
+        'id', b.id,
+        'customer', public.customer_details(b.customer_id),
+        'staff', (
+            SELECT json_build_object(
+                'id', p.id,
+                'personal_information', (
+                    SELECT json_build_object(
+                        'first_name', pi.first_name,
+                        'last_name', pi.last_name,
+                        'date_of_birth', pi.date_of_birth,
+                        'gender', pi.gender
+                    )
+                    FROM public.personal_information pi
+                    WHERE pi.person_id = p.id
+                ),
+                'address', (
+                    SELECT json_build_object(
+                        'street', a.street,
+                        'city', a.city,
+                        'state', a.state,
+                        'postal_code', a.postal_code,
+                        'country', a.country
+                    )
+                    FROM public.address a
+                    WHERE a.person_id = p.id
+                    ORDER BY a.created_at DESC
+                    LIMIT 1
+                ),
+                'profile_image', (
+                    SELECT json_build_object(
+                        'id', m.id,
+                        'type', m.type,
+                        'path', m.path
+                    )
+                    FROM public.media m
+                    WHERE m.belong_to = 'people'
+                    AND m.belong_id = p.id
+                    AND m.type = 'profile'
+                    ORDER BY m.created_at DESC
+                    LIMIT 1
+                ),
+                'contact_method', (
+                    SELECT json_agg(
+                        json_build_object(
+                            'id', cm.id,
+                            'type', cm.type,
+                            'value', cm.value,
+                            'is_primary', cm.is_primary
+                        )
+                    )
+                    FROM public.contact_method cm
+                    WHERE cm.person_id = p.id
+                )
+            )
+            FROM public.people p
+            WHERE p.id = b.staff_id
+        ),
+        'service', (
+            SELECT json_build_object(
+                'id', s.id,
+                'name', s.name,
+                'description', (
+                    SELECT n.text
+                    FROM public.notes n
+                    WHERE n.belong_to = 'services'
+                    AND n.belong_id = s.id
+                    ORDER BY n.created_at DESC
+                    LIMIT 1
+                ),
+                'duration', s.duration,
+                'price', s.price
+            )
+            FROM public.services s
+            WHERE s.id = b.service_id
+        ),
+        'status', (
+            SELECT row_to_json(bs)
+            FROM public.status bs
+            WHERE bs.id = b.status_id
+        ),
+        'start_time', b.start_time,
+        'end_time', b.end_time
+    )
+    FROM public.booking b
+    WHERE b.company_id = p_company_id
+    AND b.id = p_booking_id;
+$$ LANGUAGE SQL STABLE;
@@ -0,0 +1,135 @@
+-- Publish booking, payment and customer changes on a channel per company, so the desktop
+-- app can LISTEN for its active company only. Payloads carry ids and statuses, never
+-- personal details; the app re-fetches what it needs with its own session.
+
+-- Channel names are identifiers, so the dashes are dropped from the company id
+CREATE OR REPLACE FUNCTION public.company_channel(
+    p_company_id UUID
+) RETURNS TEXT AS $$
+    SELECT 'company_' || replace(p_company_id::text, '-', '');
+$$ LANGUAGE SQL IMMUTABLE;
+
+CREATE OR REPLACE FUNCTION public.notify_booking_change()
+RETURNS TRIGGER AS $$
+DECLARE
+    v_booking public.booking;
+BEGIN
+    IF TG_OP = 'DELETE' THEN
+        v_booking := OLD;
+    ELSE
+        v_booking := NEW;
+    END IF;
+
+    IF v_booking.company_id IS NOT NULL THEN
+        PERFORM pg_notify(
+            public.company_channel(v_booking.company_id),
+            json_build_object(
+                'entity', 'booking',
+                'op', lower(TG_OP),
+                'id', v_booking.id,
+                'status_id', v_booking.status_id,
+                'old_status_id', CASE WHEN TG_OP = 'UPDATE' THEN OLD.status_id END,
+                'rescheduled', TG_OP = 'UPDATE'
+                    AND (OLD.start_time, OLD.end_time) IS DISTINCT FROM (NEW.start_time, NEW.end_time)
+            )::text
+        );
+    END IF;
+
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS booking_notify ON public.booking;
+CREATE TRIGGER booking_notify
+    AFTER INSERT OR UPDATE OR DELETE ON public.booking
+    FOR EACH ROW
+    EXECUTE FUNCTION public.notify_booking_change();
+
+CREATE OR REPLACE FUNCTION public.notify_payment_change()
+RETURNS TRIGGER AS $$
+BEGIN
+    PERFORM pg_notify(
+        public.company_channel(NEW.company_id),
+        json_build_object(
+            'entity', 'payment',
+            'op', lower(TG_OP),
+            'id', NEW.id,
+            'status', NEW.status
+        )::text
+    );
+
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS payment_notify ON public.payments;
+CREATE TRIGGER payment_notify
+    AFTER INSERT OR UPDATE ON public.payments
+    FOR EACH ROW
+    EXECUTE FUNCTION public.notify_payment_change();
+
+-- A person becomes or stops being a customer of a company
+CREATE OR REPLACE FUNCTION public.notify_customer_role_change()
+RETURNS TRIGGER AS $$
+DECLARE
+    v_role public.role;
+BEGIN
+    IF TG_OP = 'DELETE' THEN
+        v_role := OLD;
+    ELSE
+        v_role := NEW;
+    END IF;
+
+    IF v_role.role_name = 'customer' AND v_role.company_id IS NOT NULL THEN
+        PERFORM pg_notify(
+            public.company_channel(v_role.company_id),
+            json_build_object(
+                'entity', 'customer',
+                'op', lower(TG_OP),
+                'id', v_role.person_id
+            )::text
+        );
+    END IF;
+
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS customer_role_notify ON public.role;
+CREATE TRIGGER customer_role_notify
+    AFTER INSERT OR DELETE ON public.role
+    FOR EACH ROW
+    EXECUTE FUNCTION public.notify_customer_role_change();
+
+-- A customer's details changed; every company they are a customer of hears about it
+CREATE OR REPLACE FUNCTION public.notify_customer_details_change()
+RETURNS TRIGGER AS $$
+DECLARE
+    v_company_id UUID;
+BEGIN
+    FOR v_company_id IN
+        SELECT r.company_id
+        FROM public.role r
+        WHERE r.person_id = NEW.person_id
+        AND r.role_name = 'customer'
+        AND r.company_id IS NOT NULL
+    LOOP
+        PERFORM pg_notify(
+            public.company_channel(v_company_id),
+            json_build_object(
+                'entity', 'customer',
+                'op', 'update',
+                'id', NEW.person_id
+            )::text
+        );
+    END LOOP;
+
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS customer_details_notify ON public.personal_information;
+CREATE TRIGGER customer_details_notify
+    AFTER UPDATE ON public.personal_information
+    FOR EACH ROW
+    EXECUTE FUNCTION public.notify_customer_details_change();
@@ -0,0 +1,48 @@
+-- Scheduling helpers for bookings made from the desktop app.
+
+-- Opening hours of a company as UTC instants for every local date in a range. The
+-- conversion uses each timetable row's own timezone, so the app doesn't need a timezone
+-- database and daylight saving changes are handled here.
+CREATE OR REPLACE FUNCTION public.get_opening_windows(
+    p_company_id UUID,
+    p_from DATE,
+    p_to DATE
+) RETURNS TABLE (
+    local_date DATE,
+    opens_at TIMESTAMP WITH TIME ZONE,
+    closes_at TIMESTAMP WITH TIME ZONE
+) AS $$
+    SELECT
+        d::date AS local_date,
+        (d::date + t.start_time) AT TIME ZONE t.timezone AS opens_at,
+        (d::date + t.end_time) AT TIME ZONE t.timezone AS closes_at
+    FROM generate_series(p_from, p_to, INTERVAL '1 day') d
+    JOIN public.timetable t
+        ON t.company_id = p_company_id
+        AND t.day_of_week = EXTRACT(DOW FROM d)::int
+    ORDER BY opens_at;
+$$ LANGUAGE SQL STABLE;
+
+-- The staff assignment the old booking trigger did, as a plain function: the first staff
+-- member of the company without a pending or confirmed booking overlapping the slot.
+-- NULL when everyone is busy.
+CREATE OR REPLACE FUNCTION public.check_and_assign_staff(
+    p_company_id UUID,
+    p_start_time TIMESTAMP WITH TIME ZONE,
+    p_end_time TIMESTAMP WITH TIME ZONE
+) RETURNS UUID AS $$
+    SELECT p.id
+    FROM public.people p
+    JOIN public.role r ON p.id = r.person_id
+    WHERE r.company_id = p_company_id
+    AND r.role_name = 'staff'
+    AND NOT EXISTS (
+        SELECT 1
+        FROM public.booking b
+        WHERE b.staff_id = p.id
+        AND b.status_id IN (SELECT id FROM public.status WHERE name IN ('pending', 'confirmed'))
+        AND tstzrange(b.start_time, b.end_time) && tstzrange(p_start_time, p_end_time)
+    )
+    ORDER BY r.created_at, p.id
+    LIMIT 1;
+$$ LANGUAGE SQL STABLE;
@@ -0,0 +1,31 @@
+-- Availability search shows slots in the company's own time, so the opening windows also
+-- carry the timetable's zone and its UTC offset when the window opens. The return type
+-- changes, hence the drop.
+DROP FUNCTION IF EXISTS public.get_opening_windows(UUID, DATE, DATE);
+
+CREATE FUNCTION public.get_opening_windows(
+    p_company_id UUID,
+    p_from DATE,
+    p_to DATE
+) RETURNS TABLE (
+    local_date DATE,
+    opens_at TIMESTAMP WITH TIME ZONE,
+    closes_at TIMESTAMP WITH TIME ZONE,
+    timezone TEXT,
+    utc_offset INT
+) AS $$
+    SELECT
+        d::date AS local_date,
+        (d::date + t.start_time) AT TIME ZONE t.timezone AS opens_at,
+        (d::date + t.end_time) AT TIME ZONE t.timezone AS closes_at,
+        t.timezone::text AS timezone,
+        EXTRACT(EPOCH FROM (
+            (d::date + t.start_time)
+            - ((d::date + t.start_time) AT TIME ZONE t.timezone AT TIME ZONE 'UTC')
+        ))::int AS utc_offset
+    FROM generate_series(p_from, p_to, INTERVAL '1 day') d
+    JOIN public.timetable t
+        ON t.company_id = p_company_id
+        AND t.day_of_week = EXTRACT(DOW FROM d)::int
+    ORDER BY opens_at;
+$$ LANGUAGE SQL STABLE;
@@ -0,0 +1,51 @@
+-- Bookings can also be in progress or a no-show. The app looks statuses up by name, so the
+-- new rows get whatever id this database gives them.
+ALTER TABLE public.status DROP CONSTRAINT IF EXISTS status_name_check;
+ALTER TABLE public.status ADD CONSTRAINT status_name_check
+    CHECK (name IN ('pending', 'confirmed', 'in_progress', 'completed', 'cancelled', 'no_show'));
+
+INSERT INTO public.status (name, description) VALUES
+    ('in_progress', 'Booking is in progress'),
+    ('no_show', 'Customer did not turn up')
+ON CONFLICT (name) DO NOTHING;
+
+-- A booking in progress still holds its staff member's time
+CREATE OR REPLACE FUNCTION public.get_booked_slots(
+    p_company_id UUID,
+    p_staff_id UUID,
+    p_start_time TIMESTAMP WITH TIME ZONE
+) RETURNS TABLE (
+    booked_start TIMESTAMP WITH TIME ZONE,
+    booked_end TIMESTAMP WITH TIME ZONE
+) AS $$
+    SELECT
+        b.start_time AS booked_start,
+        b.end_time AS booked_end
+    FROM public.booking b
+    WHERE b.company_id = p_company_id
+    AND b.status_id IN (SELECT id FROM public.status WHERE name IN ('pending', 'confirmed', 'in_progress'))
+    AND b.start_time >= p_start_time
+    AND (b.staff_id = p_staff_id OR p_staff_id IS NULL)
+    ORDER BY b.start_time;
+$$ LANGUAGE SQL;
+
+CREATE OR REPLACE FUNCTION public.check_and_assign_staff(
+    p_company_id UUID,
+    p_start_time TIMESTAMP WITH TIME ZONE,
+    p_end_time TIMESTAMP WITH TIME ZONE
+) RETURNS UUID AS $$
+    SELECT p.id
+    FROM public.people p
+    JOIN public.role r ON p.id = r.person_id
+    WHERE r.company_id = p_company_id
+    AND r.role_name = 'staff'
+    AND NOT EXISTS (
+        SELECT 1
+        FROM public.booking b
+        WHERE b.staff_id = p.id
+        AND b.status_id IN (SELECT id FROM public.status WHERE name IN ('pending', 'confirmed', 'in_progress'))
+        AND tstzrange(b.start_time, b.end_time) && tstzrange(p_start_time, p_end_time)
+    )
+    ORDER BY r.created_at, p.id
+    LIMIT 1;
+$$ LANGUAGE SQL STABLE;
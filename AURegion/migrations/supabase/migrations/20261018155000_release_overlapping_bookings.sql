@@ -0,0 +1,52 @@
+-- Before `booking_staff_no_overlap` is added, bookings that already hold the same staff
+-- member's time are sorted out here instead of failing the deploy. Going by creation order,
+-- a booking that overlaps one kept earlier is cancelled. Each one is reported in
+-- `booking_overlap_report` with the booking it clashed with and its old status, so the
+-- business can contact the customer or restore it somewhere else.
+CREATE TABLE IF NOT EXISTS public.booking_overlap_report (
+    booking_id UUID PRIMARY KEY REFERENCES public.booking(id) ON DELETE CASCADE,
+    kept_booking_id UUID REFERENCES public.booking(id) ON DELETE SET NULL,
+    previous_status_id UUID REFERENCES public.status(id),
+    cancelled_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+DO $$
+DECLARE
+    holding UUID[];
+    cancelled UUID;
+    candidate RECORD;
+    kept UUID;
+BEGIN
+    SELECT array_agg(id) INTO holding
+    FROM public.status WHERE name IN ('pending', 'confirmed', 'in_progress');
+    SELECT id INTO cancelled FROM public.status WHERE name = 'cancelled';
+
+    FOR candidate IN
+        SELECT b.id, b.staff_id, b.start_time, b.end_time, b.status_id, b.created_at
+        FROM public.booking b
+        WHERE b.staff_id IS NOT NULL AND b.status_id = ANY (holding)
+        ORDER BY b.created_at NULLS LAST, b.id
+    LOOP
+        -- Earlier bookings cancelled by this loop no longer hold their time
+        SELECT k.id INTO kept
+        FROM public.booking k
+        WHERE k.staff_id = candidate.staff_id
+        AND k.id <> candidate.id
+        AND k.status_id = ANY (holding)
+        AND (COALESCE(k.created_at, 'infinity'), k.id)
+            < (COALESCE(candidate.created_at, 'infinity'), candidate.id)
+        AND tstzrange(k.start_time, k.end_time) && tstzrange(candidate.start_time, candidate.end_time)
+        ORDER BY k.start_time
+        LIMIT 1;
+
+        IF FOUND THEN
+            INSERT INTO public.booking_overlap_report (booking_id, kept_booking_id, previous_status_id)
+            VALUES (candidate.id, kept, candidate.status_id)
+            ON CONFLICT (booking_id) DO NOTHING;
+
+            UPDATE public.booking SET status_id = cancelled, updated_at = NOW()
+            WHERE id = candidate.id;
+        END IF;
+    END LOOP;
+END;
+$$;
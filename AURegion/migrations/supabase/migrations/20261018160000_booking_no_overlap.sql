@@ -0,0 +1,37 @@
+-- Two bookings that hold a staff member's time can't overlap, even when they are written at
+-- the same moment. The overlap checks in the app only see committed rows, so the database
+-- has the final say.
+CREATE EXTENSION IF NOT EXISTS btree_gist;
+
+-- An exclusion constraint can't look the status name up, so whether a booking holds its
+-- staff member's time is kept on the row itself
+ALTER TABLE public.booking ADD COLUMN IF NOT EXISTS holds_slot BOOLEAN NOT NULL DEFAULT TRUE;
+
+CREATE OR REPLACE FUNCTION public.set_booking_holds_slot()
+RETURNS TRIGGER AS $$
+BEGIN
+    NEW.holds_slot := COALESCE(
+        (SELECT name IN ('pending', 'confirmed', 'in_progress')
+         FROM public.status WHERE id = NEW.status_id),
+        FALSE
+    );
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS booking_holds_slot ON public.booking;
+CREATE TRIGGER booking_holds_slot
+BEFORE INSERT OR UPDATE OF status_id, holds_slot ON public.booking
+FOR EACH ROW EXECUTE FUNCTION public.set_booking_holds_slot();
+
+UPDATE public.booking b SET holds_slot = FALSE
+WHERE NOT COALESCE(
+    (SELECT s.name IN ('pending', 'confirmed', 'in_progress') FROM public.status s WHERE s.id = b.status_id),
+    FALSE
+);
+
+-- Bookings that already overlapped were cancelled by 20261018155000_release_overlapping_bookings
+ALTER TABLE public.booking DROP CONSTRAINT IF EXISTS booking_staff_no_overlap;
+ALTER TABLE public.booking ADD CONSTRAINT booking_staff_no_overlap
+    EXCLUDE USING gist (staff_id WITH =, tstzrange(start_time, end_time) WITH &&)
+    WHERE (holds_slot);
@@ -0,0 +1,66 @@
+-- The daily campaign job as the 20250404-20250405 migrations left it, set up again so it
+-- also applies to a Postgres without pg_cron or the http extension. A local Postgres skips
+-- those migrations, so this is where it gets the function; on Supabase it is unchanged.
+DO $ext$
+BEGIN
+    IF EXISTS (SELECT 1 FROM pg_available_extensions WHERE name = 'http') THEN
+        CREATE EXTENSION IF NOT EXISTS http;
+    END IF;
+END
+$ext$;
+
+CREATE OR REPLACE FUNCTION process_active_campaigns()
+RETURNS TEXT AS $$
+DECLARE
+    v_campaigns JSONB;
+    v_response TEXT;
+BEGIN
+    -- Get all active campaigns with their company IDs and feature information
+    SELECT jsonb_agg(
+        jsonb_build_object(
+            'campaign_id', c.id,
+            'message_template', c.message_template,
+            'trigger_frequency', c.trigger_frequency,
+            'company_id', cl_company.linkable_id,
+            'feature_id', f.id,
+            'feature_name', f.name
+        )
+    ) INTO v_campaigns
+    FROM campaign c
+    -- First join to get company links
+    JOIN campaign_linkable cl_company ON c.id = cl_company.campaign_id
+        AND cl_company.linkable_type = 'company'
+    -- Then join to get feature links for the same campaign
+    LEFT JOIN campaign_linkable cl_feature ON c.id = cl_feature.campaign_id
+        AND cl_feature.linkable_type = 'features'
+    -- Finally join to get feature details
+    LEFT JOIN features f ON cl_feature.linkable_id = f.id
+    WHERE c.status = true;
+
+    -- Call edge function with the array of campaigns and capture response
+    SELECT content INTO v_response
+    FROM http_post(
+        'https://xzjrkgzptjqoyxxeqchy.supabase.co/functions/v1/review-sms',
+        json_build_object(
+            'campaigns', COALESCE(v_campaigns, '[]'::jsonb)
+        )::text,
+        'application/json'
+    );
+
+    RETURN v_response;
+END;
+$$ LANGUAGE plpgsql SECURITY DEFINER;
+
+-- Scheduling again under the same name replaces the job; without pg_cron there is nothing
+-- to schedule it with
+DO $cron$
+BEGIN
+    IF EXISTS (SELECT 1 FROM pg_extension WHERE extname = 'pg_cron') THEN
+        PERFORM cron.schedule(
+            'process-active-campaigns-daily',
+            '0 0 * * *',
+            'SELECT process_active_campaigns()'
+        );
+    END IF;
+END
+$cron$;
@@ -0,0 +1,5 @@
+-- Wrong till PINs are counted per person and lock the PIN with the same backoff as a
+-- password login
+ALTER TABLE public.login_pin ADD COLUMN IF NOT EXISTS failed_attempts INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE public.login_pin ADD COLUMN IF NOT EXISTS last_failed_at TIMESTAMPTZ;
+ALTER TABLE public.login_pin ADD COLUMN IF NOT EXISTS locked_until TIMESTAMPTZ;
@@ -4,6 +4,7 @@ import React, { useState, useEffect } from 'react';
 import { format, addHours } from 'date-fns';
 import ContactList, { ContactProps } from "@/app/business/clients/components/businesses";
 import ServiceSelector, { ServiceData } from "@/app/business/checkout/components/service";
+import { useAppContext } from '@/app/utils/AppContext';
 
 interface AddBookingOverlayProps {
   onClose: () => void;
@@ -31,6 +32,7 @@ const AddBookingOverlay: React.FC<AddBookingOverlayProps> = ({
   const [selectedClient, setSelectedClient] = useState<ContactProps | null>(null);
   const [selectedService, setSelectedService] = useState<ServiceData | null>(null);
   const [phoneNumber, setPhoneNumber] = useState<string>('');
+  const { createBooking, addNotification } = useAppContext();
 
   // Update dates when selectedDate prop changes
   useEffect(() => {
@@ -57,18 +59,20 @@ const AddBookingOverlay: React.FC<AddBookingOverlayProps> = ({
     setShowServiceModal(false);
   };
 
-  const handleSubmit = (e: React.FormEvent) => {
+  const handleSubmit = async (e: React.FormEvent) => {
     e.preventDefault();
-    
-    // onAddBooking({
-    //   title,
-    //   start: startDate,
-    //   end: endDate,
-    //   clientName,
-    //   service,
-    //   phoneNumber
-    // });
-    
+    if (!selectedClient || !selectedService) {
+      return;
+    }
+
+    // The BE checks opening hours and staff availability and picks the staff member
+    const response = await createBooking(selectedClient.id, selectedService.id, startDate);
+    if (response && typeof response === 'object' && 'code' in response) {
+      addNotification(response.message, 'error');
+      return;
+    }
+
+    addNotification('Booking created', 'success');
     onClose();
   };
 
@@ -5,7 +5,6 @@ import { format } from 'date-fns';
 import { CalendarEvent } from './booking';
 import { useRouter } from 'next/navigation';
 import { useAppContext } from '@/app/utils/AppContext';
-import { toZonedTime, fromZonedTime } from 'date-fns-tz';
 
 interface BookingOverlayProps {
   booking: CalendarEvent;
@@ -51,7 +50,7 @@ const BookingOverlay: React.FC<BookingOverlayProps> = ({
   onCancel,
   onCharge
 }) => {
-  const { cancelBooking, rescheduleBooking } = useAppContext();
+  const { cancelBooking, rescheduleBooking, addNotification } = useAppContext();
   const router = useRouter();
   const [isRescheduling, setIsRescheduling] = useState(false);
   const [newDate, setNewDate] = useState<Date>(booking.start);
@@ -60,42 +59,39 @@ const BookingOverlay: React.FC<BookingOverlayProps> = ({
   const [confirmingCancel, setConfirmingCancel] = useState(false);
   const [successMessage, setSuccessMessage] = useState<string | null>(null);
 
+  // The picked date is an instant already; the BE keeps the service's duration and checks
+  // opening hours and the staff member's other bookings
   const handleReschedule = async (booking: CalendarEvent, newDate: Date) => {
     try {
-      const duration = booking.service.duration; // Assuming format "HH:MM:SS"
-      console.log("Duration", duration);
-      
-      // Parse duration string (HH:MM:SS) into milliseconds
-      const [hours, minutes, seconds] = duration.split(':').map(Number);
-      const durationMs = (hours * 3600 + minutes * 60 + seconds) * 1000;
-
-      // Convert local time to UTC using the system's timezone
-      const localTimezone = Intl.DateTimeFormat().resolvedOptions().timeZone;
-      const localDate = toZonedTime(newDate, localTimezone);
-      const utcStartDate = fromZonedTime(newDate, localTimezone);
-      
-      // Calculate UTC end time by adding duration to UTC start time
-      const utcEndDate = new Date(utcStartDate.getTime() + durationMs);
-
-      console.log("Detected local timezone:", localTimezone);
-      console.log("Original local time:", newDate.toLocaleString());
-      console.log("Converted UTC start time:", utcStartDate.toUTCString());
-      console.log("Calculated UTC end time:", utcEndDate.toUTCString());
-      console.log("Duration (hours.ConcurrentModificationException):", durationMs / (1000 * 60 * 60));
-      console.log("Time difference (hours):", 
-        (utcStartDate.getTime() - localDate.getTime()) / (1000 * 60 * 60)
-      );
-
-      const response = await rescheduleBooking(booking.id, utcStartDate,utcEndDate);
-      console.log("Rescheduling booking response", response);
+      const response = await rescheduleBooking(booking.id, newDate);
+      if ('code' in response) {
+        addNotification(response.message, 'error');
+        return;
+      }
+      if (response.outcome === 'conflicts') {
+        response.conflicts.forEach((conflict) => {
+          if (conflict.kind === 'outside_opening_hours') {
+            const hours = conflict.opening_hours
+              .map((window) => `${format(new Date(window.opens_at), 'HH:mm')}-${format(new Date(window.closes_at), 'HH:mm')}`)
+              .join(', ');
+            addNotification(hours ? `That time is outside opening hours (${hours})` : 'The business is closed that day', 'error');
+          } else {
+            const times = conflict.bookings
+              .map((other) => `${format(new Date(other.start_time), 'HH:mm')}-${format(new Date(other.end_time), 'HH:mm')}`)
+              .join(', ');
+            addNotification(`The staff member is already booked at ${times}`, 'error');
+          }
+        });
+        return;
+      }
       if (onReschedule) {
-        onReschedule(utcStartDate);
+        onReschedule(newDate);
       }
+      setSuccessMessage("Appointment successfully rescheduled!");
     } catch (error) {
       console.error("Error rescheduling booking", error);
     } finally {
       setIsRescheduling(false);
-      setSuccessMessage("Appointment successfully rescheduled!");
     }
   };
 
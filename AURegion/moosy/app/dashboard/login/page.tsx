@@ -190,6 +190,13 @@ export interface LoginResponse {
     };
   };
   bookings: Array<BookingResponse>;
+  companies: Array<CompanyMembership>;
+}
+
+export interface CompanyMembership {
+  id: string;
+  name: string;
+  role: 'owner' | 'admin' | 'staff';
 }
 
 export interface BookingResponse {
@@ -254,18 +261,31 @@ export interface ServiceResponse {
   price: number;
 }
 
+interface MfaChallenge {
+  mfa_required: true;
+  challenge_id: string;
+}
+
 // Function to handle login
 async function handleLogin(username: string, password: string): Promise<LoginResponse | null> {
   try {
-    const response: LoginResponse = await invoke('login', {
+    const response: LoginResponse | MfaChallenge = await invoke('login', {
       username: username,
-      password: password,
-      refresh: false
+      password: password
     })
+    // Accounts with two-factor enabled need a second step before a session exists
+    if ('mfa_required' in response) {
+      const code = window.prompt('Enter the code from your authenticator app or a recovery code');
+      if (!code) {
+        return null;
+      }
+      return await invoke('verify_login_mfa', { challengeId: response.challenge_id, code });
+    }
     console.log('Login successful:', response)
     return response;
-  } catch (error) {
-    alert('Login failed')
+  } catch (error: any) {
+    // Lockouts and wrong credentials come back as { code, message } with a message safe to show
+    alert(error?.code === 'locked_out' || error?.code === 'invalid_credentials' ? error.message : 'Login failed')
     console.error('Login failed:', error)
     return null;
   }
@@ -3,7 +3,6 @@ import { createContext, useContext, ReactNode, useState, useCallback, useEffect
 import { Db, Server, PrivateKey } from "@/app/utils/db";
 import jwt from "jwt-simple";
 import { LoginResponse } from '../dashboard/login/page';
-import { Auth } from '../auth';
 import { invoke } from '@tauri-apps/api/core';
 import { listen } from '@tauri-apps/api/event';
 import {
@@ -13,6 +12,19 @@ import {
 } from '@tauri-apps/plugin-notification';
 import { ContactProps } from '../business/clients/components/businesses';
 
+// Error returned by every BE command
+export type AppErrorCode =
+    | 'not_authenticated' | 'session_expired' | 'session_locked' | 'invalid_credentials' | 'forbidden' | 'locked_out' | 'offline'
+    | 'feature_disabled' | 'schema_mismatch' | 'not_found' | 'invalid' | 'conflict' | 'duplicate' | 'broken_reference'
+    | 'check_failed' | 'database' | 'error';
+
+export interface AppError {
+    code: AppErrorCode;
+    // Safe to show to the user as is
+    message: string;
+    details: string | null;
+}
+
 // Add Notification type
 interface Notification {
     id: string;
@@ -21,8 +33,46 @@ interface Notification {
     timestamp: Date;
 }
 
+export type ConnectionStatus = 'connecting' | 'online' | 'offline' | 'incompatible';
+
+// Bookable slots from `find_available_slots`, times in the company's own zone
+export interface AvailableDay {
+    date: string;
+    timezone: string;
+    staff: {
+        id: string;
+        first_name: string;
+        last_name: string;
+        slots: { start_time: string, end_time: string }[];
+    }[];
+}
+
+// Pending can go anywhere; confirmed to anything but pending; in progress only to
+// completed. Completed, cancelled and no-show are final.
+export type BookingStatus = 'pending' | 'confirmed' | 'in_progress' | 'completed' | 'cancelled' | 'no_show';
+
+// Why `reschedule_booking` left a booking where it was
+export type ScheduleConflict =
+    | { kind: 'outside_opening_hours', opening_hours: { opens_at: string, closes_at: string }[] }
+    | { kind: 'staff_booked', bookings: { id: string, start_time: string, end_time: string }[] };
+
+export type RescheduleOutcome =
+    | { outcome: 'rescheduled', booking: Booking }
+    | { outcome: 'conflicts', conflicts: ScheduleConflict[] };
+
+export interface SlotQuery {
+    serviceId: string;
+    staffId?: string;
+    // Local date to start from, as YYYY-MM-DD
+    from: string;
+    days?: number;
+    granularityMinutes?: number;
+    minNoticeMinutes?: number;
+}
+
 export interface AppContextData {
     auth: LoginResponse | null;
+    connectionStatus: ConnectionStatus;
     setAuthentication: (loginData: LoginResponse) => void;
     logout: () => void;
     getUser: () => Promise<LoginResponse | null>;
@@ -30,18 +80,64 @@ export interface AppContextData {
     notifications: Notification[];
     addNotification: (message: string, type: 'success' | 'error' | 'info') => void;
     removeNotification: (id: string) => void;
+    createBooking: (customerId: string, serviceId: string, startTime: Date, staffId?: string) => Promise<any>;
+    findAvailableSlots: (query: SlotQuery) => Promise<AvailableDay[] | AppError>;
     cancelBooking: (bookingId: string) => Promise<any>;
-    rescheduleBooking: (bookingId: string, newDate: Date, endTime: Date) => Promise<any>;
+    updateBookingStatus: (bookingId: string, status: BookingStatus) => Promise<Booking | AppError>;
+    rescheduleBooking: (bookingId: string, startTime: Date, endTime?: Date) => Promise<RescheduleOutcome | AppError>;
     checkoutBooking: (customerId: string, amount: number, method: string, currency: string, bookingId?: string, servicesId?: string[], discountsId?: string[]) => Promise<any>;
     addCustomer: (customer: any) => Promise<any>;
     editCustomer: (customer: any) => Promise<any>;
     deleteCustomer: (customerId: string) => Promise<any>;
     checkoutWalkin: (customerId: string, amount: number, method: string, currency: string, servicesId?: string[], discountsId?: string[]) => Promise<any>;
     updateCampaign: (campaignId: string, active: boolean) => Promise<any>;
+    switchOperator: (personId: string, pin: string) => Promise<any>;
+    switchCompany: (companyId: string) => Promise<any>;
 }
 
 const AppContext = createContext<AppContextData | undefined>(undefined);
 
+type Booking = LoginResponse['bookings'][number];
+type Customer = LoginResponse['roles']['customer'][number];
+
+// A change to one cached entity, sent by the BE as `state-delta`
+export type StateDelta =
+    | { kind: 'booking_upserted', booking: Booking }
+    | { kind: 'booking_removed', id: string }
+    | { kind: 'customer_upserted', customer: Customer }
+    | { kind: 'customer_removed', id: string };
+
+function upsert<T extends { id: string }>(items: T[] | null | undefined, item: T): T[] {
+    const list = items ?? [];
+    return list.some((existing) => existing.id === item.id)
+        ? list.map((existing) => existing.id === item.id ? item : existing)
+        : [...list, item];
+}
+
+// Same patching as the BE applies to its cached copy
+function applyDelta(auth: LoginResponse, delta: StateDelta): LoginResponse {
+    switch (delta.kind) {
+        case 'booking_upserted':
+            return { ...auth, bookings: upsert(auth.bookings, delta.booking) };
+        case 'booking_removed':
+            return { ...auth, bookings: (auth.bookings ?? []).filter((booking) => booking.id !== delta.id) };
+        case 'customer_upserted':
+            return {
+                ...auth,
+                roles: { ...auth.roles, customer: upsert(auth.roles.customer, delta.customer) },
+                // Bookings carry their own copy of the customer
+                bookings: (auth.bookings ?? []).map((booking) =>
+                    booking.customer?.id === delta.customer.id ? { ...booking, customer: delta.customer } : booking),
+            };
+        case 'customer_removed':
+            return {
+                ...auth,
+                roles: { ...auth.roles, customer: (auth.roles.customer ?? []).filter((customer) => customer.id !== delta.id) },
+            };
+    }
+}
+
+
 interface AppProviderProps {
     children: ReactNode;
 }
@@ -67,6 +163,7 @@ interface AppProviderProps {
 
 export function AppProvider({ children }: AppProviderProps) {
     const [auth, setAuth] = useState<LoginResponse | null>();
+    const [connectionStatus, setConnectionStatus] = useState<ConnectionStatus>('connecting');
     // Add notifications state
     const [notifications, setNotifications] = useState<Notification[]>([]);
 
@@ -107,6 +204,7 @@ export function AppProvider({ children }: AppProviderProps) {
     }, []);
 
     const logout = useCallback(() => {
+        invoke('logout').catch((error) => console.error('Error logging out:', error));
         setAuth(null);
         // Only access localStorage on the client side
         if (typeof window !== 'undefined') {
@@ -124,15 +222,60 @@ export function AppProvider({ children }: AppProviderProps) {
     }, []);
 
 
-    const rescheduleBooking = useCallback(async (bookingId: string, newDate: Date, endTime: Date) => {
+    // The BE refuses changes the booking's current status doesn't allow, with a `conflict` code
+    const updateBookingStatus = useCallback(async (bookingId: string, status: BookingStatus) => {
+        try {
+            const response = await invoke<Booking>('update_booking_status', { bookingId, status });
+            return response;
+        } catch (error) {
+            return error as AppError;
+        }
+    }, []);
+
+    // Without a staff member the BE assigns whoever is free
+    const createBooking = useCallback(async (customerId: string, serviceId: string, startTime: Date, staffId?: string) => {
         try {
-            const response = await invoke('reschedule_booking', { bookingId, newDate, endTime })
+            const booking = { customer_id: customerId, service_id: serviceId, staff_id: staffId, start_time: startTime.toISOString() };
+            const response = await invoke('create_booking', { booking });
             return response;
         } catch (error) {
             return error;
         }
     }, []);
 
+    // Left out values fall back to the BE's booking settings
+    const findAvailableSlots = useCallback(async (query: SlotQuery) => {
+        try {
+            const response = await invoke<AvailableDay[]>('find_available_slots', {
+                query: {
+                    service_id: query.serviceId,
+                    staff_id: query.staffId,
+                    from: query.from,
+                    days: query.days,
+                    granularity_minutes: query.granularityMinutes,
+                    min_notice_minutes: query.minNoticeMinutes,
+                },
+            });
+            return response;
+        } catch (error) {
+            return error as AppError;
+        }
+    }, []);
+
+    // Without an end time the booking keeps its service's duration
+    const rescheduleBooking = useCallback(async (bookingId: string, startTime: Date, endTime?: Date) => {
+        try {
+            const response = await invoke<RescheduleOutcome>('reschedule_booking', {
+                bookingId,
+                startTime: startTime.toISOString(),
+                endTime: endTime?.toISOString(),
+            });
+            return response;
+        } catch (error) {
+            return error as AppError;
+        }
+    }, []);
+
     const addCustomer = useCallback(async (customer: ContactProps) => {
         try {
             const response = await invoke('add_customer', { customer })
@@ -191,87 +334,28 @@ export function AppProvider({ children }: AppProviderProps) {
         }
     }, []);
 
+    const switchOperator = useCallback(async (personId: string, pin: string) => {
+        try {
+            const response = await invoke('switch_operator', { personId, pin });
+            return response;
+        } catch (error) {
+            return error;
+        }
+    }, []);
+
+    const switchCompany = useCallback(async (companyId: string) => {
+        try {
+            const response = await invoke('switch_company', { companyId });
+            return response;
+        } catch (error) {
+            return error;
+        }
+    }, []);
+
     // Add useEffect to subscribe to specific database events
     // useEffect(() => {
     console.log("activate listening to bookings changes");
     if (auth?.company.id) {
-        const subscribeBooking = async () => {
-            const channel = Auth.channel('tables_chan');
-
-            // Handler function to fetch full booking details using Supabase client
-            const fetchLatestUpdate = async (booking: any) => {
-                try {
-                    const response = await invoke('fetch_latest_state');
-                    console.log("New Booking updated At:", response);
-                } catch (error) {
-                    console.error("Error fetching full booking details:", error);
-                }
-            };
-
-            // Subscribe to INSERT events
-            channel.on(
-                "postgres_changes",
-                {
-                    event: "INSERT",
-                    schema: "public",
-                    table: "booking",
-                    filter: `company_id=eq.${auth.company.id}`,
-
-                },
-                (payload) => {
-                    console.log("New booking added:", payload.new);
-                    // Fetch the full booking details
-                    fetchLatestUpdate(payload.new);
-                    // Add notification for successful update
-                    addNotification(`New booking notice at ${payload.new.created_at}`, 'info');
-                }
-            );
-
-            // Subscribe to UPDATE events
-            channel.on(
-                "postgres_changes",
-                {
-                    event: "UPDATE",
-                    schema: "public",
-                    table: "booking",
-                    filter: `company_id=eq.${auth.company.id}`
-                },
-                (payload) => {
-                    console.log("Booking updated:", payload.new);
-                    // Fetch the full booking details
-                    fetchLatestUpdate(payload.new);
-                    // Add notification for successful update
-                    const localTime = new Date(payload.new.start_time).toLocaleString();
-                    addNotification(`Booking updated notice at ${localTime}`, 'info');
-                }
-            );
-
-            // Subscribe to DELETE events
-            channel.on(
-                "postgres_changes",
-                {
-                    event: "DELETE",
-                    schema: "public",
-                    table: "booking",
-                    filter: `company_id=eq.${auth.company.id}`
-                },
-                (payload) => {
-                    console.log("Booking deleted:", payload.old);
-                    fetchLatestUpdate(payload.old);
-                    // Add notification for successful update
-                    addNotification(`Cancellation notice`, 'info');
-                    // For DELETE events, you might just want to remove the booking from your state
-                    // As an alternative, you could also fetch all current bookings to refresh the state
-                }
-            );
-
-            const subscription = channel.subscribe();
-
-            // Clean up subscription on unmount
-            return () => {
-                subscription.unsubscribe();
-            };
-        };
         // Function to fetch full booking details
         const fetchFullBookingDetails = async () => {
             console.log("activate listening to update state");
@@ -299,7 +383,6 @@ export function AppProvider({ children }: AppProviderProps) {
             };
         };
         fetchFullBookingDetails();
-        subscribeBooking();
     }
     // }, []);
 
@@ -309,6 +392,96 @@ export function AppProvider({ children }: AppProviderProps) {
         }
     }, [auth?.company.id]);
 
+    // Report user activity to the BE idle lock, at most every 30 seconds
+    useEffect(() => {
+        let lastReported = 0;
+        const reportActivity = () => {
+            const now = Date.now();
+            if (now - lastReported > 30000) {
+                lastReported = now;
+                invoke('record_activity').catch(() => { });
+            }
+        };
+        const events = ['mousemove', 'mousedown', 'keydown', 'touchstart'];
+        events.forEach((name) => window.addEventListener(name, reportActivity));
+        const unlistenLocked = listen('session-locked', () => setAuth(null));
+        return () => {
+            events.forEach((name) => window.removeEventListener(name, reportActivity));
+            unlistenLocked.then(unlistenFn => unlistenFn());
+        };
+    }, []);
+
+    // Commands patch single bookings and customers instead of resending the whole state
+    useEffect(() => {
+        const unlisten = listen<StateDelta[]>('state-delta', (event) => {
+            setAuth((current) => current && event.payload.reduce(applyDelta, current));
+        });
+        return () => {
+            unlisten.then(unlistenFn => unlistenFn());
+        };
+    }, []);
+
+    // Bookings made or changed elsewhere, e.g. on the public booking site. The BE listens
+    // for them and has already patched the state by the time these arrive.
+    useEffect(() => {
+        const unlistenCreated = listen<Booking>('booking-created', (event) => {
+            const localTime = new Date(event.payload.start_time).toLocaleString();
+            addNotification(`New booking notice at ${localTime}`, 'info');
+        });
+        const unlistenStatus = listen<{ booking: Booking }>('booking-status-changed', (event) => {
+            const { booking } = event.payload;
+            if (booking.status?.name === 'cancelled') {
+                addNotification(`Cancellation notice`, 'info');
+            } else {
+                addNotification(`Booking is now ${booking.status?.name}`, 'info');
+            }
+        });
+        const unlistenRescheduled = listen<Booking>('booking-rescheduled', (event) => {
+            const localTime = new Date(event.payload.start_time).toLocaleString();
+            addNotification(`Booking updated notice at ${localTime}`, 'info');
+        });
+        const unlistenDeleted = listen('booking-deleted', () => {
+            addNotification(`Cancellation notice`, 'info');
+        });
+        return () => {
+            unlistenCreated.then(unlistenFn => unlistenFn());
+            unlistenStatus.then(unlistenFn => unlistenFn());
+            unlistenRescheduled.then(unlistenFn => unlistenFn());
+            unlistenDeleted.then(unlistenFn => unlistenFn());
+        };
+    }, []);
+
+    // Track whether the BE can reach the database
+    useEffect(() => {
+        invoke<ConnectionStatus>('get_connection_status')
+            .then(setConnectionStatus)
+            .catch(() => { });
+        const unlisten = listen<ConnectionStatus>('connection-status', (event) => {
+            setConnectionStatus(event.payload);
+            if (event.payload === 'offline') {
+                addNotification('Connection lost, retrying in the background', 'error');
+            }
+        });
+        // The BE won't use a database whose schema doesn't match this build
+        const unlistenSchema = listen<AppError>('schema-mismatch', (event) => {
+            addNotification(event.payload.message, 'error');
+        });
+        // Changes made offline are replayed on reconnect; flag anything that didn't go through
+        const unlistenSync = listen<{ synced: number, conflicts: number, failed: number }>('sync-finished', (event) => {
+            const { synced, conflicts, failed } = event.payload;
+            if (conflicts + failed > 0) {
+                addNotification(`${conflicts + failed} offline change(s) could not be synced, check the sync report`, 'error');
+            } else if (synced > 0) {
+                addNotification(`${synced} offline change(s) synced`, 'success');
+            }
+        });
+        return () => {
+            unlisten.then(unlistenFn => unlistenFn());
+            unlistenSchema.then(unlistenFn => unlistenFn());
+            unlistenSync.then(unlistenFn => unlistenFn());
+        };
+    }, []);
+
     // UPDATE STATE BY BE EVENT
     // useEffect(() => {
     //     if (auth?.company.id) {
@@ -328,10 +501,14 @@ export function AppProvider({ children }: AppProviderProps) {
 
     const value: AppContextData = {
         auth: auth || null,
+        connectionStatus,
         setAuthentication,
         getUser,
         logout,
+        createBooking,
+        findAvailableSlots,
         cancelBooking,
+        updateBookingStatus,
         // Add notifications to context value
         notifications,
         addNotification,
@@ -342,7 +519,9 @@ export function AppProvider({ children }: AppProviderProps) {
         editCustomer,
         deleteCustomer,
         checkoutWalkin,
-        updateCampaign
+        updateCampaign,
+        switchOperator,
+        switchCompany
     };
 
     return <AppContext.Provider value={value}>{children}</AppContext.Provider>;
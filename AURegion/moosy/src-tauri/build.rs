@@ -1,3 +1,5 @@
 fn main() {
+  // The migrations are embedded with `sqlx::migrate!`
+  println!("cargo:rerun-if-changed=../../migrations/supabase/migrations");
   tauri_build::build()
 }
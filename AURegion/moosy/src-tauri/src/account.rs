@@ -0,0 +1,229 @@
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use sha2::{Digest, Sha256};
+use sqlx::{FromRow, PgPool};
+use tauri::State;
+
+use crate::{
+    clear_auth,
+    config::{Config, Feature},
+    error::AppError,
+    issue_token, lockout,
+    notifier::{Notice, Notifications},
+    password, save_auth, session_username, verify_credentials, AuthState, Session,
+};
+
+// How long a password reset code stays valid
+const RESET_CODE_MINUTES: i32 = 15;
+
+// Hash stored in `public.tokens` for a reset code. The login id is mixed in so two people
+// who happen to get the same code don't collide on the unique token column.
+fn reset_code_hash(login_id: sqlx::types::Uuid, code: &str) -> String {
+    hex::encode(Sha256::digest(format!("{}:{}", login_id, code).as_bytes()))
+}
+
+// Six random digits, easy to read off a notice and type back in
+fn generate_reset_code() -> String {
+    format!("{:06}", OsRng.next_u32() % 1_000_000)
+}
+
+// Change the signed-in person's password. Other sessions for the login are revoked and
+// this terminal carries on with a freshly issued token.
+#[tauri::command]
+pub async fn change_password(
+    current_password: String,
+    new_password: String,
+    pool: State<'_, PgPool>,
+    state: State<'_, AuthState>,
+    app: tauri::AppHandle,
+) -> Result<(), AppError> {
+    let session = state.current()?;
+
+    let username = match session_username(&pool, &session.token).await? {
+        Some(username) => username,
+        None => {
+            clear_auth(&app)?;
+            return Err(AppError::SessionExpired);
+        }
+    };
+
+    verify_credentials(&pool, &username, &current_password).await?;
+    password::validate_new_password(&new_password)?;
+
+    let new_hash = password::hash_password(&new_password)?;
+    set_password(&pool, &username, &new_hash).await?;
+
+    let (token, expires_at) = issue_token(&pool, &username, &new_hash).await?;
+    save_auth(
+        &app,
+        Session {
+            token,
+            expires_at,
+            unlock_verifier: Some(password::hash_password(&new_password)?),
+            ..session
+        },
+    )?;
+
+    Ok(())
+}
+
+// Store a new password hash and revoke every token held by the login
+async fn set_password(
+    pool: &PgPool,
+    username: &str,
+    password_hash: &str,
+) -> Result<(), AppError> {
+    let mut tx = pool
+        .begin()
+        .await?;
+
+    let login_id: sqlx::types::Uuid = sqlx::query_scalar(
+        "UPDATE public.login SET password_hash = $1, updated_at = NOW()
+         WHERE username = $2
+         RETURNING id",
+    )
+    .bind(password_hash)
+    .bind(username)
+    .fetch_one(&mut *tx)
+    .await?;
+
+    sqlx::query("UPDATE public.tokens SET is_active = FALSE WHERE login_id = $1")
+        .bind(login_id)
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+    Ok(())
+}
+
+#[derive(FromRow)]
+struct ResetTarget {
+    id: sqlx::types::Uuid,
+    recipient: String,
+    #[sqlx(flatten)]
+    attempts: lockout::LoginAttempts,
+}
+
+async fn find_reset_target(
+    pool: &PgPool,
+    username: &str,
+) -> Result<Option<ResetTarget>, AppError> {
+    // Send to the primary email on file, falling back to the username itself
+    Ok(sqlx::query_as(
+        "SELECT l.id, l.failed_attempts, l.locked_until,
+             COALESCE((
+                 SELECT cm.value FROM public.contact_method cm
+                 WHERE cm.person_id = l.person_id AND cm.type = 'email'
+                 ORDER BY cm.is_primary DESC, cm.created_at DESC
+                 LIMIT 1
+             ), l.username) AS recipient
+         FROM public.login l
+         WHERE l.username = $1",
+    )
+    .bind(username)
+    .fetch_optional(pool)
+    .await?)
+}
+
+// Issue a one-time reset code and hand it to the notifier. Succeeds whether or not the
+// username exists, so the command can't be used to probe for accounts.
+#[tauri::command]
+pub async fn request_password_reset(
+    username: String,
+    pool: State<'_, PgPool>,
+    notifications: State<'_, Notifications>,
+    config: State<'_, Config>,
+) -> Result<(), AppError> {
+    config.require(Feature::PasswordReset)?;
+    notifications.require()?;
+
+    let target = match find_reset_target(&pool, &username).await? {
+        Some(target) => target,
+        None => return Ok(()),
+    };
+
+    let code = generate_reset_code();
+
+    // Only the latest code is usable
+    sqlx::query(
+        "UPDATE public.tokens SET is_active = FALSE
+         WHERE login_id = $1 AND purpose = 'password_reset'",
+    )
+    .bind(target.id)
+    .execute(&*pool)
+    .await?;
+
+    sqlx::query(
+        "INSERT INTO public.tokens (login_id, token, expires_at, purpose)
+         VALUES ($1, $2, NOW() + make_interval(mins => $3), 'password_reset')",
+    )
+    .bind(target.id)
+    .bind(reset_code_hash(target.id, &code))
+    .bind(RESET_CODE_MINUTES)
+    .execute(&*pool)
+    .await?;
+
+    notifications.send(&Notice {
+        recipient: target.recipient,
+        subject: "Your password reset code".to_string(),
+        body: format!(
+            "Use code {} to reset your password. It expires in {} minutes.",
+            code, RESET_CODE_MINUTES
+        ),
+    })?;
+
+    Ok(())
+}
+
+// Set a new password with a reset code. Wrong codes count towards the login lockout, and
+// a successful reset revokes every existing session for the login.
+#[tauri::command]
+pub async fn reset_password(
+    username: String,
+    code: String,
+    new_password: String,
+    pool: State<'_, PgPool>,
+    state: State<'_, AuthState>,
+    config: State<'_, Config>,
+    app: tauri::AppHandle,
+) -> Result<(), AppError> {
+    const INVALID_CODE: &str = "Invalid or expired reset code";
+
+    config.require(Feature::PasswordReset)?;
+
+    // Check the new password first so a weak one doesn't burn the code
+    password::validate_new_password(&new_password)?;
+
+    let target = match find_reset_target(&pool, &username).await? {
+        Some(target) => target,
+        None => return Err(AppError::Invalid(INVALID_CODE.to_string())),
+    };
+    target.attempts.check()?;
+
+    let consumed = sqlx::query(
+        "UPDATE public.tokens SET is_active = FALSE
+         WHERE login_id = $1 AND token = $2 AND purpose = 'password_reset'
+         AND is_active = TRUE AND expires_at > NOW()",
+    )
+    .bind(target.id)
+    .bind(reset_code_hash(target.id, code.trim()))
+    .execute(&*pool)
+    .await?;
+
+    if consumed.rows_affected() == 0 {
+        lockout::record_failure(&pool, target.id).await?;
+        return Err(AppError::Invalid(INVALID_CODE.to_string()));
+    }
+    lockout::record_success(&pool, target.id).await?;
+
+    let new_hash = password::hash_password(&new_password)?;
+    set_password(&pool, &username, &new_hash).await?;
+
+    // Drop the local session too if it belonged to the login that was just reset
+    if let Some(session) = state.get() {
+        if session_username(&pool, &session.token).await?.is_none() {
+            clear_auth(&app)?;
+        }
+    }
+
+    Ok(())
+}
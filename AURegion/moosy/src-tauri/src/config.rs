@@ -0,0 +1,800 @@
+use serde::{Deserialize, Serialize};
+use std::{fmt, sync::RwLock, time::Duration};
+use tauri::{Manager, State};
+
+use crate::{crypto, error::AppError, permissions::Permission, AuthState};
+
+// Database connection settings. Pool sizes and timeouts are picked up on the next start,
+// the health check and backoff straight away.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct DatabaseConfig {
+    pub max_connections: u32,
+    pub min_connections: u32,
+    // How long to wait for a connection, including the very first one at startup
+    pub acquire_timeout_secs: u64,
+    // Close connections that sat unused this long, 0 keeps them open
+    pub idle_timeout_secs: u64,
+    // How often a reachable database is checked again
+    pub health_check_secs: u64,
+    // Longest wait between reconnect attempts while offline
+    pub max_backoff_secs: u64,
+    // Apply the embedded migrations on connecting, for a local Postgres set up by the app
+    pub migrate_on_connect: bool,
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        DatabaseConfig {
+            max_connections: 2,
+            min_connections: 0,
+            acquire_timeout_secs: 10,
+            idle_timeout_secs: 600,
+            health_check_secs: 15,
+            max_backoff_secs: 60,
+            migrate_on_connect: false,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct RefreshConfig {
+    // Reload the signed-in company's state in the background, 0 disables it
+    pub state_refresh_secs: u64,
+    // How often the idle lock and session expiry are checked
+    pub session_check_secs: u64,
+}
+
+impl Default for RefreshConfig {
+    fn default() -> Self {
+        RefreshConfig {
+            state_refresh_secs: 0,
+            session_check_secs: 5,
+        }
+    }
+}
+
+// Defaults for the availability search, the search itself can override them
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct BookingConfig {
+    // Offered start times are this far apart, counted from opening time
+    pub slot_granularity_minutes: u32,
+    // How soon from now a slot can start
+    pub min_notice_minutes: u32,
+}
+
+impl Default for BookingConfig {
+    fn default() -> Self {
+        BookingConfig {
+            slot_granularity_minutes: 15,
+            min_notice_minutes: 60,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl LogLevel {
+    pub fn filter(self) -> log::LevelFilter {
+        match self {
+            LogLevel::Error => log::LevelFilter::Error,
+            LogLevel::Warn => log::LevelFilter::Warn,
+            LogLevel::Info => log::LevelFilter::Info,
+            LogLevel::Debug => log::LevelFilter::Debug,
+            LogLevel::Trace => log::LevelFilter::Trace,
+        }
+    }
+}
+
+impl std::str::FromStr for LogLevel {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "error" => Ok(LogLevel::Error),
+            "warn" => Ok(LogLevel::Warn),
+            "info" => Ok(LogLevel::Info),
+            "debug" => Ok(LogLevel::Debug),
+            "trace" => Ok(LogLevel::Trace),
+            _ => Err(()),
+        }
+    }
+}
+
+// Log files are written to the app log dir. The level applies straight away, rotation
+// is checked on the next start.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct LoggingConfig {
+    pub level: LogLevel,
+    // Start a new file once the current one is bigger than this
+    pub max_file_kb: u64,
+    // Rotated files kept besides the current one
+    pub keep_files: usize,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        LoggingConfig {
+            level: LogLevel::Info,
+            max_file_kb: 2048,
+            keep_files: 5,
+        }
+    }
+}
+
+// Parts of the app a business can switch off on a device
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct Features {
+    pub two_factor: bool,
+    pub password_reset: bool,
+    pub registration: bool,
+    pub operator_pins: bool,
+}
+
+impl Default for Features {
+    fn default() -> Self {
+        Features {
+            two_factor: true,
+            password_reset: true,
+            registration: true,
+            operator_pins: true,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Feature {
+    TwoFactor,
+    PasswordReset,
+    Registration,
+    OperatorPins,
+}
+
+impl fmt::Display for Feature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Feature::TwoFactor => "two-factor authentication",
+            Feature::PasswordReset => "password reset",
+            Feature::Registration => "business registration",
+            Feature::OperatorPins => "operator PINs",
+        })
+    }
+}
+
+impl Features {
+    pub fn enabled(&self, feature: Feature) -> bool {
+        match feature {
+            Feature::TwoFactor => self.two_factor,
+            Feature::PasswordReset => self.password_reset,
+            Feature::Registration => self.registration,
+            Feature::OperatorPins => self.operator_pins,
+        }
+    }
+}
+
+// Values that never leave the backend through `get_settings`
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct Secrets {
+    pub database_url: Option<String>,
+    // Hex encoded 256-bit key the TOTP secrets are sealed with
+    pub mfa_encryption_key: Option<String>,
+    // Optional passphrase mixed into the local cache key, see `vault`
+    pub auth_cache_passphrase: Option<String>,
+}
+
+// Everything the app reads at startup. Built from the defaults, then `config.json` in the
+// app config dir, then `.env` and the process environment, each overriding the last.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct AppConfig {
+    pub database: DatabaseConfig,
+    pub refresh: RefreshConfig,
+    pub bookings: BookingConfig,
+    pub features: Features,
+    pub logging: LoggingConfig,
+    pub secrets: Secrets,
+}
+
+// The non-secret part of the config, as shown and edited in the app
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Settings {
+    pub database: DatabaseConfig,
+    pub refresh: RefreshConfig,
+    pub bookings: BookingConfig,
+    pub features: Features,
+    pub logging: LoggingConfig,
+}
+
+#[derive(Serialize)]
+pub struct SettingsView {
+    #[serde(flatten)]
+    pub settings: Settings,
+    // Settings pinned by an environment variable, which a saved value can't override
+    pub env_overrides: Vec<&'static str>,
+    // Why the saved settings couldn't be used at startup, until they are saved again
+    pub startup_error: Option<String>,
+}
+
+// Environment variables that override a setting, with the setting they map to
+const ENV_OVERRIDES: &[(&str, &str)] = &[
+    ("MOOSY_DB_MAX_CONNECTIONS", "database.max_connections"),
+    ("MOOSY_DB_MIN_CONNECTIONS", "database.min_connections"),
+    (
+        "MOOSY_DB_ACQUIRE_TIMEOUT_SECS",
+        "database.acquire_timeout_secs",
+    ),
+    ("MOOSY_DB_IDLE_TIMEOUT_SECS", "database.idle_timeout_secs"),
+    ("MOOSY_DB_HEALTH_CHECK_SECS", "database.health_check_secs"),
+    ("MOOSY_DB_MAX_BACKOFF_SECS", "database.max_backoff_secs"),
+    ("MOOSY_DB_MIGRATE_ON_CONNECT", "database.migrate_on_connect"),
+    ("MOOSY_STATE_REFRESH_SECS", "refresh.state_refresh_secs"),
+    ("MOOSY_SESSION_CHECK_SECS", "refresh.session_check_secs"),
+    (
+        "MOOSY_SLOT_GRANULARITY_MINUTES",
+        "bookings.slot_granularity_minutes",
+    ),
+    ("MOOSY_MIN_NOTICE_MINUTES", "bookings.min_notice_minutes"),
+    ("MOOSY_FEATURE_TWO_FACTOR", "features.two_factor"),
+    ("MOOSY_FEATURE_PASSWORD_RESET", "features.password_reset"),
+    ("MOOSY_FEATURE_REGISTRATION", "features.registration"),
+    ("MOOSY_FEATURE_OPERATOR_PINS", "features.operator_pins"),
+    ("MOOSY_LOG_LEVEL", "logging.level"),
+    ("DATABASE_URL", "secrets.database_url"),
+    ("MFA_ENCRYPTION_KEY", "secrets.mfa_encryption_key"),
+    ("AUTH_CACHE_PASSPHRASE", "secrets.auth_cache_passphrase"),
+];
+
+fn env_var(name: &str) -> Option<String> {
+    dotenv::var(name)
+        .ok()
+        .filter(|value| !value.trim().is_empty())
+}
+
+// Looks an environment variable up, `env_var` outside the tests
+type EnvLookup<'a> = &'a dyn Fn(&str) -> Option<String>;
+
+fn parse_env<T: std::str::FromStr>(
+    env: EnvLookup,
+    name: &str,
+    expected: &str,
+    errors: &mut Vec<String>,
+) -> Option<T> {
+    let value = env(name)?;
+    match value.trim().parse() {
+        Ok(parsed) => Some(parsed),
+        Err(_) => {
+            errors.push(format!("{} must be {}, got '{}'", name, expected, value));
+            None
+        }
+    }
+}
+
+impl Secrets {
+    fn apply_env(&mut self, env: EnvLookup) {
+        if let Some(v) = env("DATABASE_URL") {
+            self.database_url = Some(v);
+        }
+        if let Some(v) = env("MFA_ENCRYPTION_KEY") {
+            self.mfa_encryption_key = Some(v);
+        }
+        if let Some(v) = env("AUTH_CACHE_PASSPHRASE") {
+            self.auth_cache_passphrase = Some(v);
+        }
+    }
+}
+
+impl BookingConfig {
+    // Shared with the search, which takes the same values as overrides
+    pub fn validate(&self) -> Result<(), AppError> {
+        if self.slot_granularity_minutes == 0 || self.slot_granularity_minutes > 240 {
+            return Err(AppError::Invalid(
+                "bookings.slot_granularity_minutes must be between 1 and 240".to_string(),
+            ));
+        }
+        if self.min_notice_minutes > 7 * 24 * 60 {
+            return Err(AppError::Invalid(
+                "bookings.min_notice_minutes can't be more than a week".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl AppConfig {
+    fn path(app: &tauri::AppHandle) -> Result<std::path::PathBuf, AppError> {
+        Ok(app
+            .path()
+            .app_config_dir()
+            .map_err(|e| e.to_string())?
+            .join("config.json"))
+    }
+
+    // Only what is saved in config.json. A missing file is fine, a broken one is not.
+    fn load_file(app: &tauri::AppHandle) -> Result<AppConfig, AppError> {
+        let path = Self::path(app)?;
+        match std::fs::read_to_string(&path) {
+            Ok(content) => serde_json::from_str(&content).map_err(|e| {
+                AppError::Invalid(format!(
+                    "Invalid configuration in {}: {}",
+                    path.display(),
+                    e
+                ))
+            }),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(AppConfig::default()),
+            Err(e) => Err(AppError::Message(format!(
+                "Failed to read {}: {}",
+                path.display(),
+                e
+            ))),
+        }
+    }
+
+    fn save_file(&self, app: &tauri::AppHandle) -> Result<(), AppError> {
+        let path = Self::path(app)?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .map_err(|e| format!("Failed to create app config directory: {}", e))?;
+        }
+        let formatted_json = serde_json::to_string_pretty(self)
+            .map_err(|e| format!("Failed to serialize configuration: {}", e))?;
+        std::fs::write(path, formatted_json)
+            .map_err(|e| AppError::Message(format!("Failed to write configuration: {}", e)))
+    }
+
+    // Apply `.env` and the process environment on top of the saved file
+    fn with_env(self) -> Result<AppConfig, AppError> {
+        let _ = dotenv::dotenv();
+        self.with_vars(&env_var)
+    }
+
+    fn with_vars(mut self, env: EnvLookup) -> Result<AppConfig, AppError> {
+        let mut errors = Vec::new();
+
+        let number = "a whole number";
+        let flag = "true or false";
+        let db = &mut self.database;
+        if let Some(v) = parse_env(env, "MOOSY_DB_MAX_CONNECTIONS", number, &mut errors) {
+            db.max_connections = v;
+        }
+        if let Some(v) = parse_env(env, "MOOSY_DB_MIN_CONNECTIONS", number, &mut errors) {
+            db.min_connections = v;
+        }
+        if let Some(v) = parse_env(env, "MOOSY_DB_ACQUIRE_TIMEOUT_SECS", number, &mut errors) {
+            db.acquire_timeout_secs = v;
+        }
+        if let Some(v) = parse_env(env, "MOOSY_DB_IDLE_TIMEOUT_SECS", number, &mut errors) {
+            db.idle_timeout_secs = v;
+        }
+        if let Some(v) = parse_env(env, "MOOSY_DB_HEALTH_CHECK_SECS", number, &mut errors) {
+            db.health_check_secs = v;
+        }
+        if let Some(v) = parse_env(env, "MOOSY_DB_MAX_BACKOFF_SECS", number, &mut errors) {
+            db.max_backoff_secs = v;
+        }
+        if let Some(v) = parse_env(env, "MOOSY_DB_MIGRATE_ON_CONNECT", flag, &mut errors) {
+            db.migrate_on_connect = v;
+        }
+
+        let refresh = &mut self.refresh;
+        if let Some(v) = parse_env(env, "MOOSY_STATE_REFRESH_SECS", number, &mut errors) {
+            refresh.state_refresh_secs = v;
+        }
+        if let Some(v) = parse_env(env, "MOOSY_SESSION_CHECK_SECS", number, &mut errors) {
+            refresh.session_check_secs = v;
+        }
+
+        let bookings = &mut self.bookings;
+        if let Some(v) = parse_env(env, "MOOSY_SLOT_GRANULARITY_MINUTES", number, &mut errors) {
+            bookings.slot_granularity_minutes = v;
+        }
+        if let Some(v) = parse_env(env, "MOOSY_MIN_NOTICE_MINUTES", number, &mut errors) {
+            bookings.min_notice_minutes = v;
+        }
+
+        let features = &mut self.features;
+        if let Some(v) = parse_env(env, "MOOSY_FEATURE_TWO_FACTOR", flag, &mut errors) {
+            features.two_factor = v;
+        }
+        if let Some(v) = parse_env(env, "MOOSY_FEATURE_PASSWORD_RESET", flag, &mut errors) {
+            features.password_reset = v;
+        }
+        if let Some(v) = parse_env(env, "MOOSY_FEATURE_REGISTRATION", flag, &mut errors) {
+            features.registration = v;
+        }
+        if let Some(v) = parse_env(env, "MOOSY_FEATURE_OPERATOR_PINS", flag, &mut errors) {
+            features.operator_pins = v;
+        }
+
+        if let Some(v) = parse_env(
+            env,
+            "MOOSY_LOG_LEVEL",
+            "one of error, warn, info, debug or trace",
+            &mut errors,
+        ) {
+            self.logging.level = v;
+        }
+
+        self.secrets.apply_env(env);
+
+        if errors.is_empty() {
+            Ok(self)
+        } else {
+            Err(AppError::Invalid(format!(
+                "Invalid configuration: {}",
+                errors.join("; ")
+            )))
+        }
+    }
+
+    // Check every value and report all problems at once rather than the first one
+    pub fn validate(&self) -> Result<(), AppError> {
+        let mut errors = Vec::new();
+
+        let db = &self.database;
+        if db.max_connections == 0 || db.max_connections > 100 {
+            errors.push("database.max_connections must be between 1 and 100".to_string());
+        }
+        if db.min_connections > db.max_connections {
+            errors.push("database.min_connections can't be more than max_connections".to_string());
+        }
+        if db.acquire_timeout_secs == 0 {
+            errors.push("database.acquire_timeout_secs must be at least 1".to_string());
+        }
+        if db.health_check_secs == 0 {
+            errors.push("database.health_check_secs must be at least 1".to_string());
+        }
+        if db.max_backoff_secs == 0 || db.max_backoff_secs > 3600 {
+            errors.push("database.max_backoff_secs must be between 1 and 3600".to_string());
+        }
+
+        let refresh = &self.refresh;
+        if refresh.state_refresh_secs != 0 && refresh.state_refresh_secs < 10 {
+            errors.push("refresh.state_refresh_secs must be 0 or at least 10".to_string());
+        }
+        if refresh.session_check_secs == 0 || refresh.session_check_secs > 60 {
+            errors.push("refresh.session_check_secs must be between 1 and 60".to_string());
+        }
+
+        if let Err(e) = self.bookings.validate() {
+            errors.push(e.to_string());
+        }
+
+        let logging = &self.logging;
+        if logging.max_file_kb < 64 {
+            errors.push("logging.max_file_kb must be at least 64".to_string());
+        }
+        if logging.keep_files > 100 {
+            errors.push("logging.keep_files can't be more than 100".to_string());
+        }
+
+        match self.secrets.database_url.as_deref() {
+            None => errors.push(
+                "no database URL, set DATABASE_URL or secrets.database_url in config.json"
+                    .to_string(),
+            ),
+            Some(url) if !(url.starts_with("postgres://") || url.starts_with("postgresql://")) => {
+                errors.push("the database URL must start with postgres://".to_string())
+            }
+            Some(_) => (),
+        }
+        if let Some(key) = &self.secrets.mfa_encryption_key {
+            if let Err(e) = crypto::parse_key(key) {
+                errors.push(format!("MFA_ENCRYPTION_KEY is invalid: {}", e));
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(AppError::Invalid(format!(
+                "Invalid configuration: {}",
+                errors.join("; ")
+            )))
+        }
+    }
+
+    pub fn load(app: &tauri::AppHandle) -> Result<AppConfig, AppError> {
+        let config = Self::load_file(app)?.with_env()?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    // What to start with when `load` fails: the defaults, with whatever secrets the file and
+    // the environment hold so the database can still be reached
+    pub fn fallback(app: &tauri::AppHandle) -> AppConfig {
+        let _ = dotenv::dotenv();
+        let mut secrets = Self::load_file(app)
+            .map(|config| config.secrets)
+            .unwrap_or_default();
+        secrets.apply_env(&env_var);
+        AppConfig {
+            secrets,
+            ..AppConfig::default()
+        }
+    }
+
+    pub fn settings(&self) -> Settings {
+        Settings {
+            database: self.database.clone(),
+            refresh: self.refresh.clone(),
+            bookings: self.bookings.clone(),
+            features: self.features.clone(),
+            logging: self.logging.clone(),
+        }
+    }
+
+    pub fn acquire_timeout(&self) -> Duration {
+        Duration::from_secs(self.database.acquire_timeout_secs)
+    }
+
+    pub fn idle_timeout(&self) -> Option<Duration> {
+        match self.database.idle_timeout_secs {
+            0 => None,
+            secs => Some(Duration::from_secs(secs)),
+        }
+    }
+}
+
+// The effective config, shared with the commands and background tasks
+pub struct Config {
+    current: RwLock<AppConfig>,
+    startup_error: RwLock<Option<String>>,
+}
+
+impl Config {
+    // `startup_error` is why `config` is the fallback rather than the loaded settings
+    pub fn new(config: AppConfig, startup_error: Option<AppError>) -> Self {
+        Config {
+            current: RwLock::new(config),
+            startup_error: RwLock::new(startup_error.map(|e| e.to_string())),
+        }
+    }
+
+    pub fn get(&self) -> AppConfig {
+        self.current
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    // Refuse a command whose feature has been switched off
+    pub fn require(&self, feature: Feature) -> Result<(), AppError> {
+        if self.get().features.enabled(feature) {
+            Ok(())
+        } else {
+            Err(AppError::FeatureDisabled(feature))
+        }
+    }
+}
+
+#[tauri::command]
+pub fn get_settings(config: State<'_, Config>) -> SettingsView {
+    SettingsView {
+        settings: config.get().settings(),
+        env_overrides: ENV_OVERRIDES
+            .iter()
+            .filter(|(name, setting)| !setting.starts_with("secrets.") && env_var(name).is_some())
+            .map(|(_, setting)| *setting)
+            .collect(),
+        startup_error: config
+            .startup_error
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone(),
+    }
+}
+
+// Save new non-secret settings to config.json. Refresh intervals, booking defaults, features
+// and the log level apply straight away, pool and log file settings on the next start.
+#[tauri::command]
+pub fn update_settings(
+    settings: Settings,
+    config: State<'_, Config>,
+    state: State<'_, AuthState>,
+    app: tauri::AppHandle,
+) -> Result<SettingsView, AppError> {
+    state.authorize(Permission::ManageSettings)?;
+
+    // Only the saved layer is written back, so secrets from the environment stay out of it
+    let mut saved = AppConfig::load_file(&app)?;
+    saved.database = settings.database;
+    saved.refresh = settings.refresh;
+    saved.bookings = settings.bookings;
+    saved.features = settings.features;
+    saved.logging = settings.logging;
+
+    let effective = saved.clone().with_env()?;
+    effective.validate()?;
+
+    saved.save_file(&app)?;
+    log::set_max_level(effective.logging.level.filter());
+    *config.current.write().unwrap_or_else(|e| e.into_inner()) = effective;
+    *config
+        .startup_error
+        .write()
+        .unwrap_or_else(|e| e.into_inner()) = None;
+    log::info!(command = "update_settings"; "Settings updated");
+
+    Ok(get_settings(config))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn vars(pairs: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let vars: HashMap<String, String> = pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        move |name| vars.get(name).cloned()
+    }
+
+    fn valid() -> AppConfig {
+        AppConfig {
+            secrets: Secrets {
+                database_url: Some("postgres://localhost/moosy".to_string()),
+                ..Secrets::default()
+            },
+            ..AppConfig::default()
+        }
+    }
+
+    #[test]
+    fn defaults_with_a_database_url_are_valid() {
+        valid().validate().unwrap();
+
+        let mut config = valid();
+        config.secrets.database_url = Some("postgresql://localhost/moosy".to_string());
+        config.validate().unwrap();
+    }
+
+    #[test]
+    fn environment_overrides_the_file() {
+        let file: AppConfig = serde_json::from_str(
+            r#"{
+                "database": { "max_connections": 5, "min_connections": 2 },
+                "bookings": { "min_notice_minutes": 30 },
+                "logging": { "level": "warn" },
+                "secrets": { "database_url": "postgres://file/moosy" }
+            }"#,
+        )
+        .unwrap();
+
+        let config = file
+            .with_vars(&vars(&[
+                ("MOOSY_DB_MAX_CONNECTIONS", "20"),
+                ("MOOSY_LOG_LEVEL", "debug"),
+                ("DATABASE_URL", "postgres://env/moosy"),
+            ]))
+            .unwrap();
+
+        assert_eq!(config.database.max_connections, 20);
+        assert_eq!(config.logging.level.filter(), log::LevelFilter::Debug);
+        assert_eq!(
+            config.secrets.database_url.as_deref(),
+            Some("postgres://env/moosy")
+        );
+        // Whatever the environment doesn't set keeps its saved value
+        assert_eq!(config.database.min_connections, 2);
+        assert_eq!(config.bookings.min_notice_minutes, 30);
+    }
+
+    #[test]
+    fn malformed_environment_values_are_all_reported() {
+        let err = valid()
+            .with_vars(&vars(&[
+                ("MOOSY_DB_MAX_CONNECTIONS", "lots"),
+                ("MOOSY_FEATURE_TWO_FACTOR", "yes"),
+                ("MOOSY_LOG_LEVEL", "loud"),
+            ]))
+            .unwrap_err()
+            .to_string();
+
+        assert!(
+            err.contains("MOOSY_DB_MAX_CONNECTIONS must be a whole number"),
+            "{}",
+            err
+        );
+        assert!(
+            err.contains("MOOSY_FEATURE_TWO_FACTOR must be true or false"),
+            "{}",
+            err
+        );
+        assert!(err.contains("MOOSY_LOG_LEVEL must be one of"), "{}", err);
+    }
+
+    // Makes one value of a valid config invalid
+    type Break = fn(&mut AppConfig);
+
+    #[test]
+    fn each_invalid_value_is_rejected() {
+        let cases: &[(Break, &str)] = &[
+            (
+                |c| c.database.max_connections = 0,
+                "database.max_connections",
+            ),
+            (
+                |c| c.database.max_connections = 101,
+                "database.max_connections",
+            ),
+            (
+                |c| c.database.min_connections = 3,
+                "database.min_connections",
+            ),
+            (
+                |c| c.database.acquire_timeout_secs = 0,
+                "database.acquire_timeout_secs",
+            ),
+            (
+                |c| c.database.health_check_secs = 0,
+                "database.health_check_secs",
+            ),
+            (
+                |c| c.database.max_backoff_secs = 0,
+                "database.max_backoff_secs",
+            ),
+            (
+                |c| c.database.max_backoff_secs = 3601,
+                "database.max_backoff_secs",
+            ),
+            (
+                |c| c.refresh.state_refresh_secs = 5,
+                "refresh.state_refresh_secs",
+            ),
+            (
+                |c| c.refresh.session_check_secs = 0,
+                "refresh.session_check_secs",
+            ),
+            (
+                |c| c.refresh.session_check_secs = 61,
+                "refresh.session_check_secs",
+            ),
+            (
+                |c| c.bookings.slot_granularity_minutes = 0,
+                "bookings.slot_granularity_minutes",
+            ),
+            (
+                |c| c.bookings.slot_granularity_minutes = 241,
+                "bookings.slot_granularity_minutes",
+            ),
+            (
+                |c| c.bookings.min_notice_minutes = 7 * 24 * 60 + 1,
+                "bookings.min_notice_minutes",
+            ),
+            (|c| c.logging.max_file_kb = 63, "logging.max_file_kb"),
+            (|c| c.logging.keep_files = 101, "logging.keep_files"),
+            (|c| c.secrets.database_url = None, "no database URL"),
+            (
+                |c| c.secrets.database_url = Some("mysql://localhost/moosy".to_string()),
+                "must start with postgres://",
+            ),
+            (
+                |c| c.secrets.mfa_encryption_key = Some("not a key".to_string()),
+                "MFA_ENCRYPTION_KEY is invalid",
+            ),
+        ];
+
+        for (break_config, expected) in cases {
+            let mut config = valid();
+            break_config(&mut config);
+            let err = config.validate().unwrap_err().to_string();
+            assert!(err.contains(expected), "expected {}, got {}", expected, err);
+        }
+    }
+}
@@ -0,0 +1,218 @@
+use sqlx::PgPool;
+use std::{sync::RwLock, time::Duration};
+use tauri::{ipc::Invoke, Emitter, Manager, Runtime, State};
+
+use crate::{
+    config::Config,
+    error::AppError,
+    logging::Span,
+    schema::{self, SchemaMismatch},
+    AuthState,
+};
+
+// First retry after losing the database, doubled on every failure up to the configured cap
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+
+// Commands that only touch local state, so they keep working while offline
+const LOCAL_COMMANDS: &[&str] = &[
+    "read_auth",
+    "get_auth_state",
+    "get_connection_status",
+    "logout",
+    "record_activity",
+    "lock_session",
+    "unlock_session",
+    "get_lock_settings",
+    "set_lock_settings",
+    "get_operator",
+    "get_settings",
+    "update_settings",
+    "get_sync_report",
+    "dismiss_sync_issue",
+    "export_diagnostics",
+];
+
+// Commands that queue their change in the outbox while offline
+const QUEUED_COMMANDS: &[&str] = &[
+    "cancel_booking",
+    "add_customer",
+    "checkout_booking",
+    "checkout_walkin",
+];
+
+#[derive(serde::Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ConnectionStatus {
+    // Trying to reach the database for the first time since launch
+    Connecting,
+    Online,
+    // Unreachable; the monitor keeps retrying in the background
+    Offline,
+    // Reachable, but its schema doesn't match this build, so it isn't used
+    Incompatible,
+}
+
+// Last known reachability of the database, kept up to date by `spawn_monitor`
+pub struct Connection {
+    status: RwLock<ConnectionStatus>,
+    mismatch: RwLock<Option<SchemaMismatch>>,
+}
+
+impl Default for Connection {
+    fn default() -> Self {
+        Connection {
+            status: RwLock::new(ConnectionStatus::Connecting),
+            mismatch: RwLock::new(None),
+        }
+    }
+}
+
+impl Connection {
+    pub fn status(&self) -> ConnectionStatus {
+        *self.status.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    // Why the database was found incompatible, while it is
+    pub fn mismatch(&self) -> Option<SchemaMismatch> {
+        *self.mismatch.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn is_online(&self) -> bool {
+        self.status() == ConnectionStatus::Online
+    }
+
+    // Store the new status and tell the frontend, but only when it actually changed
+    fn set(&self, app: &tauri::AppHandle, status: ConnectionStatus) {
+        if status != ConnectionStatus::Incompatible {
+            *self.mismatch.write().unwrap_or_else(|e| e.into_inner()) = None;
+        }
+        let previous = std::mem::replace(
+            &mut *self.status.write().unwrap_or_else(|e| e.into_inner()),
+            status,
+        );
+        if previous != status {
+            if let Err(e) = app.emit("connection-status", status) {
+                log::warn!("Failed to emit connection status: {}", e);
+            }
+        }
+    }
+
+    fn set_incompatible(&self, app: &tauri::AppHandle, mismatch: SchemaMismatch) {
+        let previous = self
+            .mismatch
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .replace(mismatch);
+        if previous != Some(mismatch) {
+            log::error!("{}", mismatch);
+            let error =
+                serde_json::to_value(AppError::SchemaMismatch(mismatch)).unwrap_or_default();
+            if let Err(e) = app.emit("schema-mismatch", error) {
+                log::warn!("Failed to emit schema mismatch: {}", e);
+            }
+        }
+        self.set(app, ConnectionStatus::Incompatible);
+    }
+}
+
+// Refuse database-backed commands up front while offline, instead of letting each one
+// wait out the pool timeout, and while the schema doesn't match
+fn guard<R: Runtime>(invoke: &Invoke<R>) -> Result<(), AppError> {
+    let command = invoke.message.command();
+    if LOCAL_COMMANDS.contains(&command) || QUEUED_COMMANDS.contains(&command) {
+        return Ok(());
+    }
+    let webview = invoke.message.webview();
+    let connection = webview.state::<Connection>();
+    match (connection.status(), connection.mismatch()) {
+        (ConnectionStatus::Offline, _) => Err(AppError::Offline),
+        (ConnectionStatus::Incompatible, Some(mismatch)) => Err(AppError::SchemaMismatch(mismatch)),
+        _ => Ok(()),
+    }
+}
+
+// Debug line for every command call, with its name and the active company
+fn log_invoke<R: Runtime>(invoke: &Invoke<R>, refused: Option<&AppError>) {
+    if !log::log_enabled!(log::Level::Debug) {
+        return;
+    }
+    let session = invoke.message.webview().state::<AuthState>().get();
+    let command = invoke.message.command();
+    let span = match &session {
+        Some(session) => Span::session(command, session),
+        None => Span::new(command),
+    };
+    match refused {
+        Some(e) => span.debug(format_args!("Refused: {}", e)),
+        None => span.debug(format_args!("Invoked")),
+    }
+}
+
+// Wrap the generated command handler with `guard`
+pub fn guarded<R: Runtime>(
+    handler: impl Fn(Invoke<R>) -> bool + Send + Sync + 'static,
+) -> impl Fn(Invoke<R>) -> bool + Send + Sync + 'static {
+    move |invoke| {
+        if let Err(e) = guard(&invoke) {
+            log_invoke(&invoke, Some(&e));
+            invoke.resolver.reject(e);
+            return true;
+        }
+        log_invoke(&invoke, None);
+        handler(invoke)
+    }
+}
+
+// Background thread that checks the database, backs off while it is unreachable and runs
+// `on_reconnect` every time it comes back, including the first successful connection.
+// Each time it comes back the schema version is checked first, after migrating it when the
+// config asks for that.
+pub fn spawn_monitor(app: tauri::AppHandle, on_reconnect: fn(&tauri::AppHandle)) {
+    std::thread::spawn(move || {
+        let mut backoff = MIN_BACKOFF;
+        loop {
+            let pool = app.state::<PgPool>();
+            let connection = app.state::<Connection>();
+            let database = app.state::<Config>().get().database;
+
+            let reachable =
+                tauri::async_runtime::block_on(sqlx::query("SELECT 1").execute(&*pool)).is_ok();
+
+            if reachable {
+                backoff = MIN_BACKOFF;
+                if !connection.is_online() {
+                    let prepared = tauri::async_runtime::block_on(async {
+                        if database.migrate_on_connect {
+                            schema::migrate(&pool).await?;
+                        }
+                        schema::check(&pool).await
+                    });
+                    match prepared {
+                        Ok(()) => {
+                            log::info!("Database reachable");
+                            connection.set(&app, ConnectionStatus::Online);
+                            on_reconnect(&app);
+                        }
+                        Err(AppError::SchemaMismatch(mismatch)) => {
+                            connection.set_incompatible(&app, mismatch)
+                        }
+                        Err(e) => log::error!("Failed to check the database schema: {}", e),
+                    }
+                }
+                std::thread::sleep(Duration::from_secs(database.health_check_secs));
+            } else {
+                if connection.status() != ConnectionStatus::Offline {
+                    log::warn!("Database unreachable, retrying in the background");
+                }
+                connection.set(&app, ConnectionStatus::Offline);
+                std::thread::sleep(backoff);
+                backoff = (backoff * 2).min(Duration::from_secs(database.max_backoff_secs));
+            }
+        }
+    });
+}
+
+#[tauri::command]
+pub fn get_connection_status(connection: State<'_, Connection>) -> ConnectionStatus {
+    connection.status()
+}
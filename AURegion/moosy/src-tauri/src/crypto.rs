@@ -0,0 +1,58 @@
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use chacha20poly1305::{
+    aead::{Aead, KeyInit, Payload},
+    XChaCha20Poly1305, XNonce,
+};
+
+use crate::error::AppError;
+
+pub const KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 24;
+
+// Encrypt `plaintext` with XChaCha20-Poly1305 under a fresh random nonce. `aad` is
+// authenticated but not stored, so the ciphertext only opens for the same context.
+// The result is `nonce || ciphertext`.
+pub fn seal(key: &[u8; KEY_LEN], plaintext: &[u8], aad: &[u8]) -> Result<Vec<u8>, AppError> {
+    let mut nonce = [0u8; NONCE_LEN];
+    OsRng.fill_bytes(&mut nonce);
+
+    let ciphertext = XChaCha20Poly1305::new(key.into())
+        .encrypt(
+            XNonce::from_slice(&nonce),
+            Payload {
+                msg: plaintext,
+                aad,
+            },
+        )
+        .map_err(|_| "Failed to encrypt data".to_string())?;
+
+    let mut sealed = nonce.to_vec();
+    sealed.extend_from_slice(&ciphertext);
+    Ok(sealed)
+}
+
+// Reverse of `seal`. Fails on a wrong key, a different `aad` or any tampering.
+pub fn open(key: &[u8; KEY_LEN], sealed: &[u8], aad: &[u8]) -> Result<Vec<u8>, AppError> {
+    if sealed.len() < NONCE_LEN {
+        return Err(AppError::Message("Encrypted data is truncated".to_string()));
+    }
+    let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+
+    XChaCha20Poly1305::new(key.into())
+        .decrypt(
+            XNonce::from_slice(nonce),
+            Payload {
+                msg: ciphertext,
+                aad,
+            },
+        )
+        .map_err(|_| AppError::Message("Encrypted data could not be verified".to_string()))
+}
+
+// Parse a hex-encoded 256-bit key, e.g. from the environment
+pub fn parse_key(hex_key: &str) -> Result<[u8; KEY_LEN], AppError> {
+    let bytes = hex::decode(hex_key.trim()).map_err(|_| "Key must be hex encoded".to_string())?;
+    bytes
+        .try_into()
+        .map_err(|_| AppError::Message(format!("Key must be {} bytes", KEY_LEN)))
+}
@@ -0,0 +1,114 @@
+use tauri::{Emitter, Manager};
+
+use crate::{error::AppError, mirror, repo::CompanyRepo, AuthData, AuthState, Booking, Customer};
+
+// A change to one entity of the cached company state. Commands re-fetch only the booking or
+// customer they touched and send these to the frontend as `state-delta`, instead of reloading
+// the whole company after every change.
+#[derive(serde::Serialize, Clone, Debug)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Delta {
+    BookingUpserted { booking: Box<Booking> },
+    BookingRemoved { id: String },
+    CustomerUpserted { customer: Box<Customer> },
+    CustomerRemoved { id: String },
+}
+
+impl Delta {
+    pub fn apply(&self, data: &mut AuthData) {
+        match self {
+            Delta::BookingUpserted { booking } => {
+                let bookings = data.bookings.get_or_insert_with(Vec::new);
+                upsert(bookings, (**booking).clone(), |booking| &booking.id);
+            }
+            Delta::BookingRemoved { id } => {
+                if let Some(bookings) = &mut data.bookings {
+                    bookings.retain(|booking| &booking.id != id);
+                }
+            }
+            Delta::CustomerUpserted { customer } => {
+                let customers = data.roles.customer.get_or_insert_with(Vec::new);
+                upsert(customers, (**customer).clone(), |customer| &customer.id);
+
+                // Bookings carry their own copy of the customer
+                for booking in data.bookings.iter_mut().flatten() {
+                    if booking.customer.id == customer.id {
+                        booking.customer = (**customer).clone();
+                    }
+                }
+            }
+            Delta::CustomerRemoved { id } => {
+                if let Some(customers) = &mut data.roles.customer {
+                    customers.retain(|customer| &customer.id != id);
+                }
+            }
+        }
+    }
+}
+
+fn upsert<T>(items: &mut Vec<T>, item: T, id: impl Fn(&T) -> &String) {
+    match items.iter_mut().find(|existing| id(existing) == id(&item)) {
+        Some(existing) => *existing = item,
+        None => items.push(item),
+    }
+}
+
+// Re-fetch one booking of `company_id`. A booking that is gone is removed from the cache.
+pub async fn booking<R: CompanyRepo>(
+    repo: &R,
+    company_id: &str,
+    booking_id: &str,
+) -> Result<Delta, AppError> {
+    Ok(match repo.company_booking(company_id, booking_id).await? {
+        Some(booking) => Delta::BookingUpserted {
+            booking: Box::new(booking),
+        },
+        None => Delta::BookingRemoved {
+            id: booking_id.to_string(),
+        },
+    })
+}
+
+pub async fn customer<R: CompanyRepo>(
+    repo: &R,
+    company_id: &str,
+    customer_id: &str,
+) -> Result<Delta, AppError> {
+    let customer = repo.company_customer(company_id, customer_id).await?;
+    Ok(match customer {
+        Some(customer) => Delta::CustomerUpserted {
+            customer: Box::new(customer),
+        },
+        None => Delta::CustomerRemoved {
+            id: customer_id.to_string(),
+        },
+    })
+}
+
+// Patch the cached state of `company_id`, persist it and tell the frontend. Dropped when the
+// session has moved to another company or signed out in the meantime.
+pub fn publish(
+    app: &tauri::AppHandle,
+    company_id: &str,
+    deltas: Vec<Delta>,
+) -> Result<(), AppError> {
+    let patched = app.state::<AuthState>().update(|session| {
+        if session.data.company.id != company_id {
+            return false;
+        }
+        for delta in &deltas {
+            delta.apply(&mut session.data);
+        }
+        true
+    });
+    let session = match patched {
+        Some(session) => session,
+        None => return Ok(()),
+    };
+
+    crate::write_session_file(app, &session)?;
+    mirror::apply_in_background(app, company_id, &deltas);
+
+    app.emit("state-delta", &deltas)
+        .map_err(|e| AppError::Message(format!("Failed to emit event: {}", e)))
+}
@@ -0,0 +1,346 @@
+use serde::ser::SerializeStruct;
+use std::fmt;
+
+use crate::{
+    config::Feature,
+    permissions::{Permission, Role},
+    schema::SchemaMismatch,
+};
+
+// Error returned by commands. Serialized as `{ code, message, details }`: `code` is stable
+// for the frontend to branch on, `message` is safe to show as is and `details` carries extra
+// context when there is any.
+#[derive(Debug)]
+pub enum AppError {
+    NotAuthenticated,
+    // The session token was revoked or has lapsed, so there is no session to use any more
+    SessionExpired,
+    SessionLocked,
+    // Wrong username or password, without saying which
+    InvalidCredentials,
+    Forbidden { role: Role, permission: Permission },
+    // Too many failed sign-in attempts; the account is refused until the lockout lapses
+    LockedOut { retry_after_secs: u64 },
+    // The database can't be reached right now
+    Offline,
+    // Switched off in this device's configuration
+    FeatureDisabled(Feature),
+    // The database schema is older or newer than this build
+    SchemaMismatch(SchemaMismatch),
+    // The thing asked for doesn't exist, or not in the active company, e.g. "Booking"
+    NotFound(&'static str),
+    // The input was rejected before reaching the database
+    Invalid(String),
+    // The time asked for is taken or outside opening hours
+    Conflict(String),
+    // Constraint violations reported by Postgres, with the constraint name as details
+    Duplicate { constraint: Option<String> },
+    BrokenReference { constraint: Option<String> },
+    CheckFailed { constraint: Option<String> },
+    // Any other database failure. The driver's text is only shown in debug builds.
+    Database(String),
+    Message(String),
+}
+
+impl AppError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            AppError::NotAuthenticated => "not_authenticated",
+            AppError::SessionExpired => "session_expired",
+            AppError::SessionLocked => "session_locked",
+            AppError::InvalidCredentials => "invalid_credentials",
+            AppError::Forbidden { .. } => "forbidden",
+            AppError::LockedOut { .. } => "locked_out",
+            AppError::Offline => "offline",
+            AppError::FeatureDisabled(_) => "feature_disabled",
+            AppError::SchemaMismatch(_) => "schema_mismatch",
+            AppError::NotFound(_) => "not_found",
+            AppError::Invalid(_) => "invalid",
+            AppError::Conflict(_) => "conflict",
+            AppError::Duplicate { .. } => "duplicate",
+            AppError::BrokenReference { .. } => "broken_reference",
+            AppError::CheckFailed { .. } => "check_failed",
+            AppError::Database(_) => "database",
+            AppError::Message(_) => "error",
+        }
+    }
+
+    pub fn details(&self) -> Option<&str> {
+        match self {
+            AppError::Duplicate { constraint }
+            | AppError::BrokenReference { constraint }
+            | AppError::CheckFailed { constraint } => constraint.as_deref(),
+            AppError::Database(details) if cfg!(debug_assertions) => Some(details),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AppError::NotAuthenticated => f.write_str("Not authenticated"),
+            AppError::SessionExpired => f.write_str("Session expired or revoked"),
+            AppError::SessionLocked => f.write_str("Session locked"),
+            AppError::InvalidCredentials => f.write_str("Invalid username or password"),
+            AppError::Forbidden { role, permission } => {
+                write!(f, "The {} role is not allowed to {}", role, permission)
+            }
+            AppError::LockedOut { retry_after_secs } => write!(
+                f,
+                "Too many failed attempts, try again in {} seconds",
+                retry_after_secs
+            ),
+            AppError::Offline => f.write_str("Can't reach the server, check the connection"),
+            AppError::FeatureDisabled(feature) => {
+                write!(f, "This device has {} turned off", feature)
+            }
+            AppError::SchemaMismatch(mismatch) => mismatch.fmt(f),
+            AppError::NotFound(what) => write!(f, "{} not found", what),
+            AppError::Invalid(message) | AppError::Conflict(message) => f.write_str(message),
+            AppError::Duplicate { .. } => f.write_str("That record already exists"),
+            AppError::BrokenReference { .. } => {
+                f.write_str("It refers to a record that doesn't exist or is still in use")
+            }
+            AppError::CheckFailed { .. } => f.write_str("Some of the values aren't allowed"),
+            AppError::Database(_) => f.write_str("Something went wrong, please try again"),
+            AppError::Message(message) => f.write_str(message),
+        }
+    }
+}
+
+impl std::error::Error for AppError {}
+
+impl From<String> for AppError {
+    fn from(message: String) -> Self {
+        AppError::Message(message)
+    }
+}
+
+// Postgres SQLSTATE codes for the constraint violations we tell apart
+const UNIQUE_VIOLATION: &str = "23505";
+const FOREIGN_KEY_VIOLATION: &str = "23503";
+const CHECK_VIOLATION: &str = "23514";
+const EXCLUSION_VIOLATION: &str = "23P01";
+// Keeps two bookings from holding the same staff member's time
+const BOOKING_NO_OVERLAP: &str = "booking_staff_no_overlap";
+
+impl From<sqlx::Error> for AppError {
+    fn from(error: sqlx::Error) -> Self {
+        match &error {
+            sqlx::Error::Database(db) => {
+                let constraint = db.constraint().map(str::to_string);
+                match db.code().as_deref() {
+                    Some(UNIQUE_VIOLATION) => AppError::Duplicate { constraint },
+                    Some(FOREIGN_KEY_VIOLATION) => AppError::BrokenReference { constraint },
+                    Some(CHECK_VIOLATION) => AppError::CheckFailed { constraint },
+                    Some(EXCLUSION_VIOLATION) if db.constraint() == Some(BOOKING_NO_OVERLAP) => {
+                        AppError::Conflict(
+                            "The staff member is already booked at that time".to_string(),
+                        )
+                    }
+                    Some(EXCLUSION_VIOLATION) => {
+                        AppError::Conflict("That clashes with an existing record".to_string())
+                    }
+                    _ => AppError::Database(error.to_string()),
+                }
+            }
+            sqlx::Error::Io(_)
+            | sqlx::Error::Tls(_)
+            | sqlx::Error::PoolTimedOut
+            | sqlx::Error::PoolClosed => AppError::Offline,
+            _ => AppError::Database(error.to_string()),
+        }
+    }
+}
+
+impl serde::Serialize for AppError {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("AppError", 3)?;
+        state.serialize_field("code", self.code())?;
+        state.serialize_field("message", &self.to_string())?;
+        state.serialize_field("details", &self.details())?;
+        state.end()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+    use sqlx::error::{DatabaseError, ErrorKind};
+    use std::borrow::Cow;
+
+    // A Postgres error as the driver reports it, with just a SQLSTATE and a constraint
+    #[derive(Debug)]
+    struct PgError {
+        code: &'static str,
+        constraint: Option<&'static str>,
+    }
+
+    impl fmt::Display for PgError {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "SQLSTATE {}", self.code)
+        }
+    }
+
+    impl std::error::Error for PgError {}
+
+    impl DatabaseError for PgError {
+        fn message(&self) -> &str {
+            "constraint violated"
+        }
+
+        fn code(&self) -> Option<Cow<'_, str>> {
+            Some(Cow::Borrowed(self.code))
+        }
+
+        fn constraint(&self) -> Option<&str> {
+            self.constraint
+        }
+
+        fn as_error(&self) -> &(dyn std::error::Error + Send + Sync + 'static) {
+            self
+        }
+
+        fn as_error_mut(&mut self) -> &mut (dyn std::error::Error + Send + Sync + 'static) {
+            self
+        }
+
+        fn into_error(self: Box<Self>) -> Box<dyn std::error::Error + Send + Sync + 'static> {
+            self
+        }
+
+        fn kind(&self) -> ErrorKind {
+            ErrorKind::Other
+        }
+    }
+
+    fn from_postgres(code: &'static str, constraint: Option<&'static str>) -> AppError {
+        sqlx::Error::Database(Box::new(PgError { code, constraint })).into()
+    }
+
+    fn serialized(error: AppError) -> Value {
+        serde_json::to_value(error).unwrap()
+    }
+
+    #[test]
+    fn every_variant_serializes_with_its_code() {
+        let errors = [
+            (AppError::NotAuthenticated, "not_authenticated"),
+            (AppError::SessionExpired, "session_expired"),
+            (AppError::SessionLocked, "session_locked"),
+            (AppError::InvalidCredentials, "invalid_credentials"),
+            (
+                AppError::Forbidden {
+                    role: Role::Staff,
+                    permission: Permission::ManageStaff,
+                },
+                "forbidden",
+            ),
+            (
+                AppError::LockedOut {
+                    retry_after_secs: 30,
+                },
+                "locked_out",
+            ),
+            (AppError::Offline, "offline"),
+            (
+                AppError::FeatureDisabled(Feature::PasswordReset),
+                "feature_disabled",
+            ),
+            (
+                AppError::SchemaMismatch(SchemaMismatch {
+                    found: Some(1),
+                    expected: 2,
+                }),
+                "schema_mismatch",
+            ),
+            (AppError::NotFound("Booking"), "not_found"),
+            (AppError::Invalid("Bad input".to_string()), "invalid"),
+            (AppError::Conflict("Taken".to_string()), "conflict"),
+            (AppError::Duplicate { constraint: None }, "duplicate"),
+            (
+                AppError::BrokenReference { constraint: None },
+                "broken_reference",
+            ),
+            (AppError::CheckFailed { constraint: None }, "check_failed"),
+            (AppError::Database("boom".to_string()), "database"),
+            (AppError::Message("Something".to_string()), "error"),
+        ];
+
+        for (error, code) in errors {
+            assert_eq!(error.code(), code);
+            let message = error.to_string();
+            let value = serialized(error);
+            assert_eq!(value["code"], code);
+            assert_eq!(value["message"], message.as_str());
+        }
+    }
+
+    #[test]
+    fn serialized_errors_carry_their_details() {
+        assert_eq!(
+            serialized(AppError::Forbidden {
+                role: Role::Staff,
+                permission: Permission::ManageStaff,
+            }),
+            json!({
+                "code": "forbidden",
+                "message": "The staff role is not allowed to manage staff",
+                "details": null,
+            })
+        );
+        assert_eq!(
+            serialized(AppError::Duplicate {
+                constraint: Some("customer_phone_key".to_string()),
+            })["details"],
+            "customer_phone_key"
+        );
+    }
+
+    #[test]
+    fn constraint_violations_map_by_sqlstate() {
+        assert!(matches!(
+            from_postgres("23505", Some("customer_phone_key")),
+            AppError::Duplicate { constraint: Some(c) } if c == "customer_phone_key"
+        ));
+        assert!(matches!(
+            from_postgres("23503", Some("booking_staff_id_fkey")),
+            AppError::BrokenReference { constraint: Some(c) } if c == "booking_staff_id_fkey"
+        ));
+        assert!(matches!(
+            from_postgres("23514", Some("booking_check")),
+            AppError::CheckFailed { constraint: Some(c) } if c == "booking_check"
+        ));
+        assert!(matches!(
+            from_postgres("42P01", None),
+            AppError::Database(_)
+        ));
+    }
+
+    #[test]
+    fn only_the_booking_overlap_constraint_reports_a_booked_staff_member() {
+        assert_eq!(
+            from_postgres("23P01", Some(BOOKING_NO_OVERLAP)).to_string(),
+            "The staff member is already booked at that time"
+        );
+
+        let other = from_postgres("23P01", Some("room_no_overlap"));
+        assert!(matches!(other, AppError::Conflict(_)));
+        assert_eq!(other.to_string(), "That clashes with an existing record");
+    }
+
+    #[test]
+    fn connection_failures_are_offline() {
+        let errors = [
+            sqlx::Error::Io(std::io::Error::from(std::io::ErrorKind::ConnectionRefused)),
+            sqlx::Error::Tls("handshake failed".into()),
+            sqlx::Error::PoolTimedOut,
+            sqlx::Error::PoolClosed,
+        ];
+        for error in errors {
+            assert!(matches!(AppError::from(error), AppError::Offline));
+        }
+    }
+}
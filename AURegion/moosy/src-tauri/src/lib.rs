@@ -1,23 +1,125 @@
-use futures::future::try_join_all;
+use chrono::{DateTime, FixedOffset, NaiveDate, Utc};
 use serde::Serialize;
 use serde_json::{json, Value};
-use sha2::{Digest, Sha256};
-use sqlx::{FromRow, PgPool};
-use std::{collections::HashMap, sync::OnceLock};
-use tauri::{http::header::PROXY_AUTHENTICATE, Emitter, Manager, State};
+use sqlx::{postgres::PgConnectOptions, FromRow, PgPool};
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        RwLock,
+    },
+};
+use tauri::{Emitter, Manager, State};
+
+mod account;
+mod config;
+mod connection;
+mod crypto;
+mod delta;
+mod error;
+mod lock;
+mod lockout;
+mod logging;
+mod mfa;
+mod mirror;
+mod notifier;
+mod onboarding;
+mod operator;
+mod outbox;
+mod password;
+mod permissions;
+mod realtime;
+pub mod repo;
+mod schedule;
+mod schema;
+mod status;
+mod totp;
+mod vault;
+
+use config::{AppConfig, BookingConfig, Config};
+use connection::Connection;
+use delta::Delta;
+use error::AppError;
+use lock::{IdleLock, LockSettings};
+use logging::Span;
+use mfa::{LoginResult, MfaChallenges};
+use notifier::Notifications;
+use mirror::Mirror;
+use operator::Operator;
+use outbox::Mutation;
+use vault::{Opened, Vault};
+use password::Verification;
+use permissions::{Permission, Role};
+use schedule::ScheduleConflict;
+use status::{BookingStatus, Statuses};
+use repo::{
+    BookingRepo, CampaignRepo, CompanyRepo, CustomerRepo, NewBooking, PaymentRepo, ScheduleRepo,
+    StaffMember,
+};
 
 // Define the authentication data structure that matches the TypeScript interface
 #[derive(FromRow, serde::Serialize, serde::Deserialize, Clone, Debug)]
 pub struct AuthData {
-    // Never serialized.
-    // #[serde(skip_serializing)]
-    pub username: String,
-    // Never serialized.
-    // #[serde(skip_serializing)]
-    pub password: String,
     pub roles: Roles,
     pub company: Company,
     pub bookings: Option<Vec<Booking>>,
+    // Every company the signed-in person can switch to, including the active one
+    #[serde(default)]
+    pub companies: Vec<CompanyMembership>,
+}
+
+#[derive(FromRow, serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct CompanyMembership {
+    pub id: String,
+    pub name: String,
+    pub role: Role,
+}
+
+// A session minted through `public.manage_user_token`. This is what gets persisted in
+// auth.json: the token and its expiry plus the cached company state, never the password hash.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct Session {
+    pub token: String,
+    pub expires_at: DateTime<Utc>,
+    // The signed-in person and their role in the loaded company
+    pub person_id: String,
+    pub role: Role,
+    // Who is working the till, set by `switch_operator`. None means the signed-in person.
+    #[serde(default)]
+    pub operator: Option<Operator>,
+    // Argon2 hash of the signed-in person's password, so a locked terminal can be unlocked
+    // while the database is unreachable. Hashed separately from the stored password hash,
+    // which `issue_token` accepts as a credential. Sessions saved before it existed get one
+    // on their next online unlock.
+    #[serde(default)]
+    pub unlock_verifier: Option<String>,
+    pub data: AuthData,
+}
+
+impl Session {
+    pub fn is_expired(&self) -> bool {
+        self.expires_at <= Utc::now()
+    }
+
+    pub fn acting_person_id(&self) -> &str {
+        self.operator
+            .as_ref()
+            .map_or(&self.person_id, |operator| &operator.person_id)
+    }
+
+    // Permissions follow the acting operator, not whoever signed the terminal in
+    pub fn acting_role(&self) -> Role {
+        self.operator.as_ref().map_or(self.role, |operator| operator.role)
+    }
+}
+
+// Response of `public.manage_user_token`
+#[derive(serde::Deserialize, Debug)]
+struct TokenGrant {
+    success: bool,
+    token: Option<String>,
+    expires_at: Option<DateTime<Utc>>,
+    message: Option<String>,
 }
 
 #[derive(FromRow, serde::Serialize, serde::Deserialize, Clone, Debug)]
@@ -214,75 +316,374 @@ pub struct Status {
     pub created_at: String,
 }
 
-// Define a static DATABASE_URL that will be initialized once
-static DATABASE_URL: OnceLock<String> = OnceLock::new();
-
-// Helper function to get or initialize the DATABASE_URL
-fn get_database_url() -> &'static str {
-    DATABASE_URL
-        .get_or_init(|| dotenv::var("DATABASE_URL").unwrap_or_else(|_| "post".to_string()))
-}
-
 #[tauri::command]
 async fn login(
     pool: State<'_, PgPool>,
     username: String,
     password: String,
+    challenges: State<'_, MfaChallenges>,
     app: tauri::AppHandle,
-    refresh: bool,
-) -> Result<AuthData, String> {
-    println!("username: {}", username);
-
-    // Create SHA256 hash of the password
-    let mut hasher = Sha256::new();
-    let password_hash = match refresh {
-        true => password,
-        false => {
-            hasher.update(password.as_bytes());
-            hex::encode(hasher.finalize())
+) -> Result<LoginResult, AppError> {
+    let password_hash = verify_credentials(&pool, &username, &password).await?;
+    let unlock_verifier = password::hash_password(&password)?;
+
+    // Accounts with two-factor enabled finish signing in through `verify_login_mfa`
+    if let Some(result) = mfa::challenge_if_enrolled(
+        &pool,
+        &challenges,
+        &username,
+        &password_hash,
+        &unlock_verifier,
+    )
+    .await?
+    {
+        return Ok(result);
+    }
+
+    let auth_data = sign_in(&pool, &app, &username, &password_hash, unlock_verifier).await?;
+    Span::new("login")
+        .company(&auth_data.company.id)
+        .info(format_args!("Signed in"));
+    Ok(LoginResult::Authenticated(Box::new(auth_data)))
+}
+
+// Start a session for a login whose credentials have already been checked
+async fn sign_in(
+    pool: &PgPool,
+    app: &tauri::AppHandle,
+    username: &str,
+    password_hash: &str,
+    unlock_verifier: String,
+) -> Result<AuthData, AppError> {
+    // Mint (or rotate) the session token for this login
+    let (token, expires_at) = issue_token(pool, username, password_hash).await?;
+
+    sqlx::query("UPDATE public.login SET last_login = NOW() WHERE username = $1")
+        .bind(username)
+        .execute(pool)
+        .await?;
+
+    // Sign in to the company where the person holds their most privileged role
+    let (person_id, role, auth_data) = load_session_data(pool, &token, None).await?;
+
+    // Save the session after successful login
+    save_auth(
+        app,
+        Session {
+            token,
+            expires_at,
+            person_id,
+            role,
+            operator: None,
+            unlock_verifier: Some(unlock_verifier),
+            data: auth_data.clone(),
+        },
+    )?;
+
+    // A full login also clears a lock left by the previous operator
+    app.state::<AuthState>().unlock();
+    app.state::<IdleLock>().touch();
+
+    // Return the result
+    Ok(auth_data)
+}
+
+#[derive(FromRow)]
+struct LoginRecord {
+    id: sqlx::types::Uuid,
+    password_hash: String,
+    #[sqlx(flatten)]
+    attempts: lockout::LoginAttempts,
+}
+
+// Check `password` against the stored hash for `username` and return the hash now on record.
+// Legacy SHA-256 hashes are upgraded to Argon2id the first time the owner logs in successfully.
+// Failures count towards a temporary lockout of the username.
+async fn verify_credentials(
+    pool: &PgPool,
+    username: &str,
+    password: &str,
+) -> Result<String, AppError> {
+    let login: Option<LoginRecord> = sqlx::query_as(
+        "SELECT id, password_hash, failed_attempts, locked_until
+         FROM public.login WHERE username = $1",
+    )
+    .bind(username)
+    .fetch_optional(pool)
+    .await?;
+
+    let LoginRecord {
+        id: login_id,
+        password_hash: stored_hash,
+        attempts,
+    } = match login {
+        Some(login) => login,
+        None => {
+            // Take as long as a wrong password would
+            password::verify_unknown_user(password);
+            return Err(AppError::InvalidCredentials);
         }
     };
 
-    println!(
-        "Attempting login with username: {} and password hash: {}",
-        username, password_hash
-    );
+    attempts.check()?;
 
-    // send request to edge function get token
+    let verification = password::verify_password(password, &stored_hash)?;
+    if verification == Verification::Invalid {
+        log::warn!(login_id = login_id.to_string(); "Failed sign-in attempt");
+        lockout::record_failure(pool, login_id).await?;
+        return Err(AppError::InvalidCredentials);
+    }
+    if attempts.failed_attempts > 0 {
+        lockout::record_success(pool, login_id).await?;
+    }
 
-    // Get the result as JSON Value instead of trying to directly map to AuthData
-    let result: Option<Value> =
-        sqlx::query_scalar("SELECT public.get_company_details_by_owner($1, $2)")
-            .bind(&username)
-            .bind(&password_hash)
-            .fetch_one(&*pool)
-            .await
-            .map_err(|e| {
-                println!("Database error: {}", e.to_string());
-                format!("Database error: {}", e.to_string())
-            })?;
+    if verification == Verification::ValidNeedsRehash {
+        let new_hash = password::hash_password(password)?;
+        sqlx::query("UPDATE public.login SET password_hash = $1, updated_at = NOW() WHERE id = $2")
+            .bind(&new_hash)
+            .bind(login_id)
+            .execute(pool)
+            .await?;
+        return Ok(new_hash);
+    }
 
-    // Check if we got a NULL result or no result at all
-    if result.is_none() {
-        println!("Invalid username or password");
-        return Err("Invalid username or password".to_string());
+    Ok(stored_hash)
+}
+
+// Create or rotate the session token for an already verified login
+async fn issue_token(
+    pool: &PgPool,
+    username: &str,
+    password_hash: &str,
+) -> Result<(String, DateTime<Utc>), AppError> {
+    let grant: Value = sqlx::query_scalar("SELECT public.manage_user_token($1, $2)")
+        .bind(username)
+        .bind(password_hash)
+        .fetch_one(pool)
+        .await?;
+
+    let grant: TokenGrant = serde_json::from_value(grant)
+        .map_err(|e| format!("Failed to parse session token: {}", e))?;
+
+    match (grant.success, grant.token, grant.expires_at) {
+        (true, Some(token), Some(expires_at)) => Ok((token, expires_at)),
+        _ => Err(AppError::Message(
+            grant
+                .message
+                .unwrap_or_else(|| "Failed to create session".to_string()),
+        )),
     }
+}
 
-    // Deserialize the JSON value into AuthData
-    let auth_data: AuthData = serde_json::from_value(result.unwrap()).map_err(|e| {
-        println!("Failed to parse authentication data: {}", e);
-        format!("Failed to parse authentication data: {}", e)
-    })?;
+// The username behind a live session token, or None once it has been revoked or has expired
+async fn session_username(pool: &PgPool, token: &str) -> Result<Option<String>, AppError> {
+    Ok(sqlx::query_scalar(
+        "SELECT l.username FROM public.tokens t
+         JOIN public.login l ON l.id = t.login_id
+         WHERE t.token = $1 AND t.purpose = 'session'
+         AND t.is_active = TRUE AND t.expires_at > NOW()",
+    )
+    .bind(token)
+    .fetch_optional(pool)
+    .await?)
+}
 
-    // Save the authentication info after successful login
-    let _ = save_auth(app, auth_data.clone())?;
+#[derive(FromRow)]
+struct MembershipRow {
+    person_id: sqlx::types::Uuid,
+    company_id: Option<sqlx::types::Uuid>,
+    company_name: Option<String>,
+    role_name: Option<String>,
+}
+
+// The person behind a session token and the companies they can sign in to. Anyone with an
+// owner, admin or staff role in a company can work in it. Sorted by role, then by name.
+async fn resolve_memberships(
+    pool: &PgPool,
+    token: &str,
+) -> Result<(String, Vec<CompanyMembership>), AppError> {
+    let rows: Vec<MembershipRow> = sqlx::query_as(
+        "SELECT l.person_id, c.id AS company_id, c.name AS company_name, r.role_name
+         FROM public.tokens t
+         JOIN public.login l ON l.id = t.login_id
+         LEFT JOIN public.role r ON r.person_id = l.person_id
+         LEFT JOIN public.companies c ON c.id = r.company_id
+         WHERE t.token = $1 AND t.purpose = 'session'
+         AND t.is_active = TRUE AND t.expires_at > NOW()",
+    )
+    .bind(token)
+    .fetch_all(pool)
+    .await?;
+
+    // No rows at all means the token itself is no longer valid
+    let person_id = match rows.first() {
+        Some(row) => row.person_id.to_string(),
+        None => return Err(AppError::SessionExpired),
+    };
+
+    let mut companies: Vec<CompanyMembership> = Vec::new();
+    for row in rows {
+        let (Some(company_id), Some(name), Some(role)) = (
+            row.company_id,
+            row.company_name,
+            row.role_name.as_deref().and_then(Role::from_role_name),
+        ) else {
+            continue;
+        };
+        let id = company_id.to_string();
+        match companies.iter_mut().find(|company| company.id == id) {
+            Some(company) => company.role = company.role.min(role),
+            None => companies.push(CompanyMembership { id, name, role }),
+        }
+    }
+
+    if companies.is_empty() {
+        return Err(AppError::Message(
+            "This account does not have access to any company".to_string(),
+        ));
+    }
+    companies.sort_by(|a, b| a.role.cmp(&b.role).then_with(|| a.name.cmp(&b.name)));
+
+    Ok((person_id, companies))
+}
+
+// Resolve the person, their role and the company state for `company_id`, or for their
+// first company when none is given
+async fn load_session_data(
+    pool: &PgPool,
+    token: &str,
+    company_id: Option<&str>,
+) -> Result<(String, Role, AuthData), AppError> {
+    let (person_id, companies) = resolve_memberships(pool, token).await?;
+
+    let active = match company_id {
+        Some(company_id) => companies
+            .iter()
+            .find(|company| company.id == company_id)
+            .ok_or(AppError::NotFound("Company"))?,
+        None => &companies[0],
+    };
+    let role = active.role;
+
+    let mut auth_data = load_company_details(pool, token, &active.id).await?;
+    auth_data.companies = companies;
+
+    Ok((person_id, role, auth_data))
+}
+
+// Load the company state for a session token. A token that was revoked or has lapsed
+// gets nothing back.
+pub async fn load_company_details<R: CompanyRepo>(
+    repo: &R,
+    token: &str,
+    company_id: &str,
+) -> Result<AuthData, AppError> {
+    repo.company_details(token, company_id)
+        .await?
+        .ok_or(AppError::SessionExpired)
+}
+
+// Reload the company state with the session token and persist it. An expired or revoked
+// session removes the local auth file so it can't be replayed.
+async fn refresh_session(
+    pool: &PgPool,
+    app: &tauri::AppHandle,
+    session: Session,
+) -> Result<AuthData, AppError> {
+    if session.is_expired() {
+        clear_auth(app)?;
+        return Err(AppError::SessionExpired);
+    }
+
+    // A locked-out account can't keep pulling data through an existing token either
+    lockout::check_token(pool, &session.token).await?;
+
+    let (_, role, auth_data) =
+        match load_session_data(pool, &session.token, Some(&session.data.company.id)).await {
+            Ok(loaded) => loaded,
+            Err(AppError::SessionExpired) => {
+                clear_auth(app)?;
+                return Err(AppError::SessionExpired);
+            }
+            Err(e) => return Err(e),
+        };
+
+    // The role is re-read too, so a demotion takes effect on the next refresh
+    save_auth(
+        app,
+        Session {
+            role,
+            data: auth_data.clone(),
+            ..session
+        },
+    )?;
 
-    // Return the result
     Ok(auth_data)
 }
 
-#[tauri::command]
-fn save_auth(app: tauri::AppHandle, data: AuthData) -> Result<(), String> {
+// Re-run the startup token check and reload the company state whenever the database comes
+// back, so a session revoked while offline is dropped and the cache catches up. Queued
+// offline changes are replayed on the way while the token is still good.
+fn refresh_after_reconnect(app: &tauri::AppHandle) {
+    let session = match app.state::<AuthState>().get() {
+        Some(session) => session,
+        None => return,
+    };
+
+    let pool = app.state::<PgPool>();
+
+    // Changes made offline go first, so the refreshed state already includes them
+    if let Ok(Some(_)) = tauri::async_runtime::block_on(session_username(&pool, &session.token)) {
+        let companies = outbox::companies(&session);
+        if let Err(e) = tauri::async_runtime::block_on(outbox::replay(app, &companies)) {
+            log::error!("Error replaying offline changes: {}", e);
+        }
+    }
+
+    if let Err(e) = tauri::async_runtime::block_on(refresh_session(&pool, app, session)) {
+        log::error!("Error refreshing session after reconnect: {}", e);
+    }
+}
+
+// Background thread that keeps the cached company state fresh while a session is open
+fn spawn_state_refresher(app: tauri::AppHandle) {
+    std::thread::spawn(move || {
+        let mut last_refresh = std::time::Instant::now();
+        loop {
+            std::thread::sleep(std::time::Duration::from_secs(1));
+
+            let interval = app.state::<Config>().get().refresh.state_refresh_secs;
+            if interval == 0 || last_refresh.elapsed().as_secs() < interval {
+                continue;
+            }
+            last_refresh = std::time::Instant::now();
+
+            let state = app.state::<AuthState>();
+            let session = match state.get() {
+                Some(session) if !state.is_locked() && app.state::<Connection>().is_online() => {
+                    session
+                }
+                _ => continue,
+            };
+
+            let pool = app.state::<PgPool>();
+            if let Err(e) = tauri::async_runtime::block_on(refresh_session(&pool, &app, session)) {
+                log::warn!("Background state refresh failed: {}", e);
+            }
+        }
+    });
+}
+
+fn auth_file(app: &tauri::AppHandle) -> Result<std::path::PathBuf, AppError> {
+    Ok(app
+        .path()
+        .app_data_dir()
+        .map_err(|e| e.to_string())?
+        .join("auth.json"))
+}
+
+// Write the session to auth.json, encrypted with the device vault key
+fn write_session_file(app: &tauri::AppHandle, session: &Session) -> Result<(), AppError> {
     let app_data_dir = app.path().app_data_dir().map_err(|e| e.to_string())?;
 
     // Create the directory if it doesn't exist
@@ -291,16 +692,21 @@ fn save_auth(app: tauri::AppHandle, data: AuthData) -> Result<(), String> {
 
     let path = app_data_dir.join("auth.json");
 
-    // Serialize the AuthData struct to a formatted JSON string
-    let formatted_json = serde_json::to_string_pretty(&data)
-        .map_err(|e| format!("Failed to serialize auth data: {}", e))?;
+    // The cache holds customer details and the session token, so it never hits disk in the clear
+    let sealed = app.state::<Vault>().seal(session)?;
+
+    std::fs::write(path, sealed).map_err(|e| format!("Failed to write auth file: {}", e))?;
+    Ok(())
+}
+
+fn save_auth(app: &tauri::AppHandle, session: Session) -> Result<(), AppError> {
+    write_session_file(app, &session)?;
 
-    // Write the formatted JSON to the file
-    let _ = std::fs::write(path, formatted_json)
-        .map_err(|e| format!("Failed to write auth file: {}", e))?;
+    let data = session.data.clone();
+    mirror::store_in_background(app, &data);
 
-    // Initialize state with existing auth data
-    app.manage(AuthState(Some(data.clone())));
+    // Swap the new session into the shared auth state
+    app.state::<AuthState>().set(session);
 
     // Emit an event to notify the frontend
     app.emit("state-updated", data)
@@ -309,28 +715,57 @@ fn save_auth(app: tauri::AppHandle, data: AuthData) -> Result<(), String> {
     Ok(())
 }
 
-#[tauri::command]
-fn read_auth(app: tauri::AppHandle) -> Result<AuthData, String> {
-    let path = app
-        .path()
-        .app_data_dir()
-        .map_err(|e| e.to_string())?
-        .join("auth.json");
+// Forget the current session: remove the auth file and clear the in-memory state
+fn clear_auth(app: &tauri::AppHandle) -> Result<(), AppError> {
+    let path = auth_file(app)?;
+    if path.exists() {
+        std::fs::remove_file(path).map_err(|e| format!("Failed to remove auth file: {}", e))?;
+    }
+
+    app.state::<AuthState>().clear();
+    mirror::clear_in_background(app);
+
+    app.emit("logged-out", ())
+        .map_err(|e| format!("Failed to emit event: {}", e))?;
+
+    Ok(())
+}
+
+fn read_session(app: &tauri::AppHandle) -> Result<Session, AppError> {
+    let path = auth_file(app)?;
 
     // Check if the file exists
     if !path.exists() {
-        return Err("No authentication data found".to_string());
+        return Err(AppError::NotAuthenticated);
     }
 
     // Read the file content
-    let file_content = std::fs::read_to_string(path)
+    let file_content = std::fs::read_to_string(&path)
         .map_err(|e: std::io::Error| format!("Failed to read auth file: {}", e))?;
 
-    // Parse the JSON string into AuthData struct
-    let auth_data: AuthData = serde_json::from_str(&file_content)
-        .map_err(|e| format!("Failed to parse auth data: {}", e))?;
+    match app.state::<Vault>().open(&file_content) {
+        Ok(Opened::Sealed(session)) => Ok(session),
+        // Plaintext files from before encryption are sealed on first read
+        Ok(Opened::Plaintext(session)) => {
+            write_session_file(app, &session)?;
+            Ok(session)
+        }
+        // A tampered or unreadable file is discarded so it can't be retried
+        Err(e) => {
+            let _ = std::fs::remove_file(&path);
+            Err(e)
+        }
+    }
+}
+
+#[tauri::command]
+fn read_auth(app: tauri::AppHandle) -> Result<AuthData, AppError> {
+    // The cached state stays behind the lock screen like everything else
+    if app.state::<AuthState>().is_locked() {
+        return Err(AppError::SessionLocked);
+    }
 
-    Ok(auth_data)
+    Ok(read_session(&app)?.data)
 }
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
@@ -339,97 +774,106 @@ pub fn run() {
         .setup(|app| {
             // Initialize notification plugin
             // app.handle().plugin(tauri_plugin_notification::init())?;
+            // Broken settings don't stop the app. It starts with the defaults and the
+            // settings screen shows what was wrong.
+            let (config, config_error) = match AppConfig::load(app.handle()) {
+                Ok(config) => (config, None),
+                Err(e) => (AppConfig::fallback(app.handle()), Some(e)),
+            };
+            logging::init(app.handle(), &config.logging)?;
+            if let Some(e) = &config_error {
+                log::warn!("{}, starting with the default settings", e);
+            }
+            let database_url = config.secrets.database_url.clone().unwrap_or_default();
+            // A missing or malformed URL leaves the app offline rather than failing to start
+            let connect_options = database_url
+                .parse::<PgConnectOptions>()
+                .unwrap_or_else(|e| {
+                    log::warn!("Invalid database URL: {}", e);
+                    PgConnectOptions::new()
+                });
+            // Connections are opened on first use, so an unreachable database doesn't stop
+            // the app from starting. The pool needs the async runtime around it to be built.
             let pool = tauri::async_runtime::block_on(async {
                 sqlx::postgres::PgPoolOptions::new()
-                    .max_connections(2)
-                    .connect("s")
-                    .await
-                    .expect("Failed to create pool")
+                    .max_connections(config.database.max_connections)
+                    .min_connections(config.database.min_connections)
+                    .acquire_timeout(config.acquire_timeout())
+                    .idle_timeout(config.idle_timeout())
+                    .connect_lazy_with(connect_options)
             });
 
-            app.manage(pool.clone());
-
-            // Read authentication data and store it in app state for later use
+            app.manage(pool);
+            app.manage(Connection::default());
+            app.manage(Vault::load(
+                app.handle(),
+                config.secrets.auth_cache_passphrase.as_deref(),
+            )?);
+            app.manage(tauri::async_runtime::block_on(Mirror::open(app.handle()))?);
+            app.manage(Config::new(config, config_error));
+            app.manage(AuthState::default());
+            app.manage(Statuses::default());
+            app.manage(MfaChallenges::default());
+            app.manage(IdleLock::new(LockSettings::load(app.handle())));
+            app.manage(Notifications::new(app.handle())?);
+
+            // Restore the persisted session straight away so the cached state is usable
+            // offline. Its token is checked once the database is reachable.
             let app_handle: &tauri::AppHandle = app.handle();
-            match read_auth(app_handle.clone()) {
-                Ok(auth_data) => {
-                    let _ = tauri::async_runtime::block_on(async {
-                        // Get the result as JSON Value instead of trying to directly map to AuthData
-                        let result: Option<Value> = sqlx::query_scalar(
-                            "SELECT public.get_company_details_by_owner($1, $2)",
-                        )
-                        .bind(&auth_data.username)
-                        .bind(&auth_data.password)
-                        .fetch_one(&pool)
-                        .await
-                        .map_err(|e| {
-                            println!("Database error when setup: {}", e.to_string());
-                            format!("Database error when setup: {}", e.to_string())
-                        })?;
-
-                        // Check if we got a NULL result or no result at all
-                        if result.is_none() {
-                            println!("Invalid username or password when setup");
-                            return Err("Invalid username or password when setup".to_string());
-                        }
-
-                        // Deserialize the JSON value into AuthData
-                        let new_auth_data: AuthData = serde_json::from_value(result.unwrap())
-                            .map_err(|e| {
-                                println!("Failed to parse authentication data when setup: {}", e);
-                                format!("Failed to parse authentication data when setup: {}", e)
-                            })?;
-
-                        let app_data_dir = app.path().app_data_dir().map_err(|e| e.to_string())?;
-
-                        // Create the directory if it doesn't exist
-                        std::fs::create_dir_all(&app_data_dir).map_err(|e| {
-                            format!("Failed to create app data directory when setup: {}", e)
-                        })?;
-
-                        let path = app_data_dir.join("auth.json");
-
-                        // Serialize the AuthData struct to a formatted JSON string
-                        let formatted_json =
-                            serde_json::to_string_pretty(&new_auth_data).map_err(|e| {
-                                format!("Failed to serialize auth data when setup: {}", e)
-                            })?;
-
-                        // Write the formatted JSON to the file
-                        let _ = std::fs::write(path, formatted_json)
-                            .map_err(|e| format!("Failed to write auth file when setup: {}", e))?;
-
-                        // Initialize state with existing auth data
-                        app.manage(AuthState(Some(new_auth_data.clone())));
-
-                        app.emit("state-updated", new_auth_data)
-                            .map_err(|e| format!("Failed to emit event: {}", e))?;
-                        Ok(())
-                    })?;
-                }
-                Err(e) => {
-                    eprintln!("Error reading auth data when setup: {}", e);
-                    app.manage(AuthState(None));
+            match read_session(app_handle) {
+                Ok(session) if session.is_expired() => clear_auth(app_handle)?,
+                Ok(session) => {
+                    app.state::<AuthState>().set(session);
+                    // Shared terminals come back locked rather than silently signed in
+                    if app.state::<IdleLock>().settings().lock_on_startup {
+                        lock::lock(app_handle)?;
+                    }
                 }
-            };
-
-            if cfg!(debug_assertions) {
-                app.handle().plugin(
-                    tauri_plugin_log::Builder::default()
-                        .level(log::LevelFilter::Info)
-                        .build(),
-                )?;
+                Err(e) => log::info!("No session restored at startup: {}", e),
             }
 
+            connection::spawn_monitor(app_handle.clone(), refresh_after_reconnect);
+            lock::spawn_monitor(app_handle.clone());
+            spawn_state_refresher(app_handle.clone());
+            realtime::spawn_listener(app_handle.clone(), database_url);
+
             Ok(())
         })
-        .invoke_handler(tauri::generate_handler![
+        .invoke_handler(connection::guarded(tauri::generate_handler![
             login,
-            save_auth,
+            mfa::verify_login_mfa,
+            mfa::get_mfa_status,
+            mfa::begin_mfa_enrolment,
+            mfa::confirm_mfa_enrolment,
+            mfa::disable_mfa,
+            onboarding::register_business,
             read_auth,
             get_auth_state,
             fetch_latest_state,
+            logout,
+            switch_company,
+            account::change_password,
+            account::request_password_reset,
+            account::reset_password,
+            lock::record_activity,
+            lock::lock_session,
+            lock::unlock_session,
+            lock::get_lock_settings,
+            lock::set_lock_settings,
+            operator::set_operator_pin,
+            operator::switch_operator,
+            operator::get_operator,
+            config::get_settings,
+            config::update_settings,
+            connection::get_connection_status,
+            logging::export_diagnostics,
+            outbox::get_sync_report,
+            outbox::dismiss_sync_issue,
+            outbox::sync_now,
+            create_booking,
+            find_available_slots,
             cancel_booking,
+            update_booking_status,
             reschedule_booking,
             checkout_booking,
             add_customer,
@@ -437,127 +881,697 @@ pub fn run() {
             delete_customer,
             checkout_walkin,
             update_campaign
-        ])
+        ]))
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
 }
 
-// Add this struct to store auth state in the app
-#[derive(Clone)]
-struct AuthState(Option<AuthData>);
+// Auth state shared with every command. Tauri only registers managed state once, so the
+// session is swapped in place behind a lock instead of re-managing the whole struct.
+#[derive(Default)]
+pub struct AuthState {
+    session: RwLock<Option<Session>>,
+    // Set by the idle lock; the session is kept but commands are refused until unlocked
+    locked: AtomicBool,
+}
+
+impl AuthState {
+    pub fn get(&self) -> Option<Session> {
+        self.session.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    // The current session, or an error when nobody is signed in or the terminal is locked
+    pub fn current(&self) -> Result<Session, AppError> {
+        if self.is_locked() {
+            return Err(AppError::SessionLocked);
+        }
+        self.get().ok_or(AppError::NotAuthenticated)
+    }
+
+    // The current session, provided the acting operator's role is allowed to perform `permission`
+    pub fn authorize(&self, permission: Permission) -> Result<Session, AppError> {
+        let session = self.current()?;
+        if !session.acting_role().can(permission) {
+            return Err(AppError::Forbidden {
+                role: session.acting_role(),
+                permission,
+            });
+        }
+        Ok(session)
+    }
+
+    pub fn set(&self, session: Session) {
+        *self.session.write().unwrap_or_else(|e| e.into_inner()) = Some(session);
+    }
+
+    // Change the session in place, under the lock so concurrent patches aren't lost. Returns
+    // the updated session when `change` returns true.
+    pub fn update(&self, change: impl FnOnce(&mut Session) -> bool) -> Option<Session> {
+        let mut session = self.session.write().unwrap_or_else(|e| e.into_inner());
+        let session = session.as_mut()?;
+        change(session).then(|| session.clone())
+    }
+
+    pub fn clear(&self) {
+        *self.session.write().unwrap_or_else(|e| e.into_inner()) = None;
+        self.unlock();
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.locked.load(Ordering::SeqCst)
+    }
+
+    pub fn lock(&self) {
+        self.locked.store(true, Ordering::SeqCst);
+    }
+
+    pub fn unlock(&self) {
+        self.locked.store(false, Ordering::SeqCst);
+    }
+}
 
 // Add command to retrieve auth state from frontend
 #[tauri::command]
-fn get_auth_state(state: State<'_, AuthState>) -> Result<AuthData, String> {
-    match state.0.clone() {
-        Some(auth_data) => Ok(auth_data),
-        None => Err("Not authenticated".to_string()),
+fn get_auth_state(state: State<'_, AuthState>) -> Result<AuthData, AppError> {
+    Ok(state.authorize(Permission::ViewState)?.data)
+}
+
+// Revoke the session token and wipe the local session. Local logout always goes ahead,
+// even when the database can't be reached to revoke the token.
+#[tauri::command]
+async fn logout(
+    pool: State<'_, PgPool>,
+    state: State<'_, AuthState>,
+    app: tauri::AppHandle,
+) -> Result<(), AppError> {
+    if let Some(session) = state.get() {
+        if let Err(e) = sqlx::query("UPDATE public.tokens SET is_active = FALSE WHERE token = $1")
+            .bind(&session.token)
+            .execute(&*pool)
+            .await
+        {
+            Span::session("logout", &session)
+                .warn(format_args!("Failed to revoke session token: {}", e));
+        }
     }
+
+    clear_auth(&app)
 }
 
+// Make another of the signed-in person's companies the active one. Every command scopes
+// its queries to the active company, and any acting operator is reset to the person.
 #[tauri::command]
-async fn fetch_latest_state(
+async fn switch_company(
+    company_id: String,
     pool: State<'_, PgPool>,
     state: State<'_, AuthState>,
     app: tauri::AppHandle,
-) -> Result<String, String> {
-    // Get the current auth data
-    let auth_data = match &state.0 {
-        Some(data) => data.clone(),
-        None => return Err("Not authenticated".to_string()),
-    };
-    // Reuse login with existing credentials to get fresh state
-    let username = auth_data.username.clone();
-    let password = auth_data.password.clone();
+) -> Result<AuthData, AppError> {
+    let session = state.current()?;
+
+    let (person_id, role, auth_data) =
+        match load_session_data(&pool, &session.token, Some(&company_id)).await {
+            Ok(loaded) => loaded,
+            Err(AppError::SessionExpired) => {
+                clear_auth(&app)?;
+                return Err(AppError::SessionExpired);
+            }
+            Err(e) => return Err(e),
+        };
+
+    save_auth(
+        &app,
+        Session {
+            person_id,
+            role,
+            operator: None,
+            data: auth_data.clone(),
+            ..session
+        },
+    )?;
+
+    Ok(auth_data)
+}
 
-    // Call login function to refresh auth data
-    let _ = login(pool, username, password, app, true).await?;
+#[tauri::command]
+async fn fetch_latest_state(
+    pool: State<'_, PgPool>,
+    state: State<'_, AuthState>,
+    app: tauri::AppHandle,
+) -> Result<String, AppError> {
+    // Get the current session
+    let session = state.authorize(Permission::ViewState)?;
 
-    // // Update the application state
-    // app.manage(AuthState(Some(new_auth_data)));
+    // Reload the company state with the session token
+    refresh_session(&pool, &app, session).await?;
 
     // Return the updated auth data
     Ok("200".to_string())
 }
 
+// A booking made at the desk. The start carries its UTC offset and the end follows from
+// the service duration.
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct BookingInput {
+    pub customer_id: String,
+    pub service_id: String,
+    // None lets `check_and_assign_staff` pick a staff member who is free
+    pub staff_id: Option<String>,
+    pub start_time: String,
+}
+
 #[tauri::command]
-async fn cancel_booking(
-    booking_id: String,
+async fn create_booking(
+    booking: BookingInput,
     pool: State<'_, PgPool>,
     state: State<'_, AuthState>,
     app: tauri::AppHandle,
-) -> Result<String, String> {
-    // Get the current auth data to ensure user is authenticated
-    let _ = match &state.0 {
-        Some(data) => data.clone(),
-        None => return Err("Not authenticated".to_string()),
+) -> Result<Booking, AppError> {
+    // Make sure the signed-in role is allowed to do this
+    let session = state.authorize(Permission::CreateBooking)?;
+    let company_id = &session.data.company.id;
+
+    let booking_id = create_company_booking(&*pool, company_id, &booking, Utc::now()).await?;
+    Span::session("create_booking", &session).info(format_args!("Booking {} created", booking_id));
+
+    // Return the booking the way the company state has it, and patch it into the cache
+    let created = pool
+        .company_booking(company_id, &booking_id.to_string())
+        .await?
+        .ok_or(AppError::NotFound("Booking"))?;
+    let change = Delta::BookingUpserted {
+        booking: Box::new(created.clone()),
     };
+    delta::publish(&app, company_id, vec![change])?;
 
-    // Execute SQL query to update the booking status
-    // Convert the booking_id string to UUID since the database expects UUID type
-    let status_id = "e11e739f-44a8-4b2a-958d-c4d5ad73db88";
+    Ok(created)
+}
 
-    match sqlx::query("UPDATE booking SET status_id = $1::uuid WHERE id = $2::uuid")
-        .bind(status_id)
-        .bind(booking_id.clone())
-        .execute(&*pool)
-        .await
-    {
-        Ok(result) => {
-            if result.rows_affected() == 0 {
-                return Err(format!("No booking found with ID: {}", booking_id));
+// Check the booking against the company's services, customers, team, opening hours and
+// existing bookings, settle the staff member and insert it
+pub async fn create_company_booking<R: BookingRepo + CustomerRepo + ScheduleRepo>(
+    repo: &R,
+    company_id: &str,
+    input: &BookingInput,
+    now: DateTime<Utc>,
+) -> Result<sqlx::types::Uuid, AppError> {
+    let ids = [
+        ("customer", &input.customer_id),
+        ("service", &input.service_id),
+    ];
+    let staff = input.staff_id.iter().map(|id| ("staff", id));
+    for (what, id) in ids.into_iter().chain(staff) {
+        sqlx::types::Uuid::parse_str(id)
+            .map_err(|_| AppError::Invalid(format!("Invalid {} ID", what)))?;
+    }
+
+    let start = schedule::parse_instant(&input.start_time)?;
+    if start < now {
+        return Err(AppError::Invalid(
+            "Bookings can't start in the past".to_string(),
+        ));
+    }
+    let duration = repo
+        .service_duration(company_id, &input.service_id)
+        .await?
+        .ok_or(AppError::NotFound("Service"))?;
+    if duration <= chrono::TimeDelta::zero() {
+        return Err(AppError::Invalid("The service has no duration".to_string()));
+    }
+    let end = start + duration;
+
+    ensure_company_customer(repo, company_id, &input.customer_id).await?;
+
+    let windows = schedule::windows_around(repo, company_id, start, end).await?;
+    if schedule::opening_window(&windows, start, end).is_none() {
+        return Err(AppError::Conflict("That time is outside opening hours".to_string()));
+    }
+
+    let staff_id = match &input.staff_id {
+        Some(staff_id) => {
+            if !repo.is_team_member(company_id, staff_id).await? {
+                return Err(AppError::NotFound("Staff member"));
+            }
+            let conflict =
+                schedule::staff_conflict(repo, company_id, staff_id, start, end).await?;
+            if let Some(booking) = conflict {
+                return Err(AppError::Conflict(format!(
+                    "The staff member is already booked from {} to {}",
+                    schedule::describe(booking.start_time),
+                    schedule::describe(booking.end_time)
+                )));
             }
+            staff_id.clone()
+        }
+        None => repo
+            .available_staff(company_id, start, end)
+            .await?
+            .ok_or_else(|| AppError::Conflict("No staff member is free at that time".to_string()))?
+            .to_string(),
+    };
+
+    let booking = NewBooking {
+        id: outbox::new_id(),
+        customer_id: input.customer_id.clone(),
+        staff_id,
+        service_id: input.service_id.clone(),
+        start_time: start,
+        end_time: end,
+    };
+    // Someone else can take the slot between the checks and the insert
+    if !repo.insert_booking(company_id, &booking).await? {
+        return Err(AppError::Conflict(
+            "That time was just booked, please pick another".to_string(),
+        ));
+    }
 
-            // Fetch the latest state to ensure the app has up-to-date data
-            let _ = fetch_latest_state(pool, state, app).await?;
+    Ok(booking.id)
+}
+
+// Longest stretch of days one availability search covers
+const MAX_SEARCH_DAYS: u32 = 31;
+
+// What `find_available_slots` looks for. Granularity and notice fall back to the `bookings`
+// settings.
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct SlotQuery {
+    pub service_id: String,
+    // None searches every staff member `check_and_assign_staff` picks from
+    pub staff_id: Option<String>,
+    // First local date to search, in the timetable's zone
+    pub from: NaiveDate,
+    pub days: Option<u32>,
+    pub granularity_minutes: Option<u32>,
+    pub min_notice_minutes: Option<u32>,
+}
 
-            Ok(format!("Booking {} successfully cancelled", booking_id))
+#[derive(serde::Serialize, Clone, Debug)]
+pub struct AvailableDay {
+    pub date: NaiveDate,
+    pub timezone: String,
+    pub staff: Vec<StaffSlots>,
+}
+
+#[derive(serde::Serialize, Clone, Debug)]
+pub struct StaffSlots {
+    #[serde(flatten)]
+    pub staff: StaffMember,
+    pub slots: Vec<Slot>,
+}
+
+// Local times with their offset, ready to pass back to `create_booking`
+#[derive(serde::Serialize, Clone, Debug)]
+pub struct Slot {
+    pub start_time: DateTime<FixedOffset>,
+    pub end_time: DateTime<FixedOffset>,
+}
+
+#[tauri::command]
+async fn find_available_slots(
+    query: SlotQuery,
+    pool: State<'_, PgPool>,
+    state: State<'_, AuthState>,
+    config: State<'_, Config>,
+) -> Result<Vec<AvailableDay>, AppError> {
+    let session = state.authorize(Permission::ViewState)?;
+    let defaults = config.get().bookings;
+    let company_id = &session.data.company.id;
+    find_company_slots(&*pool, company_id, &query, &defaults, Utc::now()).await
+}
+
+// Every start time the service fits in, per local day and staff member: inside opening
+// hours, clear of anything booked for them at any company and with enough notice. Days and
+// staff without a free slot are left out.
+pub async fn find_company_slots<R: ScheduleRepo>(
+    repo: &R,
+    company_id: &str,
+    query: &SlotQuery,
+    defaults: &BookingConfig,
+    now: DateTime<Utc>,
+) -> Result<Vec<AvailableDay>, AppError> {
+    sqlx::types::Uuid::parse_str(&query.service_id)
+        .map_err(|_| AppError::Invalid("Invalid service ID".to_string()))?;
+    if let Some(staff_id) = &query.staff_id {
+        sqlx::types::Uuid::parse_str(staff_id)
+            .map_err(|_| AppError::Invalid("Invalid staff ID".to_string()))?;
+    }
+
+    let days = query.days.unwrap_or(7);
+    if days == 0 || days > MAX_SEARCH_DAYS {
+        return Err(AppError::Invalid(format!(
+            "Search between 1 and {} days",
+            MAX_SEARCH_DAYS
+        )));
+    }
+    let settings = BookingConfig {
+        slot_granularity_minutes: query
+            .granularity_minutes
+            .unwrap_or(defaults.slot_granularity_minutes),
+        min_notice_minutes: query
+            .min_notice_minutes
+            .unwrap_or(defaults.min_notice_minutes),
+    };
+    settings.validate()?;
+    let step = chrono::TimeDelta::minutes(settings.slot_granularity_minutes.into());
+    let earliest = now + chrono::TimeDelta::minutes(settings.min_notice_minutes.into());
+
+    let duration = repo
+        .service_duration(company_id, &query.service_id)
+        .await?
+        .ok_or(AppError::NotFound("Service"))?;
+    if duration <= chrono::TimeDelta::zero() {
+        return Err(AppError::Invalid("The service has no duration".to_string()));
+    }
+
+    let staff = repo
+        .bookable_staff(company_id, query.staff_id.as_deref())
+        .await?;
+    if query.staff_id.is_some() && staff.is_empty() {
+        return Err(AppError::NotFound("Staff member"));
+    }
+
+    let to = query.from + chrono::Days::new((days - 1).into());
+    let windows = repo.opening_windows(company_id, query.from, to).await?;
+    let (first_open, last_close) = match windows.first() {
+        Some(window) => (
+            window.opens_at,
+            windows.iter().map(|window| window.closes_at).max().unwrap_or(window.closes_at),
+        ),
+        None => return Ok(Vec::new()),
+    };
+
+    // Anything overlapping the search, including bookings that start before the first window
+    // and the staff member's bookings at other companies
+    let mut booked = Vec::with_capacity(staff.len());
+    for member in &staff {
+        booked.push(repo.booked_slots(&member.id, first_open, last_close).await?);
+    }
+
+    let mut available: Vec<AvailableDay> = Vec::new();
+    for window in &windows {
+        for (member, booked) in staff.iter().zip(&booked) {
+            let slots: Vec<Slot> = schedule::free_starts(window, duration, step, earliest, booked)
+                .into_iter()
+                .map(|start| Slot {
+                    start_time: schedule::local_time(window, start),
+                    end_time: schedule::local_time(window, start + duration),
+                })
+                .collect();
+            if slots.is_empty() {
+                continue;
+            }
+
+            // A day can have more than one window, e.g. a split shift
+            let new_day = available
+                .last()
+                .map_or(true, |day| day.date != window.local_date);
+            if new_day {
+                available.push(AvailableDay {
+                    date: window.local_date,
+                    timezone: window.timezone.clone(),
+                    staff: Vec::new(),
+                });
+            }
+            let last = available.len() - 1;
+            let listed = &mut available[last].staff;
+            match listed.iter_mut().find(|entry| entry.staff.id == member.id) {
+                Some(entry) => entry.slots.extend(slots),
+                None => listed.push(StaffSlots {
+                    staff: member.clone(),
+                    slots,
+                }),
+            }
         }
-        Err(e) => Err(format!("Failed to cancel booking: {}", e)),
     }
+
+    Ok(available)
+}
+
+#[tauri::command]
+async fn cancel_booking(
+    booking_id: String,
+    pool: State<'_, PgPool>,
+    state: State<'_, AuthState>,
+    app: tauri::AppHandle,
+) -> Result<String, AppError> {
+    // Make sure the signed-in role is allowed to do this
+    let session = state.authorize(Permission::CancelBooking)?;
+
+    // Offline the cancellation waits in the outbox until the database is back
+    if !app.state::<Connection>().is_online() {
+        let mutation = Mutation::CancelBooking {
+            booking_id: booking_id.clone(),
+        };
+        outbox::queue(&app, session, mutation).await?;
+        return Ok(format!(
+            "Booking {} cancelled, it will sync once back online",
+            booking_id
+        ));
+    }
+
+    let statuses = app.state::<Statuses>();
+    let company_id = &session.data.company.id;
+    if !cancel_company_booking(&*pool, &statuses, company_id, &booking_id).await? {
+        return Err(AppError::NotFound("Booking"));
+    }
+    Span::session("cancel_booking", &session)
+        .info(format_args!("Booking {} cancelled", booking_id));
+
+    // Only the cancelled booking is re-fetched into the cached state
+    let change = delta::booking(&*pool, company_id, &booking_id).await?;
+    delta::publish(&app, company_id, vec![change])?;
+
+    Ok(format!("Booking {} successfully cancelled", booking_id))
 }
 
+// Set a booking of `company_id` to cancelled, false when the company has no such booking.
+// Completed and no-show bookings stay as they are.
+pub async fn cancel_company_booking<R: BookingRepo>(
+    repo: &R,
+    statuses: &Statuses,
+    company_id: &str,
+    booking_id: &str,
+) -> Result<bool, AppError> {
+    status::transition(
+        repo,
+        statuses,
+        company_id,
+        booking_id,
+        BookingStatus::Cancelled,
+    )
+    .await
+}
+
+// Confirm, start, complete or cancel a booking, or mark it a no-show, as far as its current
+// status allows
+#[tauri::command]
+async fn update_booking_status(
+    booking_id: String,
+    status: BookingStatus,
+    pool: State<'_, PgPool>,
+    state: State<'_, AuthState>,
+    app: tauri::AppHandle,
+) -> Result<Booking, AppError> {
+    // Make sure the signed-in role is allowed to do this
+    let session = state.authorize(Permission::UpdateBookingStatus)?;
+    if status == BookingStatus::Cancelled {
+        state.authorize(Permission::CancelBooking)?;
+    }
+    let company_id = &session.data.company.id;
+
+    let statuses = app.state::<Statuses>();
+    if !status::transition(&*pool, &statuses, company_id, &booking_id, status).await? {
+        return Err(AppError::NotFound("Booking"));
+    }
+    Span::session("update_booking_status", &session)
+        .info(format_args!("Booking {} is now {}", booking_id, status));
+
+    let updated = pool
+        .company_booking(company_id, &booking_id)
+        .await?
+        .ok_or(AppError::NotFound("Booking"))?;
+    let change = Delta::BookingUpserted {
+        booking: Box::new(updated.clone()),
+    };
+    delta::publish(&app, company_id, vec![change])?;
+
+    Ok(updated)
+}
+
+#[derive(serde::Serialize, Clone, Debug)]
+#[serde(tag = "outcome", rename_all = "snake_case")]
+pub enum RescheduleOutcome {
+    Rescheduled { booking: Box<Booking> },
+    // Nothing was changed
+    Conflicts { conflicts: Vec<ScheduleConflict> },
+}
+
+// Move a booking. Times carry their UTC offset and the end, when left out, keeps the
+// service's duration.
 #[tauri::command]
 async fn reschedule_booking(
     booking_id: String,
-    new_date: String,
-    end_time: String,
+    start_time: String,
+    end_time: Option<String>,
     pool: State<'_, PgPool>,
     state: State<'_, AuthState>,
     app: tauri::AppHandle,
-) -> Result<String, String> {
-    // Get the current auth data to ensure user is authenticated
-    let _ = match &state.0 {
-        Some(data) => data.clone(),
-        None => return Err("Not authenticated".to_string()),
+) -> Result<RescheduleOutcome, AppError> {
+    // Make sure the signed-in role is allowed to do this
+    let session = state.authorize(Permission::RescheduleBooking)?;
+    let company_id = &session.data.company.id;
+
+    let conflicts = reschedule_company_booking(
+        &*pool,
+        company_id,
+        &booking_id,
+        &start_time,
+        end_time.as_deref(),
+        Utc::now(),
+    )
+    .await?;
+    if !conflicts.is_empty() {
+        return Ok(RescheduleOutcome::Conflicts { conflicts });
+    }
+    Span::session("reschedule_booking", &session)
+        .info(format_args!("Booking {} rescheduled", booking_id));
+
+    let moved = pool
+        .company_booking(company_id, &booking_id)
+        .await?
+        .ok_or(AppError::NotFound("Booking"))?;
+    let change = Delta::BookingUpserted {
+        booking: Box::new(moved.clone()),
     };
+    delta::publish(&app, company_id, vec![change])?;
 
-    // Execute SQL query to update the booking status
-    // Convert the booking_id string to UUID since the database expects UUID type
-    let status_id = "e11e739f-44a8-4b2a-958d-c4d5ad73db88";
+    Ok(RescheduleOutcome::Rescheduled {
+        booking: Box::new(moved),
+    })
+}
 
-    match sqlx::query("UPDATE booking SET start_time = $1::timestamp, end_time = $2::timestamp WHERE id = $3::uuid")
-        .bind(new_date)
-        .bind(end_time)
-        .bind(booking_id.clone())
-        .execute(&*pool)
-        .await
-    {
-        Ok(result) => {
-            if result.rows_affected() == 0 {
-                return Err(format!("No booking found with ID: {}", booking_id));
-            }
+// Check the new times against opening hours and the staff member's other bookings, and move
+// the booking when nothing is in the way. The conflicts found are returned instead, and an
+// empty list means it moved.
+pub async fn reschedule_company_booking<R: BookingRepo + ScheduleRepo>(
+    repo: &R,
+    company_id: &str,
+    booking_id: &str,
+    start_time: &str,
+    end_time: Option<&str>,
+    now: DateTime<Utc>,
+) -> Result<Vec<ScheduleConflict>, AppError> {
+    sqlx::types::Uuid::parse_str(booking_id)
+        .map_err(|_| AppError::Invalid("Invalid booking ID".to_string()))?;
+    let current = repo
+        .booking(company_id, booking_id)
+        .await?
+        .ok_or(AppError::NotFound("Booking"))?;
+    let status = current.status.as_deref().and_then(BookingStatus::from_name);
+    match status {
+        Some(BookingStatus::Pending | BookingStatus::Confirmed) => (),
+        Some(status) => {
+            return Err(AppError::Conflict(format!(
+                "A {} booking can't be rescheduled",
+                status
+            )))
+        }
+        None => {
+            return Err(AppError::Invalid(
+                "The booking has no known status".to_string(),
+            ))
+        }
+    }
 
-            // Fetch the latest state to ensure the app has up-to-date data
-            let _ = fetch_latest_state(pool, state, app).await?;
+    let start = schedule::parse_instant(start_time)?;
+    if start < now {
+        return Err(AppError::Invalid(
+            "Bookings can't be moved into the past".to_string(),
+        ));
+    }
+    let end = match end_time {
+        Some(end_time) => schedule::parse_instant(end_time)?,
+        None => {
+            // A booking whose service is gone keeps its current length
+            let duration = match &current.service_id {
+                Some(service_id) => repo.service_duration(company_id, service_id).await?,
+                None => None,
+            };
+            start + duration.unwrap_or(current.end_time - current.start_time)
+        }
+    };
+    if end <= start {
+        return Err(AppError::Invalid(
+            "The end time has to be after the start time".to_string(),
+        ));
+    }
+
+    let mut conflicts = Vec::new();
+    let windows = schedule::windows_around(repo, company_id, start, end).await?;
+    if schedule::opening_window(&windows, start, end).is_none() {
+        conflicts.push(ScheduleConflict::OutsideOpeningHours {
+            opening_hours: schedule::hours_on(&windows, start),
+        });
+    }
+    if let Some(staff_id) = &current.staff_id {
+        let bookings = repo
+            .overlapping_bookings(company_id, staff_id, start, end, Some(booking_id))
+            .await?;
+        if !bookings.is_empty() {
+            conflicts.push(ScheduleConflict::StaffBooked { bookings });
+        }
+    }
+    if !conflicts.is_empty() {
+        return Ok(conflicts);
+    }
 
-            Ok(format!("Booking {} successfully rescheduled", booking_id))
+    let moved = repo
+        .move_booking(company_id, booking_id, start, end)
+        .await?;
+    if moved {
+        return Ok(Vec::new());
+    }
+    // Either the booking went away or someone took the time since the checks above
+    let bookings = match &current.staff_id {
+        Some(staff_id) => {
+            repo.overlapping_bookings(company_id, staff_id, start, end, Some(booking_id))
+                .await?
         }
-        Err(e) => Err(format!("Failed to reschedule booking: {}", e)),
+        None => Vec::new(),
+    };
+    if bookings.is_empty() {
+        return Err(AppError::NotFound("Booking"));
+    }
+    Ok(vec![ScheduleConflict::StaffBooked { bookings }])
+}
+
+// A payment taken at the till, for a booking or a walk-in
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct Checkout {
+    pub booking_id: Option<String>,
+    pub customer_id: String,
+    pub services_id: Option<Vec<String>>,
+    pub discounts_id: Option<Vec<String>>,
+    pub currency_id: String,
+    pub method: String,
+    pub amount: f64,
+    pub status: String,
+}
+
+impl Checkout {
+    // (linkable id, linkable type) of the booking, services and discounts paid for
+    pub fn links(&self) -> impl Iterator<Item = (&str, &'static str)> {
+        let booking = self.booking_id.iter().map(|id| (id.as_str(), "booking"));
+        let services = self.services_id.iter().flatten();
+        let discounts = self.discounts_id.iter().flatten();
+        booking
+            .chain(services.map(|id| (id.as_str(), "services")))
+            .chain(discounts.map(|id| (id.as_str(), "discounts")))
     }
 }
 
 #[tauri::command]
+#[allow(clippy::too_many_arguments)]
 async fn checkout_booking(
     booking_id: Option<String>,
     customer_id: String,
@@ -570,117 +1584,104 @@ async fn checkout_booking(
     pool: State<'_, PgPool>,
     state: State<'_, AuthState>,
     app: tauri::AppHandle,
-) -> Result<sqlx::types::Uuid, String> {
-    // Get the current auth data to ensure user is authenticated
-    let data = match &state.0 {
-        Some(data) => data.clone(),
-        None => return Err("Not authenticated".to_string()),
+) -> Result<sqlx::types::Uuid, AppError> {
+    // Make sure the signed-in role is allowed to do this
+    let session = state.authorize(Permission::Checkout)?;
+
+    let checkout = Checkout {
+        booking_id,
+        customer_id,
+        services_id,
+        discounts_id,
+        currency_id,
+        method,
+        amount,
+        status,
     };
 
-    let payment_result = sqlx::query_as::<_, (sqlx::types::Uuid,)>(
-        "INSERT INTO public.payments (amount, currency_id, payment_method, person_id, company_id, status) 
-         VALUES ($1, $2::uuid, $3, $4::uuid, $5::uuid, $6)
-         RETURNING id"
+    // The payment id is chosen here so a payment taken offline keeps it once synced
+    let payment_id = outbox::new_id();
+    if !app.state::<Connection>().is_online() {
+        outbox::queue(&app, session, Mutation::Checkout { payment_id, checkout }).await?;
+        return Ok(payment_id);
+    }
+
+    record_payment(
+        &*pool,
+        &app.state::<Statuses>(),
+        &session.data.company.id,
+        session.acting_person_id(), // Whoever is working the till takes the payment
+        payment_id,
+        &checkout,
     )
-    .bind(amount)
-    .bind(currency_id)
-    .bind(method)
-    .bind(customer_id)
-    .bind(data.company.id)  // Use company ID from auth data directly
-    .bind(status)
-    .fetch_one(&*pool)
-    .await
-    .map_err(|e| format!("Failed to process payment: {}", e))?.0;
+    .await?;
+    Span::session("checkout_booking", &session).info(format_args!("Payment {} recorded", payment_id));
+
+    // The paid booking is completed now
+    if let Some(booking_id) = &checkout.booking_id {
+        let company_id = &session.data.company.id;
+        let change = delta::booking(&*pool, company_id, booking_id).await?;
+        delta::publish(&app, company_id, vec![change])?;
+    }
 
-    // update booking status & link booking to payment
-    match booking_id {
-        Some(id) => {
-            let id = id.clone();
-            // Update booking status
-            sqlx::query("UPDATE booking SET status_id = $1::uuid WHERE id = $2::uuid")
-                .bind("0758ec7a-8cf6-4247-923c-cdbdfeb214db") // completed status id
-                .bind(id.clone())
-                .execute(&*pool)
-                .await
-                .map_err(|e| format!("Failed to update booking status: {}", e))?;
-
-            // Link booking to payment
-            let payment_id = payment_result.to_string();
-            let pool = pool.clone();
-            sqlx::query("INSERT INTO public.payment_linkable (payment_id, linkable_id, linkable_type) VALUES ($1::uuid, $2::uuid, $3)")
-                .bind(payment_id)
-                .bind(id)
-                .bind("booking")
-                .execute(&*pool)
-                .await
-                .map_err(|e| format!("Failed to link booking to payment: {}", e))?;
-        }
-        None => (),
-    };
+    Ok(payment_id)
+}
 
-    // link services to payment
-    match services_id {
-        Some(ids) => {
-            let payment_id = payment_result.to_string();
-            let futures = ids.iter().map(|id| {
-                let pool = pool.clone();
-                let payment_id = payment_id.clone();
-                let id = id.clone();
-                async move {
-                    sqlx::query("INSERT INTO public.payment_linkable (payment_id, linkable_id, linkable_type) VALUES ($1::uuid, $2::uuid, $3)")
-                        .bind(payment_id)
-                        .bind(id)
-                        .bind("services")
-                        .execute(&*pool)
-                        .await
-                        .map_err(|e| format!("Failed to link service to payment: {}", e))
-                }
-            });
-            try_join_all(futures).await?;
+// Insert the payment, complete the booking it pays for and link the services and discounts
+pub async fn record_payment<R: BookingRepo + PaymentRepo>(
+    repo: &R,
+    statuses: &Statuses,
+    company_id: &str,
+    operator_id: &str,
+    payment_id: sqlx::types::Uuid,
+    checkout: &Checkout,
+) -> Result<(), AppError> {
+    // A cancelled or no-show booking can't be paid for, nor a completed one again. The
+    // payment, its links and the booking's completion are written together, so a replay cut
+    // short leaves none of them.
+    let completion = match &checkout.booking_id {
+        Some(id) => {
+            let completed = BookingStatus::Completed;
+            let change = status::plan_transition(repo, statuses, company_id, id, completed);
+            let change = change.await?.ok_or(AppError::NotFound("Booking"))?;
+            if change.from_status_id == change.status_id {
+                return Err(AppError::Conflict(
+                    "This booking has already been paid for".to_string(),
+                ));
+            }
+            Some(change)
         }
-        None => (),
+        None => None,
     };
 
-    // link discounts to payment
-    match discounts_id {
-        Some(ids) => {
-            let payment_id = payment_result.to_string();
-            let futures = ids.iter().map(|id| {
-                let pool = pool.clone();
-                let payment_id = payment_id.clone();
-                let id = id.clone();
-                async move {
-                    sqlx::query("INSERT INTO public.payment_linkable (payment_id, linkable_id, linkable_type) VALUES ($1::uuid, $2::uuid, $3)")
-                        .bind(payment_id)
-                        .bind(id)
-                        .bind("discounts")
-                        .execute(&*pool)
-                        .await
-                        .map_err(|e| format!("Failed to link discount to payment: {}", e))
-                }
-            });
-            try_join_all(futures).await?;
-        }
-        None => (),
-    };
-    Ok(payment_result) // Return the UUID from the tuple
+    let recorded = repo.insert_payment(
+        company_id,
+        operator_id,
+        payment_id,
+        checkout,
+        completion.as_ref(),
+    );
+    if !recorded.await? {
+        return Err(status::changed_meanwhile());
+    }
+    Ok(())
 }
 
 // First, define the struct for the customer input
-#[derive(serde::Deserialize, Clone, Serialize)]
-struct CustomerInput {
-    id: Option<String>,
-    avatar: Option<String>,
-    phone: String,
+#[derive(serde::Deserialize, Clone, Serialize, Debug)]
+pub struct CustomerInput {
+    pub id: Option<String>,
+    pub avatar: Option<String>,
+    pub phone: String,
     #[serde(rename = "firstName")]
-    first_name: String,
+    pub first_name: String,
     #[serde(rename = "lastName")]
-    last_name: String,
-    date_of_birth: Option<String>,
-    gender: Option<String>,
-    email: Option<String>,
-    notes: Option<String>,
-    address: Option<Address>,
+    pub last_name: String,
+    pub date_of_birth: Option<String>,
+    pub gender: Option<String>,
+    pub email: Option<String>,
+    pub notes: Option<String>,
+    pub address: Option<Address>,
 }
 
 #[tauri::command]
@@ -689,50 +1690,47 @@ async fn add_customer(
     pool: State<'_, PgPool>,
     state: State<'_, AuthState>,
     app: tauri::AppHandle,
-) -> Result<serde_json::Value, String> {
-    // Get the current auth data to ensure user is authenticated
-    let auth = match &state.0 {
-        Some(data) => data.clone(),
-        None => return Err("Not authenticated".to_string()),
-    };
-
-    // Execute the create_or_update_customer function
-    let result: Option<sqlx::types::Uuid> = sqlx::query_scalar(
-        "SELECT public.create_or_update_customer($1, $2::uuid, $3, $4, $5::date, $6, $7, $8, $9, $10, $11, $12, $13)"
-    )
-    .bind(&customer.phone)
-    .bind(&auth.company.id)
-    .bind(&customer.first_name)
-    .bind(&customer.last_name)
-    .bind(customer.date_of_birth)
-    .bind(customer.gender)
-    .bind(customer.email.as_ref().map(|x|x.clone()))
-    .bind(customer.address.as_ref().map(|a| a.street.clone()))
-    .bind(customer.address.as_ref().map(|a| a.city.clone()))
-    .bind(customer.address.as_ref().map(|a| a.state.clone()))
-    .bind(customer.address.as_ref().map(|a| a.postal_code.clone()))  // This is p_postal_code in the SQL function
-    .bind(customer.address.as_ref().map(|a| a.country.clone()))
-    .bind(customer.notes)  // This is p_note in the SQL function
-        .fetch_one(&*pool)
-        .await
-        .map_err(|e| format!("Failed to create/update customer: {}", e))?;
-
-    match result {
-        Some(customer_id) => {
-            let _ = fetch_latest_state(pool, state, app).await?;
-            Ok(json!({
-                "id": customer_id.to_string(),
-                "name": format!("{} {}", customer.first_name.clone(), customer.last_name.clone()),
-                "firstName": customer.first_name.clone(),
-                "lastName": customer.last_name.clone(),
-                "email": customer.email.map(|x|x.clone()),
-                "phone": customer.phone,
-                "address": customer.address.map(|x|x.clone()),
-            }
-            ))
-        }
-        None => Err("Failed to create/update customer".to_string()),
+) -> Result<serde_json::Value, AppError> {
+    // Make sure the signed-in role is allowed to do this
+    let auth = state.authorize(Permission::AddCustomer)?;
+
+    // Offline the customer gets a local id, swapped for the real one when it syncs
+    if !app.state::<Connection>().is_online() {
+        let local_id = outbox::new_id().to_string();
+        let response = customer_response(&local_id, &customer);
+        outbox::queue(&app, auth, Mutation::AddCustomer { local_id, customer }).await?;
+        return Ok(response);
     }
+
+    let company_id = &auth.data.company.id;
+    let customer_id = create_customer(&*pool, company_id, &customer).await?;
+    let change = delta::customer(&*pool, company_id, &customer_id.to_string()).await?;
+    delta::publish(&app, company_id, vec![change])?;
+
+    Ok(customer_response(&customer_id.to_string(), &customer))
+}
+
+// Create the customer for `company_id`, or update the one with the same phone number
+pub async fn create_customer<R: CustomerRepo>(
+    repo: &R,
+    company_id: &str,
+    customer: &CustomerInput,
+) -> Result<sqlx::types::Uuid, AppError> {
+    repo.upsert_customer(company_id, customer)
+        .await?
+        .ok_or_else(|| AppError::Database("create_or_update_customer returned no id".to_string()))
+}
+
+fn customer_response(id: &str, customer: &CustomerInput) -> serde_json::Value {
+    json!({
+        "id": id,
+        "name": format!("{} {}", customer.first_name, customer.last_name),
+        "firstName": customer.first_name,
+        "lastName": customer.last_name,
+        "email": customer.email,
+        "phone": customer.phone,
+        "address": customer.address,
+    })
 }
 
 #[tauri::command]
@@ -741,39 +1739,42 @@ async fn edit_customer(
     pool: State<'_, PgPool>,
     state: State<'_, AuthState>,
     app: tauri::AppHandle,
-) -> Result<sqlx::types::Uuid, String> {
-    // Get the current auth data to ensure user is authenticated
-    let auth = match &state.0 {
-        Some(data) => data.clone(),
-        None => return Err("Not authenticated".to_string()),
-    };
+) -> Result<sqlx::types::Uuid, AppError> {
+    // Make sure the signed-in role is allowed to do this
+    let auth = state.authorize(Permission::EditCustomer)?;
+
+    let company_id = &auth.data.company.id;
+    let customer_id = update_company_customer(&*pool, company_id, &customer).await?;
+    let change = delta::customer(&*pool, company_id, &customer_id.to_string()).await?;
+    delta::publish(&app, company_id, vec![change])?;
+    Ok(customer_id)
+}
 
-    // Execute the update_person_by_id function
-    let result: Option<sqlx::types::Uuid> = sqlx::query_scalar(
-        "SELECT public.update_person_by_id($1::uuid, $2, $3, $4::date, $5, $6, $7, $8, $9, $10, $11, $12)"
-    )
-        .bind(&customer.id)
-        .bind(&customer.first_name)
-        .bind(&customer.last_name)
-        .bind(&customer.date_of_birth)
-        .bind(&customer.gender)
-        .bind(&customer.email)
-        .bind(customer.address.as_ref().map(|a| a.street.clone()))
-        .bind(customer.address.as_ref().map(|a| a.city.clone()))
-        .bind(customer.address.as_ref().map(|a| a.state.clone()))
-        .bind(customer.address.as_ref().map(|a| a.postal_code.clone()))  // This is p_postal_code in the SQL function
-        .bind(customer.address.as_ref().map(|a| a.country.clone()))
-        .bind(&customer.notes)  // This is p_note in the SQL function
-        .fetch_one(&*pool)
-        .await
-        .map_err(|e| format!("Failed to update customer: {}", e))?;
+// Update a customer, provided they are a customer of `company_id`
+pub async fn update_company_customer<R: CustomerRepo>(
+    repo: &R,
+    company_id: &str,
+    customer: &CustomerInput,
+) -> Result<sqlx::types::Uuid, AppError> {
+    // Only customers of the active company can be edited from here
+    let customer_id = customer.id.clone().unwrap_or_default();
+    ensure_company_customer(repo, company_id, &customer_id).await?;
+
+    repo.update_customer(customer)
+        .await?
+        .ok_or(AppError::NotFound("Customer"))
+}
 
-    match result {
-        Some(customer_id) => {
-            let _ = fetch_latest_state(pool, state, app).await?;
-            Ok(customer_id)
-        }
-        None => Err("Failed to create/update customer".to_string()),
+// Fail unless `customer_id` is a customer of `company_id`
+async fn ensure_company_customer<R: CustomerRepo>(
+    repo: &R,
+    company_id: &str,
+    customer_id: &str,
+) -> Result<(), AppError> {
+    if repo.is_company_customer(company_id, customer_id).await? {
+        Ok(())
+    } else {
+        Err(AppError::NotFound("Customer"))
     }
 }
 
@@ -783,36 +1784,37 @@ async fn delete_customer(
     pool: State<'_, PgPool>,
     state: State<'_, AuthState>,
     app: tauri::AppHandle,
-) -> Result<sqlx::types::Uuid, String> {
-    // Get the current auth data to ensure user is authenticated
-    let auth = match &state.0 {
-        Some(data) => data.clone(),
-        None => return Err("Not authenticated".to_string()),
+) -> Result<sqlx::types::Uuid, AppError> {
+    // Make sure the signed-in role is allowed to do this
+    let auth = state.authorize(Permission::DeleteCustomer)?;
+
+    let company_id = &auth.data.company.id;
+    let deleted = delete_company_customer(&*pool, company_id, &customer_id).await?;
+    let change = Delta::CustomerRemoved {
+        id: deleted.to_string(),
     };
+    delta::publish(&app, company_id, vec![change])?;
+    Ok(deleted)
+}
 
-    // Parse the customer_id string into a UUID
-    let customer_uuid = sqlx::types::Uuid::parse_str(&customer_id)
-        .map_err(|e| format!("Invalid customer ID format: {}", e))?;
-
-    // Execute the delete operation
-    let result = sqlx::query!(
-        "DELETE FROM people WHERE id = $1 RETURNING id",
-        customer_uuid
-    )
-    .fetch_optional(&*pool)
-    .await
-    .map_err(|e| format!("Failed to delete customer: {}", e))?;
-
-    match result {
-        Some(record) => {
-            let _ = fetch_latest_state(pool, state, app).await?;
-            Ok(record.id)
-        }
-        None => Err("Customer not found".to_string()),
-    }
+// Delete a person, limited to customers of `company_id`
+pub async fn delete_company_customer<R: CustomerRepo>(
+    repo: &R,
+    company_id: &str,
+    customer_id: &str,
+) -> Result<sqlx::types::Uuid, AppError> {
+    let customer_uuid = sqlx::types::Uuid::parse_str(customer_id)
+        .map_err(|_| AppError::Invalid("Invalid customer ID".to_string()))?;
+    let company_uuid = sqlx::types::Uuid::parse_str(company_id)
+        .map_err(|_| AppError::Invalid("Invalid company ID".to_string()))?;
+
+    repo.delete_customer(company_uuid, customer_uuid)
+        .await?
+        .ok_or(AppError::NotFound("Customer"))
 }
 
 #[tauri::command]
+#[allow(clippy::too_many_arguments)]
 async fn checkout_walkin(
     customer_id: String,
     services_id: Option<Vec<String>>,
@@ -824,101 +1826,40 @@ async fn checkout_walkin(
     pool: State<'_, PgPool>,
     state: State<'_, AuthState>,
     app: tauri::AppHandle,
-) -> Result<sqlx::types::Uuid, String> {
-    // Get the current auth data to ensure user is authenticated
-    let data = match &state.0 {
-        Some(data) => data.clone(),
-        None => return Err("Not authenticated".to_string()),
+) -> Result<sqlx::types::Uuid, AppError> {
+    // Make sure the signed-in role is allowed to do this
+    let session = state.authorize(Permission::Checkout)?;
+
+    let checkout = Checkout {
+        booking_id: None,
+        customer_id,
+        services_id,
+        discounts_id,
+        currency_id,
+        method,
+        amount,
+        status,
     };
 
-    let payment_result = sqlx::query_as::<_, (sqlx::types::Uuid,)>(
-        "INSERT INTO public.payments (amount, currency_id, payment_method, person_id, company_id, status) 
-         VALUES ($1, $2::uuid, $3, $4::uuid, $5::uuid, $6)
-         RETURNING id"
+    let payment_id = outbox::new_id();
+    if !app.state::<Connection>().is_online() {
+        outbox::queue(&app, session, Mutation::Checkout { payment_id, checkout }).await?;
+        return Ok(payment_id);
+    }
+
+    record_payment(
+        &*pool,
+        &app.state::<Statuses>(),
+        &session.data.company.id,
+        session.acting_person_id(), // Whoever is working the till takes the payment
+        payment_id,
+        &checkout,
     )
-    .bind(amount)
-    .bind(currency_id)
-    .bind(method)
-    .bind(customer_id)
-    .bind(data.company.id)  // Use company ID from auth data directly
-    .bind(status)
-    .fetch_one(&*pool)
-    .await
-    .map_err(|e| format!("Failed to process payment: {}", e))?.0;
-
-    // update booking status & link booking to payment
-    // match booking_id {
-    //     Some(id) => {
-    //         let id = id.clone();
-    //         // Update booking status
-    //         sqlx::query("UPDATE booking SET status_id = $1::uuid WHERE id = $2::uuid")
-    //             .bind("0758ec7a-8cf6-4247-923c-cdbdfeb214db") // completed status id
-    //             .bind(id.clone())
-    //             .execute(&*pool)
-    //             .await
-    //             .map_err(|e| format!("Failed to update booking status: {}", e))?;
-
-    //         // Link booking to payment
-    //         let payment_id = payment_result.to_string();
-    //         let pool = pool.clone();
-    //         sqlx::query("INSERT INTO public.payment_linkable (payment_id, linkable_id, linkable_type) VALUES ($1::uuid, $2::uuid, $3)")
-    //             .bind(payment_id)
-    //             .bind(id)
-    //             .bind("booking")
-    //             .execute(&*pool)
-    //             .await
-    //             .map_err(|e| format!("Failed to link booking to payment: {}", e))?;
-    //     }
-    //     None => (),
-    // };
-
-    //  link services to payment
-    match services_id {
-        Some(ids) => {
-            let payment_id = payment_result.to_string();
-            let futures = ids.iter().map(|id| {
-                let pool = pool.clone();
-                let payment_id = payment_id.clone();
-                let id = id.clone();
-                async move {
-                    sqlx::query("INSERT INTO public.payment_linkable (payment_id, linkable_id, linkable_type) VALUES ($1::uuid, $2::uuid, $3)")
-                        .bind(payment_id)
-                        .bind(id)
-                        .bind("services")
-                        .execute(&*pool)
-                        .await
-                        .map_err(|e| format!("Failed to link service to payment: {}", e))
-                }
-            });
-            try_join_all(futures).await?;
-        }
-        None => (),
-    };
+    .await?;
+    Span::session("checkout_walkin", &session).info(format_args!("Payment {} recorded", payment_id));
 
-    // link discounts to payment
-    match discounts_id {
-        Some(ids) => {
-            let payment_id = payment_result.to_string();
-            let futures = ids.iter().map(|id| {
-                let pool = pool.clone();
-                let payment_id = payment_id.clone();
-                let id = id.clone();
-                async move {
-                    sqlx::query("INSERT INTO public.payment_linkable (payment_id, linkable_id, linkable_type) VALUES ($1::uuid, $2::uuid, $3)")
-                        .bind(payment_id)
-                        .bind(id)
-                        .bind("discounts")
-                        .execute(&*pool)
-                        .await
-                        .map_err(|e| format!("Failed to link discount to payment: {}", e))
-                }
-            });
-            try_join_all(futures).await?;
-        }
-        None => (),
-    };
-    let _ = fetch_latest_state(pool, state, app).await?;
-    Ok(payment_result) // Return the UUID from the tuple
+    // Payments aren't part of the cached state, so there is nothing to refresh
+    Ok(payment_id)
 }
 
 #[tauri::command]
@@ -926,70 +1867,683 @@ async fn update_campaign(
     campaign_id: String,
     pool: State<'_, PgPool>,
     state: State<'_, AuthState>,
-    app: tauri::AppHandle,
-) -> Result<String, String> {
-    // Get the current auth data to ensure user is authenticated
-    let company_id = match &state.0 {
-        Some(data) => data.company.id.clone(),
-        None => return Err("Not authenticated".to_string()),
-    };
+) -> Result<String, AppError> {
+    // Make sure the signed-in role is allowed to do this
+    let company_id = state.authorize(Permission::ManageCampaigns)?.data.company.id;
 
-    // First, check if the link already exists
-    let existing_link = sqlx::query!(
-        "SELECT id FROM public.campaign_linkable 
-         WHERE campaign_id = $1::uuid 
-         AND linkable_id = $2::uuid 
-         AND linkable_type = 'company'",
-        sqlx::types::Uuid::parse_str(&campaign_id)
-            .map_err(|e| format!("Invalid campaign ID: {}", e))?,
-        sqlx::types::Uuid::parse_str(&company_id)
-            .map_err(|e| format!("Invalid company ID: {}", e))?
-    )
-    .fetch_optional(&*pool)
-    .await
-    .map_err(|e| format!("Failed to check campaign link: {}", e))?;
-
-    match existing_link {
-        // If link exists, delete it
-        Some(_) => {
-            sqlx::query!(
-                "DELETE FROM public.campaign_linkable 
-                 WHERE campaign_id = $1::uuid 
-                 AND linkable_id = $2::uuid 
-                 AND linkable_type = 'company'",
-                sqlx::types::Uuid::parse_str(&campaign_id)
-                    .map_err(|e| format!("Invalid campaign ID: {}", e))?,
-                sqlx::types::Uuid::parse_str(&company_id)
-                    .map_err(|e| format!("Invalid company ID: {}", e))?
-            )
-            .execute(&*pool)
+    // Campaign links aren't part of the cached state, so there is nothing to refresh
+    let linked = toggle_campaign(&*pool, &company_id, &campaign_id).await?;
+
+    if linked {
+        Ok("Campaign link successfully created".to_string())
+    } else {
+        Ok("Campaign link successfully removed".to_string())
+    }
+}
+
+// Link the campaign to the company, or unlink it when it already is. Returns whether it
+// is linked now.
+pub async fn toggle_campaign<R: CampaignRepo>(
+    repo: &R,
+    company_id: &str,
+    campaign_id: &str,
+) -> Result<bool, AppError> {
+    let campaign_id = sqlx::types::Uuid::parse_str(campaign_id)
+        .map_err(|_| AppError::Invalid("Invalid campaign ID".to_string()))?;
+    let company_id = sqlx::types::Uuid::parse_str(company_id)
+        .map_err(|_| AppError::Invalid("Invalid company ID".to_string()))?;
+
+    if repo.campaign_linked(company_id, campaign_id).await? {
+        repo.unlink_campaign(company_id, campaign_id).await?;
+        Ok(false)
+    } else {
+        repo.link_campaign(company_id, campaign_id).await?;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Timelike;
+    use repo::MemoryRepo;
+
+    const COMPANY: &str = "00000000-0000-4000-8000-000000000001";
+    const OTHER_COMPANY: &str = "00000000-0000-4000-8000-000000000002";
+    const STAFF_A: &str = "00000000-0000-4000-8000-00000000000a";
+    const STAFF_B: &str = "00000000-0000-4000-8000-00000000000b";
+    const SERVICE: &str = "00000000-0000-4000-8000-000000000005";
+    const BOOKING: &str = "00000000-0000-4000-8000-0000000000b1";
+    const OPERATOR: &str = "00000000-0000-4000-8000-0000000000f1";
+
+    fn now() -> DateTime<Utc> {
+        "2030-01-01T00:00:00Z".parse().unwrap()
+    }
+
+    fn person(id: &str, first_name: &str) -> Value {
+        json!({
+            "id": id,
+            "personal_information": { "first_name": first_name, "last_name": "Lee" },
+        })
+    }
+
+    // A company open 09:00 to 17:00 UTC every day, with two staff and a one hour service
+    fn repo() -> MemoryRepo {
+        let repo = MemoryRepo::default();
+        for status in [
+            "pending",
+            "confirmed",
+            "in_progress",
+            "completed",
+            "cancelled",
+            "no_show",
+        ] {
+            repo.add_status(&format!("status-{}", status), status);
+        }
+
+        let timetable: Vec<Value> = (0..7)
+            .map(|day| {
+                json!({
+                    "id": format!("slot-{}", day),
+                    "company_id": COMPANY,
+                    "day_of_week": day,
+                    "start_time": "09:00:00",
+                    "end_time": "17:00:00",
+                    "timezone": "UTC",
+                })
+            })
+            .collect();
+        let data = json!({
+            "roles": {
+                "owner": [person("00000000-0000-4000-8000-00000000000c", "Alex")],
+                "staff": [person(STAFF_A, "Ari"), person(STAFF_B, "Bo")],
+            },
+            "company": {
+                "id": COMPANY,
+                "name": "Salon",
+                "description": "",
+                "currency": { "id": "aud", "code": "AUD", "symbol": "$" },
+                "timetable": timetable,
+                "services_by_catalogue": [{
+                    "services": [{
+                        "id": SERVICE,
+                        "name": "Cut",
+                        "duration": "01:00:00",
+                        "price": 50.0,
+                    }],
+                }],
+            },
+        });
+        repo.add_company("token", serde_json::from_value(data).unwrap());
+        repo
+    }
+
+    fn customer_input(phone: &str) -> CustomerInput {
+        CustomerInput {
+            id: None,
+            avatar: None,
+            phone: phone.to_string(),
+            first_name: "Jo".to_string(),
+            last_name: "Citizen".to_string(),
+            date_of_birth: None,
+            gender: None,
+            email: None,
+            notes: None,
+            address: None,
+        }
+    }
+
+    async fn customer(repo: &MemoryRepo, company_id: &str) -> String {
+        repo.upsert_customer(company_id, &customer_input("0400000001"))
             .await
-            .map_err(|e| format!("Failed to delete campaign link: {}", e))?;
+            .unwrap()
+            .unwrap()
+            .to_string()
+    }
 
-            // Fetch the latest state to ensure the app has up-to-date data
-            let _ = fetch_latest_state(pool, state, app).await?;
+    fn booking_input(customer_id: &str, staff_id: Option<&str>, start_time: &str) -> BookingInput {
+        BookingInput {
+            customer_id: customer_id.to_string(),
+            service_id: SERVICE.to_string(),
+            staff_id: staff_id.map(str::to_string),
+            start_time: start_time.to_string(),
+        }
+    }
+
+    fn add_booking(
+        repo: &MemoryRepo,
+        id: &str,
+        staff_id: &str,
+        status: &str,
+        start: &str,
+        end: &str,
+    ) {
+        let status_id = format!("status-{}", status);
+        repo.add_booking(
+            COMPANY,
+            id,
+            Some(staff_id),
+            Some(SERVICE),
+            &status_id,
+            start,
+            end,
+        )
+        .unwrap();
+    }
 
-            Ok(format!("Campaign link successfully removed"))
+    fn checkout(booking_id: Option<&str>) -> Checkout {
+        Checkout {
+            booking_id: booking_id.map(str::to_string),
+            customer_id: "00000000-0000-4000-8000-0000000000c1".to_string(),
+            services_id: Some(vec![SERVICE.to_string()]),
+            discounts_id: None,
+            currency_id: "aud".to_string(),
+            method: "card".to_string(),
+            amount: 50.0,
+            status: "paid".to_string(),
         }
-        // If link doesn't exist, insert it
-        None => {
-            sqlx::query!(
-                "INSERT INTO public.campaign_linkable 
-                 (campaign_id, linkable_id, linkable_type) 
-                 VALUES ($1::uuid, $2::uuid, 'company')",
-                sqlx::types::Uuid::parse_str(&campaign_id)
-                    .map_err(|e| format!("Invalid campaign ID: {}", e))?,
-                sqlx::types::Uuid::parse_str(&company_id)
-                    .map_err(|e| format!("Invalid company ID: {}", e))?
-            )
-            .execute(&*pool)
+    }
+
+    #[tokio::test]
+    async fn create_books_the_staff_member_asked_for() {
+        let repo = repo();
+        let customer_id = customer(&repo, COMPANY).await;
+        let input = booking_input(&customer_id, Some(STAFF_A), "2030-01-07T10:00:00+00:00");
+
+        let id = create_company_booking(&repo, COMPANY, &input, now())
+            .await
+            .unwrap();
+
+        let booking = repo
+            .booking(COMPANY, &id.to_string())
             .await
-            .map_err(|e| format!("Failed to create campaign link: {}", e))?;
+            .unwrap()
+            .unwrap();
+        assert_eq!(booking.status.as_deref(), Some("pending"));
+        assert_eq!(booking.staff_id.as_deref(), Some(STAFF_A));
+        assert_eq!(
+            booking.end_time - booking.start_time,
+            chrono::TimeDelta::hours(1)
+        );
+    }
 
-            // Fetch the latest state to ensure the app has up-to-date data
-            let _ = fetch_latest_state(pool, state, app).await?;
+    #[tokio::test]
+    async fn create_picks_a_free_staff_member() {
+        let repo = repo();
+        let customer_id = customer(&repo, COMPANY).await;
+        add_booking(
+            &repo,
+            BOOKING,
+            STAFF_A,
+            "confirmed",
+            "2030-01-07T10:00:00Z",
+            "2030-01-07T11:00:00Z",
+        );
+        let input = booking_input(&customer_id, None, "2030-01-07T10:30:00+00:00");
+
+        let id = create_company_booking(&repo, COMPANY, &input, now())
+            .await
+            .unwrap();
 
-            Ok(format!("Campaign link successfully created"))
+        let booking = repo
+            .booking(COMPANY, &id.to_string())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(booking.staff_id.as_deref(), Some(STAFF_B));
+    }
+
+    #[tokio::test]
+    async fn create_refuses_taken_closed_and_past_times() {
+        let repo = repo();
+        let customer_id = customer(&repo, COMPANY).await;
+        add_booking(
+            &repo,
+            BOOKING,
+            STAFF_A,
+            "pending",
+            "2030-01-07T10:00:00Z",
+            "2030-01-07T11:00:00Z",
+        );
+
+        let taken = booking_input(&customer_id, Some(STAFF_A), "2030-01-07T10:30:00+00:00");
+        let closed = booking_input(&customer_id, Some(STAFF_A), "2030-01-07T16:30:00+00:00");
+        let past = booking_input(&customer_id, Some(STAFF_A), "2029-12-31T10:00:00+00:00");
+        for input in [taken, closed] {
+            let result = create_company_booking(&repo, COMPANY, &input, now()).await;
+            assert!(matches!(result, Err(AppError::Conflict(_))), "{:?}", result);
         }
+        let result = create_company_booking(&repo, COMPANY, &past, now()).await;
+        assert!(matches!(result, Err(AppError::Invalid(_))), "{:?}", result);
+    }
+
+    #[tokio::test]
+    async fn create_sees_a_booking_from_before_opening() {
+        let repo = repo();
+        let customer_id = customer(&repo, COMPANY).await;
+        add_booking(
+            &repo,
+            BOOKING,
+            STAFF_A,
+            "confirmed",
+            "2030-01-07T08:30:00Z",
+            "2030-01-07T09:30:00Z",
+        );
+        let input = booking_input(&customer_id, Some(STAFF_A), "2030-01-07T09:00:00+00:00");
+
+        match create_company_booking(&repo, COMPANY, &input, now()).await {
+            Err(AppError::Conflict(message)) => assert_eq!(
+                message,
+                "The staff member is already booked from 2030-01-07 08:30 UTC to 2030-01-07 09:30 UTC"
+            ),
+            result => panic!("{:?}", result),
+        }
+    }
+
+    #[tokio::test]
+    async fn create_ignores_cancelled_bookings() {
+        let repo = repo();
+        let customer_id = customer(&repo, COMPANY).await;
+        add_booking(
+            &repo,
+            BOOKING,
+            STAFF_A,
+            "cancelled",
+            "2030-01-07T10:00:00Z",
+            "2030-01-07T11:00:00Z",
+        );
+        let input = booking_input(&customer_id, Some(STAFF_A), "2030-01-07T10:00:00+00:00");
+
+        assert!(create_company_booking(&repo, COMPANY, &input, now())
+            .await
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn create_needs_a_customer_of_the_company() {
+        let repo = repo();
+        let customer_id = customer(&repo, OTHER_COMPANY).await;
+        let input = booking_input(&customer_id, Some(STAFF_A), "2030-01-07T10:00:00+00:00");
+
+        let result = create_company_booking(&repo, COMPANY, &input, now()).await;
+        assert!(
+            matches!(result, Err(AppError::NotFound("Customer"))),
+            "{:?}",
+            result
+        );
+    }
+
+    #[tokio::test]
+    async fn reschedule_moves_to_a_free_time() {
+        let repo = repo();
+        add_booking(
+            &repo,
+            BOOKING,
+            STAFF_A,
+            "pending",
+            "2030-01-07T10:00:00Z",
+            "2030-01-07T11:00:00Z",
+        );
+
+        let conflicts = reschedule_company_booking(
+            &repo,
+            COMPANY,
+            BOOKING,
+            "2030-01-07T14:00:00+00:00",
+            None,
+            now(),
+        )
+        .await
+        .unwrap();
+
+        assert!(conflicts.is_empty());
+        let booking = repo.booking(COMPANY, BOOKING).await.unwrap().unwrap();
+        assert_eq!(
+            booking.start_time,
+            "2030-01-07T14:00:00Z".parse::<DateTime<Utc>>().unwrap()
+        );
+        assert_eq!(
+            booking.end_time,
+            "2030-01-07T15:00:00Z".parse::<DateTime<Utc>>().unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn reschedule_reports_conflicts_without_moving() {
+        let repo = repo();
+        let other = "00000000-0000-4000-8000-0000000000b2";
+        add_booking(
+            &repo,
+            BOOKING,
+            STAFF_A,
+            "pending",
+            "2030-01-07T10:00:00Z",
+            "2030-01-07T11:00:00Z",
+        );
+        add_booking(
+            &repo,
+            other,
+            STAFF_A,
+            "confirmed",
+            "2030-01-07T16:00:00Z",
+            "2030-01-07T17:00:00Z",
+        );
+
+        let conflicts = reschedule_company_booking(
+            &repo,
+            COMPANY,
+            BOOKING,
+            "2030-01-07T16:30:00+00:00",
+            None,
+            now(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(conflicts.len(), 2);
+        assert!(matches!(
+            &conflicts[0],
+            ScheduleConflict::OutsideOpeningHours { .. }
+        ));
+        match &conflicts[1] {
+            ScheduleConflict::StaffBooked { bookings } => assert_eq!(bookings[0].id, other),
+            conflict => panic!("{:?}", conflict),
+        }
+        let booking = repo.booking(COMPANY, BOOKING).await.unwrap().unwrap();
+        assert_eq!(
+            booking.start_time,
+            "2030-01-07T10:00:00Z".parse::<DateTime<Utc>>().unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn reschedule_refuses_finished_bookings() {
+        let repo = repo();
+        add_booking(
+            &repo,
+            BOOKING,
+            STAFF_A,
+            "completed",
+            "2030-01-07T10:00:00Z",
+            "2030-01-07T11:00:00Z",
+        );
+
+        let result = reschedule_company_booking(
+            &repo,
+            COMPANY,
+            BOOKING,
+            "2030-01-07T14:00:00+00:00",
+            None,
+            now(),
+        )
+        .await;
+        assert!(matches!(result, Err(AppError::Conflict(_))), "{:?}", result);
+    }
+
+    #[tokio::test]
+    async fn cancel_is_idempotent_and_leaves_completed_bookings() {
+        let repo = repo();
+        let statuses = Statuses::default();
+        let completed = "00000000-0000-4000-8000-0000000000b2";
+        add_booking(
+            &repo,
+            BOOKING,
+            STAFF_A,
+            "confirmed",
+            "2030-01-07T10:00:00Z",
+            "2030-01-07T11:00:00Z",
+        );
+        add_booking(
+            &repo,
+            completed,
+            STAFF_A,
+            "completed",
+            "2030-01-07T12:00:00Z",
+            "2030-01-07T13:00:00Z",
+        );
+
+        for _ in 0..2 {
+            assert!(cancel_company_booking(&repo, &statuses, COMPANY, BOOKING)
+                .await
+                .unwrap());
+            assert_eq!(
+                repo.booking_status_id(BOOKING).as_deref(),
+                Some("status-cancelled")
+            );
+        }
+
+        let result = cancel_company_booking(&repo, &statuses, COMPANY, completed).await;
+        assert!(matches!(result, Err(AppError::Conflict(_))), "{:?}", result);
+        assert_eq!(
+            repo.booking_status_id(completed).as_deref(),
+            Some("status-completed")
+        );
+
+        assert!(
+            !cancel_company_booking(&repo, &statuses, OTHER_COMPANY, BOOKING)
+                .await
+                .unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn payment_completes_the_booking_and_links_it() {
+        let repo = repo();
+        let statuses = Statuses::default();
+        let payment_id = outbox::new_id();
+        add_booking(
+            &repo,
+            BOOKING,
+            STAFF_A,
+            "pending",
+            "2030-01-07T10:00:00Z",
+            "2030-01-07T11:00:00Z",
+        );
+
+        record_payment(
+            &repo,
+            &statuses,
+            COMPANY,
+            OPERATOR,
+            payment_id,
+            &checkout(Some(BOOKING)),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            repo.booking_status_id(BOOKING).as_deref(),
+            Some("status-completed")
+        );
+        let payment = &repo.payments()[&payment_id];
+        assert_eq!(payment.operator_id, OPERATOR);
+        assert_eq!(
+            payment.links,
+            [
+                (BOOKING.to_string(), "booking".to_string()),
+                (SERVICE.to_string(), "services".to_string()),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn completed_bookings_are_not_paid_twice() {
+        let repo = repo();
+        let statuses = Statuses::default();
+        let first = outbox::new_id();
+        add_booking(
+            &repo,
+            BOOKING,
+            STAFF_A,
+            "pending",
+            "2030-01-07T10:00:00Z",
+            "2030-01-07T11:00:00Z",
+        );
+
+        let checkout = checkout(Some(BOOKING));
+        record_payment(&repo, &statuses, COMPANY, OPERATOR, first, &checkout)
+            .await
+            .unwrap();
+        let second = outbox::new_id();
+        let result = record_payment(&repo, &statuses, COMPANY, OPERATOR, second, &checkout).await;
+
+        assert!(matches!(result, Err(AppError::Conflict(_))), "{:?}", result);
+        assert_eq!(repo.payments().keys().collect::<Vec<_>>(), [&first]);
+    }
+
+    #[tokio::test]
+    async fn walk_in_payment_needs_no_booking() {
+        let repo = repo();
+        let payment_id = outbox::new_id();
+
+        record_payment(
+            &repo,
+            &Statuses::default(),
+            COMPANY,
+            OPERATOR,
+            payment_id,
+            &checkout(None),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(repo.payments()[&payment_id].links.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn cancelled_or_missing_bookings_are_not_paid() {
+        let repo = repo();
+        let statuses = Statuses::default();
+        add_booking(
+            &repo,
+            BOOKING,
+            STAFF_A,
+            "cancelled",
+            "2030-01-07T10:00:00Z",
+            "2030-01-07T11:00:00Z",
+        );
+
+        let result = record_payment(
+            &repo,
+            &statuses,
+            COMPANY,
+            OPERATOR,
+            outbox::new_id(),
+            &checkout(Some(BOOKING)),
+        )
+        .await;
+        assert!(matches!(result, Err(AppError::Conflict(_))), "{:?}", result);
+
+        let missing = "00000000-0000-4000-8000-0000000000b2";
+        let result = record_payment(
+            &repo,
+            &statuses,
+            COMPANY,
+            OPERATOR,
+            outbox::new_id(),
+            &checkout(Some(missing)),
+        )
+        .await;
+        assert!(
+            matches!(result, Err(AppError::NotFound("Booking"))),
+            "{:?}",
+            result
+        );
+        assert!(repo.payments().is_empty());
+    }
+
+    #[tokio::test]
+    async fn delete_only_removes_the_company_customer() {
+        let repo = repo();
+        let own = customer(&repo, COMPANY).await;
+        let other = customer(&repo, OTHER_COMPANY).await;
+
+        let result = delete_company_customer(&repo, COMPANY, &other).await;
+        assert!(
+            matches!(result, Err(AppError::NotFound("Customer"))),
+            "{:?}",
+            result
+        );
+        assert!(repo.customer(other.parse().unwrap()).is_some());
+
+        let deleted = delete_company_customer(&repo, COMPANY, &own).await.unwrap();
+        assert_eq!(deleted.to_string(), own);
+        assert!(repo.customer(deleted).is_none());
+
+        let result = delete_company_customer(&repo, COMPANY, "not-a-uuid").await;
+        assert!(matches!(result, Err(AppError::Invalid(_))), "{:?}", result);
+    }
+
+    #[tokio::test]
+    async fn toggle_campaign_links_and_unlinks() {
+        let repo = repo();
+        let campaign = "00000000-0000-4000-8000-0000000000ca";
+
+        assert!(toggle_campaign(&repo, COMPANY, campaign).await.unwrap());
+        assert!(!toggle_campaign(&repo, COMPANY, campaign).await.unwrap());
+        assert!(toggle_campaign(&repo, COMPANY, campaign).await.unwrap());
+        assert!(toggle_campaign(&repo, OTHER_COMPANY, campaign)
+            .await
+            .unwrap());
+
+        let result = toggle_campaign(&repo, COMPANY, "campaign").await;
+        assert!(matches!(result, Err(AppError::Invalid(_))), "{:?}", result);
+    }
+    fn slot_starts(days: &[AvailableDay], staff_id: &str) -> Vec<u32> {
+        days.iter()
+            .flat_map(|day| &day.staff)
+            .filter(|entry| entry.staff.id == staff_id)
+            .flat_map(|entry| &entry.slots)
+            .map(|slot| slot.start_time.hour())
+            .collect()
+    }
+
+    async fn first_day_slots(repo: &MemoryRepo) -> Vec<AvailableDay> {
+        let query = SlotQuery {
+            service_id: SERVICE.to_string(),
+            staff_id: None,
+            from: NaiveDate::from_ymd_opt(2030, 1, 1).unwrap(),
+            days: Some(1),
+            granularity_minutes: Some(60),
+            min_notice_minutes: Some(0),
+        };
+        find_company_slots(repo, COMPANY, &query, &BookingConfig::default(), now())
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn slots_skip_a_booking_from_before_opening() {
+        let repo = repo();
+        add_booking(
+            &repo,
+            BOOKING,
+            STAFF_A,
+            "confirmed",
+            "2030-01-01T08:00:00Z",
+            "2030-01-01T10:00:00Z",
+        );
+
+        let days = first_day_slots(&repo).await;
+        assert_eq!(slot_starts(&days, STAFF_A), (10..17).collect::<Vec<_>>());
+        assert_eq!(slot_starts(&days, STAFF_B), (9..17).collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn slots_skip_bookings_at_other_companies() {
+        let repo = repo();
+        repo.add_booking(
+            OTHER_COMPANY,
+            BOOKING,
+            Some(STAFF_B),
+            None,
+            "status-pending",
+            "2030-01-01T12:00:00Z",
+            "2030-01-01T13:00:00Z",
+        )
+        .unwrap();
+
+        let days = first_day_slots(&repo).await;
+        assert_eq!(slot_starts(&days, STAFF_B), vec![9, 10, 11, 13, 14, 15, 16]);
     }
 }
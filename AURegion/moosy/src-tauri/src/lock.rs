@@ -0,0 +1,323 @@
+use chrono::Utc;
+use sqlx::PgPool;
+use std::{
+    sync::{Mutex, RwLock},
+    time::{Duration, Instant},
+};
+use tauri::{Emitter, Manager, State};
+
+use crate::{
+    clear_auth,
+    config::Config,
+    connection::Connection,
+    error::AppError,
+    lockout::{self, LoginAttempts},
+    password::{self, Verification},
+    permissions::Permission,
+    session_username, verify_credentials, write_session_file, AuthState, Session,
+};
+
+// Persisted in `lock.json` in the app config dir so each front desk PC keeps its own policy
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct LockSettings {
+    // Lock after this many seconds without user activity, 0 disables the idle lock
+    pub idle_timeout_secs: u64,
+    // Restore a persisted session in the locked state instead of signing straight back in
+    pub lock_on_startup: bool,
+}
+
+impl Default for LockSettings {
+    fn default() -> Self {
+        LockSettings {
+            idle_timeout_secs: 300,
+            lock_on_startup: true,
+        }
+    }
+}
+
+impl LockSettings {
+    fn path(app: &tauri::AppHandle) -> Result<std::path::PathBuf, AppError> {
+        Ok(app
+            .path()
+            .app_config_dir()
+            .map_err(|e| e.to_string())?
+            .join("lock.json"))
+    }
+
+    // Missing or unreadable settings fall back to the defaults
+    pub fn load(app: &tauri::AppHandle) -> LockSettings {
+        Self::path(app)
+            .ok()
+            .and_then(|path| std::fs::read_to_string(path).ok())
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default()
+    }
+
+    fn save(&self, app: &tauri::AppHandle) -> Result<(), AppError> {
+        let path = Self::path(app)?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .map_err(|e| format!("Failed to create app config directory: {}", e))?;
+        }
+        let formatted_json = serde_json::to_string_pretty(self)
+            .map_err(|e| format!("Failed to serialize lock settings: {}", e))?;
+        std::fs::write(path, formatted_json)
+            .map_err(|e| format!("Failed to write lock settings: {}", e))?;
+        Ok(())
+    }
+}
+
+// Tracks user activity reported by the frontend against the configured idle timeout
+pub struct IdleLock {
+    settings: RwLock<LockSettings>,
+    last_activity: Mutex<Instant>,
+    // Wrong passwords against the local verifier. The database can't count them while
+    // offline, so they are held here with the same backoff.
+    offline_failures: Mutex<LoginAttempts>,
+}
+
+impl IdleLock {
+    pub fn new(settings: LockSettings) -> Self {
+        IdleLock {
+            settings: RwLock::new(settings),
+            last_activity: Mutex::new(Instant::now()),
+            offline_failures: Mutex::new(LoginAttempts {
+                failed_attempts: 0,
+                locked_until: None,
+            }),
+        }
+    }
+
+    pub fn settings(&self) -> LockSettings {
+        self.settings.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    pub fn touch(&self) {
+        *self.last_activity.lock().unwrap_or_else(|e| e.into_inner()) = Instant::now();
+    }
+
+    fn is_idle(&self) -> bool {
+        let timeout = self.settings().idle_timeout_secs;
+        let last_activity = *self.last_activity.lock().unwrap_or_else(|e| e.into_inner());
+        timeout > 0 && last_activity.elapsed() >= Duration::from_secs(timeout)
+    }
+}
+
+// Lock the current session and tell the frontend to show the lock screen
+pub fn lock(app: &tauri::AppHandle) -> Result<(), AppError> {
+    let state = app.state::<AuthState>();
+    if state.get().is_none() || state.is_locked() {
+        return Ok(());
+    }
+
+    state.lock();
+
+    app.emit("session-locked", ())
+        .map_err(|e| format!("Failed to emit event: {}", e))?;
+    Ok(())
+}
+
+// Background thread that locks idle sessions and drops the ones that have expired
+pub fn spawn_monitor(app: tauri::AppHandle) {
+    std::thread::spawn(move || loop {
+        // Re-read every time so a changed interval applies without a restart
+        let interval = app.state::<Config>().get().refresh.session_check_secs;
+        std::thread::sleep(Duration::from_secs(interval));
+
+        let session = match app.state::<AuthState>().get() {
+            Some(session) => session,
+            None => continue,
+        };
+
+        if session.is_expired() {
+            if let Err(e) = clear_auth(&app) {
+                log::error!("Failed to clear expired session: {}", e);
+            }
+            let _ = app.emit("session-expired", ());
+            continue;
+        }
+
+        if app.state::<IdleLock>().is_idle() {
+            if let Err(e) = lock(&app) {
+                log::error!("Failed to lock idle session: {}", e);
+            }
+        }
+    });
+}
+
+// Called by the frontend on user input to push back the idle timeout
+#[tauri::command]
+pub fn record_activity(idle: State<'_, IdleLock>) {
+    idle.touch();
+}
+
+#[tauri::command]
+pub fn lock_session(app: tauri::AppHandle) -> Result<(), AppError> {
+    lock(&app)
+}
+
+// Check the password against the account in the database. The token is checked again on
+// the way, so a session revoked while the terminal was locked can't be resumed.
+async fn unlock_online(
+    pool: &PgPool,
+    session: &Session,
+    password: &str,
+    app: &tauri::AppHandle,
+) -> Result<(), AppError> {
+    let username = match session_username(pool, &session.token).await? {
+        Some(username) => username,
+        None => {
+            clear_auth(app)?;
+            return Err(AppError::SessionExpired);
+        }
+    };
+
+    verify_credentials(pool, &username, password).await?;
+
+    // Sessions saved before the local verifier existed pick one up here
+    if session.unlock_verifier.is_none() {
+        let unlock_verifier = password::hash_password(password)?;
+        let updated = app.state::<AuthState>().update(|session| {
+            session.unlock_verifier.is_none() && {
+                session.unlock_verifier = Some(unlock_verifier);
+                true
+            }
+        });
+        if let Some(updated) = updated {
+            write_session_file(app, &updated)?;
+        }
+    }
+    Ok(())
+}
+
+// Check the password against the verifier kept in the sealed session, with the same backoff
+// as the database applies to wrong passwords
+fn unlock_offline(
+    idle: &IdleLock,
+    unlock_verifier: Option<&str>,
+    password: &str,
+) -> Result<(), AppError> {
+    let unlock_verifier = unlock_verifier.ok_or(AppError::Offline)?;
+
+    let mut failures = idle
+        .offline_failures
+        .lock()
+        .unwrap_or_else(|e| e.into_inner());
+    failures.check()?;
+
+    if password::verify_password(password, unlock_verifier)? == Verification::Invalid {
+        *failures = lockout::after_failure(failures.failed_attempts + 1, Utc::now());
+        failures.check()?;
+        return Err(AppError::InvalidCredentials);
+    }
+
+    failures.failed_attempts = 0;
+    failures.locked_until = None;
+    Ok(())
+}
+
+// Unlock with the signed-in account's password, checked by the database when it can be
+// reached and against the session's local verifier when it can't. The token of a session
+// unlocked offline is checked when the database comes back, like after a restart.
+#[tauri::command]
+pub async fn unlock_session(
+    password: String,
+    pool: State<'_, PgPool>,
+    state: State<'_, AuthState>,
+    idle: State<'_, IdleLock>,
+    connection: State<'_, Connection>,
+    app: tauri::AppHandle,
+) -> Result<(), AppError> {
+    let session = match state.get() {
+        Some(session) => session,
+        None => return Err(AppError::NotAuthenticated),
+    };
+
+    let checked_online = connection.is_online()
+        && match unlock_online(&pool, &session, &password, &app).await {
+            Ok(()) => true,
+            Err(AppError::Offline) => false,
+            Err(e) => return Err(e),
+        };
+    if !checked_online {
+        unlock_offline(&idle, session.unlock_verifier.as_deref(), &password)?;
+    }
+
+    state.unlock();
+    idle.touch();
+
+    app.emit("session-unlocked", ())
+        .map_err(|e| format!("Failed to emit event: {}", e))?;
+
+    Ok(())
+}
+
+#[tauri::command]
+pub fn get_lock_settings(idle: State<'_, IdleLock>) -> LockSettings {
+    idle.settings()
+}
+
+#[tauri::command]
+pub fn set_lock_settings(
+    settings: LockSettings,
+    idle: State<'_, IdleLock>,
+    state: State<'_, AuthState>,
+    app: tauri::AppHandle,
+) -> Result<(), AppError> {
+    // Changing the policy is itself a privileged action
+    state.authorize(Permission::ManageSettings)?;
+
+    settings.save(&app)?;
+    *idle.settings.write().unwrap_or_else(|e| e.into_inner()) = settings;
+    idle.touch();
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn idle() -> IdleLock {
+        IdleLock::new(LockSettings::default())
+    }
+
+    #[test]
+    fn offline_unlock_checks_the_local_verifier() {
+        let verifier = password::hash_password("correct horse").unwrap();
+        let idle = idle();
+        assert!(matches!(
+            unlock_offline(&idle, Some(&verifier), "wrong"),
+            Err(AppError::InvalidCredentials)
+        ));
+        assert!(unlock_offline(&idle, Some(&verifier), "correct horse").is_ok());
+    }
+
+    #[test]
+    fn offline_unlock_needs_a_verifier() {
+        assert!(matches!(
+            unlock_offline(&idle(), None, "correct horse"),
+            Err(AppError::Offline)
+        ));
+    }
+
+    #[test]
+    fn repeated_wrong_passwords_lock_offline_unlock() {
+        let verifier = password::hash_password("correct horse").unwrap();
+        let idle = idle();
+        for _ in 1..lockout::FREE_ATTEMPTS {
+            assert!(matches!(
+                unlock_offline(&idle, Some(&verifier), "wrong"),
+                Err(AppError::InvalidCredentials)
+            ));
+        }
+        assert!(matches!(
+            unlock_offline(&idle, Some(&verifier), "wrong"),
+            Err(AppError::LockedOut { .. })
+        ));
+        assert!(matches!(
+            unlock_offline(&idle, Some(&verifier), "correct horse"),
+            Err(AppError::LockedOut { .. })
+        ));
+    }
+}
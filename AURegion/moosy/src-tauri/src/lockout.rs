@@ -0,0 +1,166 @@
+use chrono::{DateTime, Duration, Utc};
+use sqlx::PgPool;
+
+use crate::error::AppError;
+
+// Failures allowed before the account is locked at all
+pub const FREE_ATTEMPTS: i32 = 3;
+// First lockout, doubled with every further failure up to the cap
+const BASE_LOCKOUT_SECS: i64 = 30;
+const MAX_LOCKOUT_SECS: i64 = 15 * 60;
+// The failure counter starts over once the last failure is older than this
+pub const ATTEMPT_WINDOW_SECS: i64 = 60 * 60;
+
+// Brute-force bookkeeping kept on `public.login` and `public.login_pin`
+#[derive(sqlx::FromRow, Clone, Debug)]
+pub struct LoginAttempts {
+    pub failed_attempts: i32,
+    pub locked_until: Option<DateTime<Utc>>,
+}
+
+impl LoginAttempts {
+    // Refuse any attempt, right or wrong, while the account is locked
+    pub fn check(&self) -> Result<(), AppError> {
+        match self.locked_until {
+            Some(until) if until > Utc::now() => Err(AppError::LockedOut {
+                retry_after_secs: (until - Utc::now()).num_seconds().max(1) as u64,
+            }),
+            _ => Ok(()),
+        }
+    }
+}
+
+// How long to lock the account after `failed_attempts` consecutive failures
+fn lockout_duration(failed_attempts: i32) -> Option<Duration> {
+    if failed_attempts < FREE_ATTEMPTS {
+        return None;
+    }
+    let doublings = (failed_attempts - FREE_ATTEMPTS).min(16) as u32;
+    let secs = BASE_LOCKOUT_SECS
+        .saturating_mul(1 << doublings)
+        .min(MAX_LOCKOUT_SECS);
+    Some(Duration::seconds(secs))
+}
+
+// The state after the `failed_attempts`th consecutive failure, made at `at`
+pub fn after_failure(failed_attempts: i32, at: DateTime<Utc>) -> LoginAttempts {
+    LoginAttempts {
+        failed_attempts,
+        locked_until: lockout_duration(failed_attempts).map(|duration| at + duration),
+    }
+}
+
+// Count a failed attempt. Returns the lockout error once the account crosses the threshold,
+// so the caller can report it instead of a plain wrong-password error. The counter is bumped
+// in the update itself, so wrong guesses made in parallel all count.
+pub async fn record_failure(pool: &PgPool, login_id: sqlx::types::Uuid) -> Result<(), AppError> {
+    let (failed_attempts, now): (i32, DateTime<Utc>) = sqlx::query_as(
+        "UPDATE public.login
+         SET failed_attempts = CASE
+                 WHEN last_failed_at > NOW() - make_interval(secs => $1) THEN failed_attempts + 1
+                 ELSE 1
+             END,
+             last_failed_at = NOW()
+         WHERE id = $2
+         RETURNING failed_attempts, last_failed_at",
+    )
+    .bind(ATTEMPT_WINDOW_SECS as f64)
+    .bind(login_id)
+    .fetch_one(pool)
+    .await?;
+
+    // A lockout set by a failure counted in parallel is never shortened
+    let attempts = after_failure(failed_attempts, now);
+    if let Some(locked_until) = attempts.locked_until {
+        sqlx::query(
+            "UPDATE public.login SET locked_until = GREATEST(locked_until, $1) WHERE id = $2",
+        )
+        .bind(locked_until)
+        .bind(login_id)
+        .execute(pool)
+        .await?;
+    }
+
+    attempts.check()
+}
+
+// Clear the counter after a successful attempt
+pub async fn record_success(pool: &PgPool, login_id: sqlx::types::Uuid) -> Result<(), AppError> {
+    sqlx::query(
+        "UPDATE public.login
+         SET failed_attempts = 0, last_failed_at = NULL, locked_until = NULL
+         WHERE id = $1",
+    )
+    .bind(login_id)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+// The token-based refresh honours a lockout on the account behind the token too
+pub async fn check_token(pool: &PgPool, token: &str) -> Result<(), AppError> {
+    let attempts: Option<LoginAttempts> = sqlx::query_as(
+        "SELECT l.failed_attempts, l.locked_until
+         FROM public.tokens t
+         JOIN public.login l ON l.id = t.login_id
+         WHERE t.token = $1 AND t.purpose = 'session'",
+    )
+    .bind(token)
+    .fetch_optional(pool)
+    .await?;
+
+    match attempts {
+        Some(attempts) => attempts.check(),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secs(failed_attempts: i32) -> Option<i64> {
+        lockout_duration(failed_attempts).map(|duration| duration.num_seconds())
+    }
+
+    #[test]
+    fn free_attempts_are_not_locked() {
+        for failed_attempts in 0..FREE_ATTEMPTS {
+            assert_eq!(secs(failed_attempts), None, "{}", failed_attempts);
+        }
+    }
+
+    #[test]
+    fn lockout_doubles_from_the_base() {
+        assert_eq!(secs(FREE_ATTEMPTS), Some(BASE_LOCKOUT_SECS));
+        assert_eq!(secs(FREE_ATTEMPTS + 1), Some(BASE_LOCKOUT_SECS * 2));
+        assert_eq!(secs(FREE_ATTEMPTS + 2), Some(BASE_LOCKOUT_SECS * 4));
+        assert_eq!(secs(FREE_ATTEMPTS + 4), Some(BASE_LOCKOUT_SECS * 16));
+    }
+
+    #[test]
+    fn lockout_is_capped() {
+        assert_eq!(secs(FREE_ATTEMPTS + 5), Some(MAX_LOCKOUT_SECS));
+        assert_eq!(secs(FREE_ATTEMPTS + 16), Some(MAX_LOCKOUT_SECS));
+        assert_eq!(secs(i32::MAX), Some(MAX_LOCKOUT_SECS));
+    }
+
+    #[test]
+    fn only_a_lockout_in_the_future_refuses() {
+        let attempts = |locked_until| LoginAttempts {
+            failed_attempts: FREE_ATTEMPTS,
+            locked_until,
+        };
+        assert!(attempts(None).check().is_ok());
+        assert!(attempts(Some(Utc::now() - Duration::seconds(1)))
+            .check()
+            .is_ok());
+        match attempts(Some(Utc::now() + Duration::seconds(90))).check() {
+            Err(AppError::LockedOut { retry_after_secs }) => {
+                assert!((89..=90).contains(&retry_after_secs))
+            }
+            result => panic!("{:?}", result),
+        }
+    }
+}
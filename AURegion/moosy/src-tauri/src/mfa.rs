@@ -0,0 +1,433 @@
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use tauri::State;
+
+use crate::{
+    clear_auth,
+    config::{Config, Feature},
+    crypto,
+    error::AppError,
+    lockout::{self, LoginAttempts},
+    permissions::Permission,
+    session_username, sign_in, totp, verify_credentials, AuthData, AuthState,
+};
+
+// How long the second step of a login stays open after the password was accepted
+const CHALLENGE_TTL: Duration = Duration::from_secs(5 * 60);
+const RECOVERY_CODE_COUNT: usize = 10;
+const INVALID_CODE: &str = "Invalid verification code";
+
+// TOTP secrets are sealed with this key before they reach the database. It is shared by
+// every terminal of a business and never stored alongside the data it protects.
+fn encryption_key(config: &Config) -> Result<[u8; crypto::KEY_LEN], AppError> {
+    let key = config
+        .get()
+        .secrets
+        .mfa_encryption_key
+        .ok_or_else(|| "Two-factor authentication is not configured on this device".to_string())?;
+    crypto::parse_key(&key)
+}
+
+// A login whose password checked out and is waiting for its second factor
+#[derive(Clone)]
+struct Challenge {
+    login_id: sqlx::types::Uuid,
+    username: String,
+    password_hash: String,
+    // See `Session::unlock_verifier`; the password itself isn't kept
+    unlock_verifier: String,
+    created_at: Instant,
+}
+
+// Pending second steps, keyed by the challenge id handed to the frontend
+#[derive(Default)]
+pub struct MfaChallenges(Mutex<HashMap<String, Challenge>>);
+
+impl MfaChallenges {
+    fn insert(&self, challenge: Challenge) -> String {
+        let mut id = [0u8; 16];
+        OsRng.fill_bytes(&mut id);
+        let id = hex::encode(id);
+
+        let mut challenges = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        challenges.retain(|_, challenge| challenge.created_at.elapsed() < CHALLENGE_TTL);
+        challenges.insert(id.clone(), challenge);
+        id
+    }
+
+    fn get(&self, id: &str) -> Option<Challenge> {
+        let challenges = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        challenges
+            .get(id)
+            .filter(|challenge| challenge.created_at.elapsed() < CHALLENGE_TTL)
+            .cloned()
+    }
+
+    fn remove(&self, id: &str) {
+        self.0.lock().unwrap_or_else(|e| e.into_inner()).remove(id);
+    }
+}
+
+// Result of `login`: either a session, or a challenge to finish with `verify_login_mfa`.
+// Untagged so a plain login still returns the same `AuthData` shape as before.
+#[derive(serde::Serialize)]
+#[serde(untagged)]
+pub enum LoginResult {
+    Authenticated(Box<AuthData>),
+    MfaRequired {
+        mfa_required: bool,
+        challenge_id: String,
+    },
+}
+
+// Hold the login at the second step when the account has two-factor enabled. Returns
+// None when the password alone is enough.
+pub async fn challenge_if_enrolled(
+    pool: &PgPool,
+    challenges: &MfaChallenges,
+    username: &str,
+    password_hash: &str,
+    unlock_verifier: &str,
+) -> Result<Option<LoginResult>, AppError> {
+    let login_id: Option<sqlx::types::Uuid> = sqlx::query_scalar(
+        "SELECT l.id FROM public.login l
+         JOIN public.login_mfa m ON m.login_id = l.id
+         WHERE l.username = $1 AND m.enabled = TRUE",
+    )
+    .bind(username)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(login_id.map(|login_id| {
+        let challenge_id = challenges.insert(Challenge {
+            login_id,
+            username: username.to_string(),
+            password_hash: password_hash.to_string(),
+            unlock_verifier: unlock_verifier.to_string(),
+            created_at: Instant::now(),
+        });
+        LoginResult::MfaRequired {
+            mfa_required: true,
+            challenge_id,
+        }
+    }))
+}
+
+// Second step of `login`. The session token is only minted once the code verifies.
+#[tauri::command]
+pub async fn verify_login_mfa(
+    challenge_id: String,
+    code: String,
+    pool: State<'_, PgPool>,
+    challenges: State<'_, MfaChallenges>,
+    config: State<'_, Config>,
+    app: tauri::AppHandle,
+) -> Result<AuthData, AppError> {
+    let challenge = challenges
+        .get(&challenge_id)
+        .ok_or(AppError::SessionExpired)?;
+    let login_id = challenge.login_id;
+
+    let attempts: LoginAttempts = sqlx::query_as(
+        "SELECT failed_attempts, locked_until FROM public.login WHERE id = $1",
+    )
+    .bind(login_id)
+    .fetch_one(&*pool)
+    .await?;
+    attempts.check()?;
+
+    if !verify_second_factor(&pool, &config, login_id, &code).await? {
+        lockout::record_failure(&pool, login_id).await?;
+        return Err(AppError::Invalid(INVALID_CODE.to_string()));
+    }
+    if attempts.failed_attempts > 0 {
+        lockout::record_success(&pool, login_id).await?;
+    }
+
+    challenges.remove(&challenge_id);
+
+    sign_in(
+        &pool,
+        &app,
+        &challenge.username,
+        &challenge.password_hash,
+        challenge.unlock_verifier,
+    )
+    .await
+}
+
+// Accept either a current TOTP code or an unused recovery code
+async fn verify_second_factor(
+    pool: &PgPool,
+    config: &Config,
+    login_id: sqlx::types::Uuid,
+    code: &str,
+) -> Result<bool, AppError> {
+    // A TOTP failure, e.g. no key configured on this device, mustn't keep a recovery code
+    // from working
+    let totp = verify_totp(pool, config, login_id, code, true).await;
+    if let Ok(true) = totp {
+        return Ok(true);
+    }
+
+    let used = sqlx::query(
+        "UPDATE public.login_recovery_code SET used_at = NOW()
+         WHERE login_id = $1 AND code_hash = $2 AND used_at IS NULL",
+    )
+    .bind(login_id)
+    .bind(recovery_code_hash(login_id, code))
+    .execute(pool)
+    .await?;
+    if used.rows_affected() > 0 {
+        return Ok(true);
+    }
+
+    totp
+}
+
+// Check a TOTP code against the stored secret, enabled or still being enrolled, and
+// remember its time step so it can't be used twice
+async fn verify_totp(
+    pool: &PgPool,
+    config: &Config,
+    login_id: sqlx::types::Uuid,
+    code: &str,
+    enabled: bool,
+) -> Result<bool, AppError> {
+    let row: Option<(String, Option<i64>)> = sqlx::query_as(
+        "SELECT secret_ciphertext, last_used_step FROM public.login_mfa
+         WHERE login_id = $1 AND enabled = $2",
+    )
+    .bind(login_id)
+    .bind(enabled)
+    .fetch_optional(pool)
+    .await?;
+
+    let (secret_ciphertext, last_used_step) = match row {
+        Some(row) => row,
+        None => return Ok(false),
+    };
+
+    let sealed = hex::decode(secret_ciphertext)
+        .map_err(|_| "Stored two-factor secret is corrupt".to_string())?;
+    let secret = crypto::open(&encryption_key(config)?, &sealed, login_id.as_bytes())?;
+
+    let step = match totp::verify(&secret, code, chrono::Utc::now().timestamp()) {
+        Some(step) if last_used_step.map_or(true, |last| step > last) => step,
+        _ => return Ok(false),
+    };
+
+    // Only one of two requests racing with the same code gets to record its step
+    let recorded = sqlx::query(
+        "UPDATE public.login_mfa SET last_used_step = $1
+         WHERE login_id = $2 AND enabled = $3
+         AND (last_used_step IS NULL OR last_used_step < $1)",
+    )
+    .bind(step)
+    .bind(login_id)
+    .bind(enabled)
+    .execute(pool)
+    .await?;
+
+    Ok(recorded.rows_affected() > 0)
+}
+
+fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+fn recovery_code_hash(login_id: sqlx::types::Uuid, code: &str) -> String {
+    let code = normalize_recovery_code(code);
+    hex::encode(Sha256::digest(format!("{}:{}", login_id, code).as_bytes()))
+}
+
+// Ten characters of base32, shown as `xxxxx-xxxxx`
+fn generate_recovery_code() -> String {
+    let mut bytes = [0u8; 7];
+    OsRng.fill_bytes(&mut bytes);
+    let code = totp::encode_secret(&bytes).to_ascii_lowercase();
+    format!("{}-{}", &code[..5], &code[5..10])
+}
+
+// The login behind the current session, for managing its own second factor
+async fn session_login(
+    pool: &PgPool,
+    state: &AuthState,
+    app: &tauri::AppHandle,
+) -> Result<(sqlx::types::Uuid, String), AppError> {
+    let session = state.authorize(Permission::ManageTwoFactor)?;
+
+    let username = match session_username(pool, &session.token).await? {
+        Some(username) => username,
+        None => {
+            clear_auth(app)?;
+            return Err(AppError::SessionExpired);
+        }
+    };
+
+    let login_id: sqlx::types::Uuid =
+        sqlx::query_scalar("SELECT id FROM public.login WHERE username = $1")
+            .bind(&username)
+            .fetch_one(pool)
+            .await?;
+
+    Ok((login_id, username))
+}
+
+#[derive(serde::Serialize)]
+pub struct MfaEnrolment {
+    // Base32 secret for typing in by hand
+    pub secret: String,
+    pub provisioning_uri: String,
+    // SVG rendering of the provisioning URI for scanning
+    pub qr_svg: String,
+}
+
+#[tauri::command]
+pub async fn get_mfa_status(
+    pool: State<'_, PgPool>,
+    state: State<'_, AuthState>,
+    app: tauri::AppHandle,
+) -> Result<bool, AppError> {
+    let (login_id, _) = session_login(&pool, &state, &app).await?;
+
+    let enabled: Option<bool> =
+        sqlx::query_scalar("SELECT enabled FROM public.login_mfa WHERE login_id = $1")
+            .bind(login_id)
+            .fetch_optional(&*pool)
+            .await?;
+
+    Ok(enabled.unwrap_or(false))
+}
+
+// Start enrolment with a new secret. Nothing changes for login until the first code is
+// confirmed with `confirm_mfa_enrolment`.
+#[tauri::command]
+pub async fn begin_mfa_enrolment(
+    pool: State<'_, PgPool>,
+    state: State<'_, AuthState>,
+    config: State<'_, Config>,
+    app: tauri::AppHandle,
+) -> Result<MfaEnrolment, AppError> {
+    // Accounts already enrolled keep being challenged at login, but no new ones start
+    config.require(Feature::TwoFactor)?;
+    let (login_id, username) = session_login(&pool, &state, &app).await?;
+
+    let secret = totp::generate_secret();
+    let sealed = crypto::seal(&encryption_key(&config)?, &secret, login_id.as_bytes())?;
+
+    let started = sqlx::query(
+        "INSERT INTO public.login_mfa (login_id, secret_ciphertext) VALUES ($1, $2)
+         ON CONFLICT (login_id) DO UPDATE
+         SET secret_ciphertext = EXCLUDED.secret_ciphertext, last_used_step = NULL,
+             created_at = NOW()
+         WHERE public.login_mfa.enabled = FALSE",
+    )
+    .bind(login_id)
+    .bind(hex::encode(sealed))
+    .execute(&*pool)
+    .await?;
+
+    if started.rows_affected() == 0 {
+        return Err(AppError::Conflict(
+            "Two-factor authentication is already enabled".to_string(),
+        ));
+    }
+
+    let provisioning_uri = totp::provisioning_uri(&secret, &username);
+    let qr_svg = qrcode::QrCode::new(provisioning_uri.as_bytes())
+        .map_err(|e| format!("Failed to build QR code: {}", e))?
+        .render::<qrcode::render::svg::Color>()
+        .min_dimensions(200, 200)
+        .build();
+
+    Ok(MfaEnrolment {
+        secret: totp::encode_secret(&secret),
+        provisioning_uri,
+        qr_svg,
+    })
+}
+
+// Finish enrolment with a code from the authenticator app. Returns the recovery codes,
+// which are only ever shown this once.
+#[tauri::command]
+pub async fn confirm_mfa_enrolment(
+    code: String,
+    pool: State<'_, PgPool>,
+    state: State<'_, AuthState>,
+    config: State<'_, Config>,
+    app: tauri::AppHandle,
+) -> Result<Vec<String>, AppError> {
+    let (login_id, _) = session_login(&pool, &state, &app).await?;
+
+    if !verify_totp(&pool, &config, login_id, &code, false).await? {
+        return Err(AppError::Invalid(INVALID_CODE.to_string()));
+    }
+
+    let codes: Vec<String> = (0..RECOVERY_CODE_COUNT)
+        .map(|_| generate_recovery_code())
+        .collect();
+
+    let mut tx = pool.begin().await?;
+
+    sqlx::query(
+        "UPDATE public.login_mfa SET enabled = TRUE, confirmed_at = NOW() WHERE login_id = $1",
+    )
+    .bind(login_id)
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query("DELETE FROM public.login_recovery_code WHERE login_id = $1")
+        .bind(login_id)
+        .execute(&mut *tx)
+        .await?;
+
+    for code in &codes {
+        sqlx::query("INSERT INTO public.login_recovery_code (login_id, code_hash) VALUES ($1, $2)")
+            .bind(login_id)
+            .bind(recovery_code_hash(login_id, code))
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    tx.commit().await?;
+
+    Ok(codes)
+}
+
+// Turn two-factor off again. Needs the account password, not just an open session.
+#[tauri::command]
+pub async fn disable_mfa(
+    password: String,
+    pool: State<'_, PgPool>,
+    state: State<'_, AuthState>,
+    app: tauri::AppHandle,
+) -> Result<(), AppError> {
+    let (login_id, username) = session_login(&pool, &state, &app).await?;
+
+    verify_credentials(&pool, &username, &password).await?;
+
+    let mut tx = pool.begin().await?;
+
+    sqlx::query("DELETE FROM public.login_recovery_code WHERE login_id = $1")
+        .bind(login_id)
+        .execute(&mut *tx)
+        .await?;
+
+    sqlx::query("DELETE FROM public.login_mfa WHERE login_id = $1")
+        .bind(login_id)
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+    Ok(())
+}
@@ -0,0 +1,235 @@
+use sqlx::{
+    sqlite::{Sqlite, SqliteConnectOptions, SqlitePool, SqlitePoolOptions},
+    FromRow, Transaction,
+};
+use tauri::Manager;
+use tokio::sync::mpsc;
+
+use crate::{delta::Delta, error::AppError, AuthData, Booking};
+
+// The bookings of the active company as replay needs them to spot conflicts, plus the
+// outbox of changes made while offline. The cached state itself is served from auth.json.
+// `bookings` and the other entity tables of older versions are dropped.
+const SCHEMA: &str = "
+DROP TABLE IF EXISTS customers;
+DROP TABLE IF EXISTS services;
+DROP TABLE IF EXISTS bookings;
+DROP TABLE IF EXISTS timetable;
+CREATE TABLE IF NOT EXISTS booking_base (
+    company_id TEXT NOT NULL,
+    id TEXT NOT NULL,
+    status TEXT NOT NULL,
+    start_time TEXT NOT NULL,
+    end_time TEXT NOT NULL,
+    PRIMARY KEY (company_id, id)
+);
+CREATE TABLE IF NOT EXISTS mirrored_companies (
+    company_id TEXT PRIMARY KEY,
+    mirrored_at TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS outbox (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    company_id TEXT NOT NULL,
+    operator_id TEXT NOT NULL,
+    command TEXT NOT NULL,
+    payload TEXT NOT NULL,
+    base TEXT,
+    local_id TEXT,
+    remote_id TEXT,
+    status TEXT NOT NULL DEFAULT 'pending'
+        CHECK (status IN ('pending', 'synced', 'conflict', 'failed', 'dismissed')),
+    detail TEXT,
+    created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%SZ', 'now')),
+    synced_at TEXT
+);
+";
+
+// What a booking looked like locally when an offline change was made to it. Replay
+// compares this with the server to spot bookings that were changed elsewhere meanwhile.
+#[derive(serde::Serialize, serde::Deserialize, FromRow, Clone, Debug)]
+pub struct BookingBase {
+    pub status: String,
+    pub start_time: String,
+    pub end_time: String,
+}
+
+// Changes to the mirrored bookings, applied one after another by the writer task so a
+// later change can't be overtaken by an earlier one
+enum Write {
+    Store {
+        company_id: String,
+        bookings: Vec<Booking>,
+    },
+    Apply {
+        company_id: String,
+        deltas: Vec<Delta>,
+    },
+    Clear,
+}
+
+pub struct Mirror {
+    pub pool: SqlitePool,
+    writes: mpsc::UnboundedSender<Write>,
+}
+
+impl Mirror {
+    // Open (or create) `offline.db` in the app data dir and start its writer task
+    pub async fn open(app: &tauri::AppHandle) -> Result<Mirror, AppError> {
+        let dir = app.path().app_data_dir().map_err(|e| e.to_string())?;
+        std::fs::create_dir_all(&dir)
+            .map_err(|e| format!("Failed to create app data directory: {}", e))?;
+
+        let options = SqliteConnectOptions::new()
+            .filename(dir.join("offline.db"))
+            .create_if_missing(true);
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect_with(options)
+            .await
+            .map_err(|e| format!("Failed to open offline database: {}", e))?;
+
+        sqlx::raw_sql(SCHEMA)
+            .execute(&pool)
+            .await
+            .map_err(|e| format!("Failed to prepare offline database: {}", e))?;
+
+        let (writes, mut queued) = mpsc::unbounded_channel();
+        let writer = pool.clone();
+        tauri::async_runtime::spawn(async move {
+            // A failed write only costs conflict detection for the bookings involved
+            while let Some(write) = queued.recv().await {
+                if let Err(e) = apply_write(&writer, write).await {
+                    log::warn!("{}", e);
+                }
+            }
+        });
+
+        Ok(Mirror { pool, writes })
+    }
+
+    fn queue(&self, write: Write) {
+        // The writer task only stops with the runtime
+        let _ = self.writes.send(write);
+    }
+
+    pub async fn booking_base(
+        &self,
+        company_id: &str,
+        booking_id: &str,
+    ) -> Result<Option<BookingBase>, AppError> {
+        sqlx::query_as(
+            "SELECT status, start_time, end_time FROM booking_base WHERE company_id = ? AND id = ?",
+        )
+        .bind(company_id)
+        .bind(booking_id)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|e| AppError::Message(format!("Offline database error: {}", e)))
+    }
+}
+
+async fn apply_write(pool: &SqlitePool, write: Write) -> Result<(), AppError> {
+    let mut tx = pool
+        .begin()
+        .await
+        .map_err(|e| format!("Offline database error: {}", e))?;
+
+    match write {
+        // Replace the mirrored bookings of the active company
+        Write::Store {
+            company_id,
+            bookings,
+        } => {
+            sqlx::query("DELETE FROM booking_base WHERE company_id = ?")
+                .bind(&company_id)
+                .execute(&mut *tx)
+                .await
+                .map_err(|e| format!("Failed to update offline copy: {}", e))?;
+
+            for booking in &bookings {
+                insert_booking(&mut tx, &company_id, booking).await?;
+            }
+
+            sqlx::query(
+                "INSERT INTO mirrored_companies (company_id, mirrored_at)
+                 VALUES (?, strftime('%Y-%m-%dT%H:%M:%SZ', 'now'))
+                 ON CONFLICT (company_id) DO UPDATE SET mirrored_at = excluded.mirrored_at",
+            )
+            .bind(&company_id)
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| format!("Failed to update offline copy: {}", e))?;
+        }
+        // Patch them with the deltas a command just published
+        Write::Apply { company_id, deltas } => {
+            for delta in &deltas {
+                match delta {
+                    Delta::BookingUpserted { booking } => {
+                        insert_booking(&mut tx, &company_id, booking).await?
+                    }
+                    Delta::BookingRemoved { id } => {
+                        sqlx::query("DELETE FROM booking_base WHERE company_id = ? AND id = ?")
+                            .bind(&company_id)
+                            .bind(id)
+                            .execute(&mut *tx)
+                            .await
+                            .map_err(|e| format!("Failed to update offline copy: {}", e))?;
+                    }
+                    Delta::CustomerUpserted { .. } | Delta::CustomerRemoved { .. } => {}
+                }
+            }
+        }
+        // Drop every mirrored row. The outbox is kept so nothing waiting to sync is lost.
+        Write::Clear => {
+            for table in ["booking_base", "mirrored_companies"] {
+                sqlx::query(&format!("DELETE FROM {}", table))
+                    .execute(&mut *tx)
+                    .await
+                    .map_err(|e| format!("Failed to clear offline copy: {}", e))?;
+            }
+        }
+    }
+
+    tx.commit()
+        .await
+        .map_err(|e| AppError::Message(format!("Failed to update offline copy: {}", e)))
+}
+
+async fn insert_booking(
+    tx: &mut Transaction<'_, Sqlite>,
+    company_id: &str,
+    booking: &Booking,
+) -> Result<(), AppError> {
+    sqlx::query(
+        "INSERT OR REPLACE INTO booking_base (company_id, id, status, start_time, end_time)
+         VALUES (?, ?, ?, ?, ?)",
+    )
+    .bind(company_id)
+    .bind(&booking.id)
+    .bind(&booking.status.name)
+    .bind(&booking.start_time)
+    .bind(&booking.end_time)
+    .execute(&mut **tx)
+    .await
+    .map_err(|e| format!("Failed to update offline copy: {}", e))?;
+    Ok(())
+}
+
+// Mirror writes happen off the calling command, in the order they are queued
+pub fn store_in_background(app: &tauri::AppHandle, data: &AuthData) {
+    app.state::<Mirror>().queue(Write::Store {
+        company_id: data.company.id.clone(),
+        bookings: data.bookings.clone().unwrap_or_default(),
+    });
+}
+
+pub fn apply_in_background(app: &tauri::AppHandle, company_id: &str, deltas: &[Delta]) {
+    app.state::<Mirror>().queue(Write::Apply {
+        company_id: company_id.to_string(),
+        deltas: deltas.to_vec(),
+    });
+}
+
+pub fn clear_in_background(app: &tauri::AppHandle) {
+    app.state::<Mirror>().queue(Write::Clear);
+}
@@ -0,0 +1,105 @@
+#[cfg(feature = "dev-notifier")]
+use std::{io::Write, path::PathBuf};
+#[cfg(feature = "dev-notifier")]
+use tauri::Manager;
+
+use crate::{config::Feature, error::AppError};
+
+#[cfg(all(feature = "dev-notifier", not(debug_assertions)))]
+compile_error!("dev-notifier writes reset codes to disk and must not be enabled in release builds");
+
+// A message for a person outside the app, e.g. a password reset code
+#[derive(serde::Serialize, Clone, Debug)]
+pub struct Notice {
+    pub recipient: String,
+    pub subject: String,
+    pub body: String,
+}
+
+// Delivery channel for notices. Swap in an email or SMS implementation when one exists.
+pub trait Notifier: Send + Sync {
+    fn send(&self, notice: &Notice) -> Result<(), AppError>;
+}
+
+// Managed state wrapper so commands can take `State<'_, Notifications>`. None when the
+// build has no way to deliver a notice.
+pub struct Notifications(pub Option<Box<dyn Notifier>>);
+
+impl Notifications {
+    // There is no real delivery channel yet, so only development builds with the
+    // `dev-notifier` feature can send anything
+    pub fn new(app: &tauri::AppHandle) -> Result<Self, AppError> {
+        #[cfg(feature = "dev-notifier")]
+        return Ok(Notifications(Some(Box::new(FileNotifier::new(app)?))));
+
+        #[cfg(not(feature = "dev-notifier"))]
+        {
+            let _ = app;
+            Ok(Notifications(None))
+        }
+    }
+
+    // Refuse a password reset up front when its code couldn't be delivered
+    pub fn require(&self) -> Result<(), AppError> {
+        match self.0 {
+            Some(_) => Ok(()),
+            None => Err(AppError::FeatureDisabled(Feature::PasswordReset)),
+        }
+    }
+
+    pub fn send(&self, notice: &Notice) -> Result<(), AppError> {
+        match &self.0 {
+            Some(notifier) => notifier.send(notice),
+            None => Err(AppError::FeatureDisabled(Feature::PasswordReset)),
+        }
+    }
+}
+
+// Development stand-in that appends notices to `notifications.log` in the app log dir, so
+// a reset code can be read off the machine without any outside service. It writes the code
+// to disk, which is why it only exists with the `dev-notifier` feature.
+#[cfg(feature = "dev-notifier")]
+pub struct FileNotifier {
+    path: PathBuf,
+}
+
+#[cfg(feature = "dev-notifier")]
+impl FileNotifier {
+    pub fn new(app: &tauri::AppHandle) -> Result<Self, AppError> {
+        let dir = app.path().app_log_dir().map_err(|e| e.to_string())?;
+        Ok(FileNotifier {
+            path: dir.join("notifications.log"),
+        })
+    }
+}
+
+#[cfg(feature = "dev-notifier")]
+impl Notifier for FileNotifier {
+    fn send(&self, notice: &Notice) -> Result<(), AppError> {
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)
+                .map_err(|e| format!("Failed to create log directory: {}", e))?;
+        }
+
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .map_err(|e| format!("Failed to open notifications log: {}", e))?;
+
+        writeln!(
+            file,
+            "[{}] To: {}\nSubject: {}\n{}\n",
+            chrono::Utc::now().to_rfc3339(),
+            notice.recipient,
+            notice.subject,
+            notice.body
+        )
+        .map_err(|e| format!("Failed to write notification: {}", e))?;
+
+        // The body can hold a secret, so only say where it went
+        log::info!("Notification written to {}", self.path.display());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,295 @@
+use chrono::NaiveTime;
+use sqlx::{PgPool, Postgres, Transaction};
+use tauri::State;
+
+use crate::{
+    config::{Config, Feature},
+    error::AppError,
+    password, sign_in, AuthData,
+};
+
+// Starter services without a catalogue are filed under this one
+const DEFAULT_CATALOGUE: &str = "General";
+
+// Everything needed to open a new business from the app
+#[derive(serde::Deserialize, Debug)]
+pub struct BusinessRegistration {
+    pub business_name: String,
+    pub description: Option<String>,
+    // ISO 4217 code of a row in `public.currency`, e.g. "AUD"
+    pub currency: String,
+    pub first_name: String,
+    pub last_name: String,
+    pub username: String,
+    pub password: String,
+    pub timetable: Vec<OpeningHours>,
+    #[serde(default)]
+    pub services: Vec<StarterService>,
+}
+
+// One row of `public.timetable`; the ids are assigned on insert
+#[derive(serde::Deserialize, Debug)]
+pub struct OpeningHours {
+    pub day_of_week: i32,
+    pub start_time: String,
+    pub end_time: String,
+    #[serde(default = "default_timezone")]
+    pub timezone: String,
+}
+
+fn default_timezone() -> String {
+    "UTC".to_string()
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct StarterService {
+    pub name: String,
+    pub catalogue: Option<String>,
+    pub duration_minutes: i32,
+    pub price: f64,
+    pub description: Option<String>,
+}
+
+fn parse_time(value: &str) -> Result<NaiveTime, AppError> {
+    NaiveTime::parse_from_str(value, "%H:%M:%S")
+        .or_else(|_| NaiveTime::parse_from_str(value, "%H:%M"))
+        .map_err(|_| AppError::Invalid(format!("Invalid time: {}", value)))
+}
+
+impl BusinessRegistration {
+    fn validate(&self) -> Result<(), AppError> {
+        if self.business_name.trim().is_empty() {
+            return Err(AppError::Invalid("Business name is required".to_string()));
+        }
+        if self.first_name.trim().is_empty() || self.last_name.trim().is_empty() {
+            return Err(AppError::Invalid("Owner name is required".to_string()));
+        }
+        if self.username.trim().is_empty() {
+            return Err(AppError::Invalid("Username is required".to_string()));
+        }
+        password::validate_new_password(&self.password)?;
+        // The company state can't be loaded without at least one opening day
+        if self.timetable.is_empty() {
+            return Err(AppError::Invalid("Opening hours are required".to_string()));
+        }
+        for hours in &self.timetable {
+            if !(0..=6).contains(&hours.day_of_week) {
+                return Err(AppError::Invalid(format!(
+                    "Invalid day of week: {}",
+                    hours.day_of_week
+                )));
+            }
+            if parse_time(&hours.end_time)? <= parse_time(&hours.start_time)? {
+                return Err(AppError::Invalid(
+                    "Closing time must be after opening time".to_string(),
+                ));
+            }
+        }
+        for service in &self.services {
+            if service.name.trim().is_empty() {
+                return Err(AppError::Invalid("Service name is required".to_string()));
+            }
+            if service.duration_minutes <= 0 || service.price < 0.0 {
+                return Err(AppError::Invalid(format!(
+                    "Invalid duration or price for {}",
+                    service.name
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+// Lowercase, dash separated version of the business name for `companies.identifier`
+fn slugify(name: &str) -> String {
+    let slug = name
+        .to_lowercase()
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join("-");
+    if slug.is_empty() {
+        "business".to_string()
+    } else {
+        slug
+    }
+}
+
+// Create the company, its owner, the owner's login and role, opening hours and any starter
+// services in one transaction, then sign the new owner in.
+#[tauri::command]
+pub async fn register_business(
+    registration: BusinessRegistration,
+    pool: State<'_, PgPool>,
+    config: State<'_, Config>,
+    app: tauri::AppHandle,
+) -> Result<AuthData, AppError> {
+    config.require(Feature::Registration)?;
+    registration.validate()?;
+
+    let password_hash = password::hash_password(&registration.password)?;
+
+    let mut tx = pool.begin().await?;
+
+    create_business(&mut tx, &registration, &password_hash).await?;
+
+    tx.commit().await?;
+
+    let unlock_verifier = password::hash_password(&registration.password)?;
+    sign_in(
+        &pool,
+        &app,
+        registration.username.trim(),
+        &password_hash,
+        unlock_verifier,
+    )
+    .await
+}
+
+async fn create_business(
+    tx: &mut Transaction<'_, Postgres>,
+    registration: &BusinessRegistration,
+    password_hash: &str,
+) -> Result<(), AppError> {
+    let username = registration.username.trim();
+    let taken: bool =
+        sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM public.login WHERE username = $1)")
+            .bind(username)
+            .fetch_one(&mut **tx)
+            .await?;
+    if taken {
+        return Err(AppError::Invalid(
+            "That username is already taken".to_string(),
+        ));
+    }
+
+    let currency_id: sqlx::types::Uuid =
+        sqlx::query_scalar("SELECT id FROM public.currency WHERE code = $1")
+            .bind(registration.currency.to_uppercase())
+            .fetch_optional(&mut **tx)
+            .await?
+            .ok_or_else(|| {
+                AppError::Invalid(format!("Unknown currency: {}", registration.currency))
+            })?;
+
+    // Identifiers are unique, so a clashing name gets a random suffix
+    let company_id: sqlx::types::Uuid = sqlx::query_scalar(
+        "INSERT INTO public.companies (name, description, currency_id, identifier)
+         VALUES ($1, $2, $3, CASE
+             WHEN EXISTS (SELECT 1 FROM public.companies WHERE identifier = $4)
+             THEN $4 || '-' || substr(md5(random()::text), 1, 6)
+             ELSE $4
+         END)
+         RETURNING id",
+    )
+    .bind(registration.business_name.trim())
+    .bind(registration.description.clone().unwrap_or_default())
+    .bind(currency_id)
+    .bind(slugify(&registration.business_name))
+    .fetch_one(&mut **tx)
+    .await?;
+
+    let person_id: sqlx::types::Uuid =
+        sqlx::query_scalar("INSERT INTO public.people (company_id) VALUES ($1) RETURNING id")
+            .bind(company_id)
+            .fetch_one(&mut **tx)
+            .await?;
+
+    sqlx::query(
+        "INSERT INTO public.personal_information (person_id, first_name, last_name)
+         VALUES ($1, $2, $3)",
+    )
+    .bind(person_id)
+    .bind(registration.first_name.trim())
+    .bind(registration.last_name.trim())
+    .execute(&mut **tx)
+    .await?;
+
+    sqlx::query(
+        "INSERT INTO public.login (person_id, username, password_hash) VALUES ($1, $2, $3)",
+    )
+    .bind(person_id)
+    .bind(username)
+    .bind(password_hash)
+    .execute(&mut **tx)
+    .await?;
+
+    sqlx::query(
+        "INSERT INTO public.role (person_id, company_id, role_name) VALUES ($1, $2, 'owner')",
+    )
+    .bind(person_id)
+    .bind(company_id)
+    .execute(&mut **tx)
+    .await?;
+
+    for hours in &registration.timetable {
+        sqlx::query(
+            "INSERT INTO public.timetable (company_id, day_of_week, start_time, end_time, timezone)
+             VALUES ($1, $2, $3::time, $4::time, $5)",
+        )
+        .bind(company_id)
+        .bind(hours.day_of_week)
+        .bind(&hours.start_time)
+        .bind(&hours.end_time)
+        .bind(&hours.timezone)
+        .execute(&mut **tx)
+        .await?;
+    }
+
+    for service in &registration.services {
+        let catalogue_id = find_or_create_catalogue(
+            tx,
+            service.catalogue.as_deref().unwrap_or(DEFAULT_CATALOGUE),
+        )
+        .await?;
+
+        let service_id: sqlx::types::Uuid = sqlx::query_scalar(
+            "INSERT INTO public.services (company_id, name, duration, price, catalogue_id)
+             VALUES ($1, $2, make_interval(mins => $3), $4::numeric, $5)
+             RETURNING id",
+        )
+        .bind(company_id)
+        .bind(service.name.trim())
+        .bind(service.duration_minutes)
+        .bind(service.price)
+        .bind(catalogue_id)
+        .fetch_one(&mut **tx)
+        .await?;
+
+        // Service descriptions live in notes, like everywhere else
+        if let Some(description) = service.description.as_deref().filter(|d| !d.is_empty()) {
+            sqlx::query(
+                "INSERT INTO public.notes (text, belong_to, belong_id) VALUES ($1, 'services', $2)",
+            )
+            .bind(description)
+            .bind(service_id)
+            .execute(&mut **tx)
+            .await?;
+        }
+    }
+
+    Ok(())
+}
+
+// Catalogues are shared between companies and matched by name
+async fn find_or_create_catalogue(
+    tx: &mut Transaction<'_, Postgres>,
+    name: &str,
+) -> Result<sqlx::types::Uuid, AppError> {
+    let existing: Option<sqlx::types::Uuid> =
+        sqlx::query_scalar("SELECT id FROM public.service_catalogue WHERE name = $1 LIMIT 1")
+            .bind(name)
+            .fetch_optional(&mut **tx)
+            .await?;
+
+    match existing {
+        Some(id) => Ok(id),
+        None => sqlx::query_scalar(
+            "INSERT INTO public.service_catalogue (name) VALUES ($1) RETURNING id",
+        )
+        .bind(name)
+        .fetch_one(&mut **tx)
+        .await
+        .map_err(AppError::from),
+    }
+}
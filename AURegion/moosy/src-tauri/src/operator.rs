@@ -0,0 +1,213 @@
+use sqlx::PgPool;
+use tauri::{Emitter, State};
+
+use crate::{
+    config::{Config, Feature},
+    error::AppError,
+    lock::IdleLock,
+    lockout::{self, ATTEMPT_WINDOW_SECS},
+    password::{self, Verification},
+    permissions::{Permission, Role},
+    repo::OperatorRepo,
+    save_auth, AuthState, Session,
+};
+
+// The person working the till inside a signed-in company session
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct Operator {
+    pub person_id: String,
+    pub role: Role,
+}
+
+// PINs are short numeric codes typed at the till
+fn validate_pin(pin: &str) -> Result<(), AppError> {
+    if (4..=8).contains(&pin.len()) && pin.bytes().all(|b| b.is_ascii_digit()) {
+        Ok(())
+    } else {
+        Err(AppError::Invalid("PIN must be 4 to 8 digits".to_string()))
+    }
+}
+
+// Check `pin` against the person's till PIN. Wrong guesses lock the PIN with the same
+// backoff as a password login, and a locked PIN is refused even when it is right.
+pub async fn check_pin<R: OperatorRepo>(
+    repo: &R,
+    person_id: &str,
+    pin: &str,
+) -> Result<(), AppError> {
+    let Some(stored) = repo.operator_pin(person_id).await? else {
+        password::verify_unknown_user(pin);
+        return Err(AppError::Invalid("Invalid PIN".to_string()));
+    };
+    stored.attempts.check()?;
+
+    if password::verify_password(pin, &stored.pin_hash)? == Verification::Invalid {
+        let (failed_attempts, at) = repo
+            .count_pin_failure(person_id, ATTEMPT_WINDOW_SECS)
+            .await?;
+        let attempts = lockout::after_failure(failed_attempts, at);
+        if let Some(until) = attempts.locked_until {
+            repo.lock_pin(person_id, until).await?;
+        }
+        attempts.check()?;
+        return Err(AppError::Invalid("Invalid PIN".to_string()));
+    }
+
+    if stored.attempts.failed_attempts > 0 {
+        repo.clear_pin_failures(person_id).await?;
+    }
+    Ok(())
+}
+
+// The most privileged desktop role `person_id` holds in the session's company
+async fn company_role(
+    pool: &PgPool,
+    session: &Session,
+    person_id: &str,
+) -> Result<Role, AppError> {
+    let role_names: Vec<String> = sqlx::query_scalar(
+        "SELECT role_name FROM public.role WHERE person_id = $1::uuid AND company_id = $2::uuid",
+    )
+    .bind(person_id)
+    .bind(&session.data.company.id)
+    .fetch_all(pool)
+    .await?;
+
+    role_names
+        .iter()
+        .filter_map(|role_name| Role::from_role_name(role_name))
+        .min()
+        .ok_or_else(|| AppError::Invalid("This person does not work for this company".to_string()))
+}
+
+// Set the till PIN for a team member. Anyone can change their own PIN; setting somebody
+// else's needs the staff management permission.
+#[tauri::command]
+pub async fn set_operator_pin(
+    person_id: String,
+    pin: String,
+    pool: State<'_, PgPool>,
+    state: State<'_, AuthState>,
+    config: State<'_, Config>,
+) -> Result<(), AppError> {
+    config.require(Feature::OperatorPins)?;
+    let session = state.current()?;
+    if person_id != session.acting_person_id() {
+        state.authorize(Permission::ManageStaff)?;
+    }
+
+    validate_pin(&pin)?;
+    company_role(&pool, &session, &person_id).await?;
+
+    let pin_hash = password::hash_password(&pin)?;
+    sqlx::query(
+        "INSERT INTO public.login_pin (person_id, pin_hash) VALUES ($1::uuid, $2)
+         ON CONFLICT (person_id) DO UPDATE SET pin_hash = EXCLUDED.pin_hash, updated_at = NOW()",
+    )
+    .bind(&person_id)
+    .bind(&pin_hash)
+    .execute(&*pool)
+    .await?;
+
+    Ok(())
+}
+
+// Hand the till to another team member of the current company with their PIN. The
+// company session stays signed in; only the acting person and their permissions change.
+#[tauri::command]
+pub async fn switch_operator(
+    person_id: String,
+    pin: String,
+    pool: State<'_, PgPool>,
+    state: State<'_, AuthState>,
+    idle: State<'_, IdleLock>,
+    config: State<'_, Config>,
+    app: tauri::AppHandle,
+) -> Result<Operator, AppError> {
+    config.require(Feature::OperatorPins)?;
+    let session = state.current()?;
+
+    check_pin(&*pool, &person_id, &pin).await?;
+
+    let operator = Operator {
+        role: company_role(&pool, &session, &person_id).await?,
+        person_id,
+    };
+
+    save_auth(
+        &app,
+        Session {
+            operator: Some(operator.clone()),
+            ..session
+        },
+    )?;
+    idle.touch();
+
+    app.emit("operator-switched", &operator)
+        .map_err(|e| format!("Failed to emit event: {}", e))?;
+
+    Ok(operator)
+}
+
+#[tauri::command]
+pub fn get_operator(state: State<'_, AuthState>) -> Result<Operator, AppError> {
+    let session = state.current()?;
+    Ok(Operator {
+        person_id: session.acting_person_id().to_string(),
+        role: session.acting_role(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::MemoryRepo;
+
+    const PERSON: &str = "00000000-0000-0000-0000-0000000000a1";
+
+    fn repo() -> MemoryRepo {
+        let repo = MemoryRepo::default();
+        repo.add_pin(PERSON, &password::hash_password("1234").unwrap());
+        repo
+    }
+
+    #[tokio::test]
+    async fn right_pin_passes() {
+        assert!(check_pin(&repo(), PERSON, "1234").await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn unknown_person_is_an_invalid_pin() {
+        let err = check_pin(&repo(), "someone-else", "1234")
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AppError::Invalid(_)));
+    }
+
+    #[tokio::test]
+    async fn repeated_wrong_pins_lock_the_person_out() {
+        let repo = repo();
+        for _ in 1..lockout::FREE_ATTEMPTS {
+            let err = check_pin(&repo, PERSON, "0000").await.unwrap_err();
+            assert!(matches!(err, AppError::Invalid(_)));
+        }
+        let err = check_pin(&repo, PERSON, "0000").await.unwrap_err();
+        assert!(matches!(err, AppError::LockedOut { .. }));
+
+        // Even the right PIN is refused until the lockout runs out
+        let err = check_pin(&repo, PERSON, "1234").await.unwrap_err();
+        assert!(matches!(err, AppError::LockedOut { .. }));
+    }
+
+    #[tokio::test]
+    async fn right_pin_clears_earlier_failures() {
+        let repo = repo();
+        for _ in 1..lockout::FREE_ATTEMPTS {
+            check_pin(&repo, PERSON, "0000").await.unwrap_err();
+        }
+        check_pin(&repo, PERSON, "1234").await.unwrap();
+
+        let err = check_pin(&repo, PERSON, "0000").await.unwrap_err();
+        assert!(matches!(err, AppError::Invalid(_)));
+    }
+}
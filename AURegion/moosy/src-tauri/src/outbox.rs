@@ -0,0 +1,524 @@
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{types::Uuid, FromRow, PgPool};
+use std::collections::HashMap;
+use tauri::{Emitter, Manager, State};
+
+use crate::{
+    cancel_company_booking, create_customer,
+    error::AppError,
+    logging::Span,
+    mirror::{BookingBase, Mirror},
+    permissions::Permission,
+    record_payment, refresh_session,
+    repo::{BookingRepo, CustomerRepo, PaymentRepo},
+    save_auth,
+    status::{BookingStatus, Statuses},
+    vault::{Opened, Vault},
+    AuthData, AuthState, Checkout, ContactMethod, Customer, CustomerInput, PersonalInfo, Session,
+};
+
+// A change made while the database was unreachable, kept in the outbox until it syncs
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum Mutation {
+    CancelBooking {
+        booking_id: String,
+    },
+    // `local_id` stands in for the customer's id until the server hands out the real one
+    AddCustomer {
+        local_id: String,
+        customer: CustomerInput,
+    },
+    Checkout {
+        payment_id: Uuid,
+        checkout: Checkout,
+    },
+}
+
+impl Mutation {
+    fn command(&self) -> &'static str {
+        match self {
+            Mutation::CancelBooking { .. } => "cancel_booking",
+            Mutation::AddCustomer { .. } => "add_customer",
+            Mutation::Checkout { .. } => "checkout",
+        }
+    }
+
+    // The booking this change was made against, if any
+    fn booking_id(&self) -> Option<&str> {
+        match self {
+            Mutation::CancelBooking { booking_id } => Some(booking_id),
+            Mutation::Checkout { checkout, .. } => checkout.booking_id.as_deref(),
+            Mutation::AddCustomer { .. } => None,
+        }
+    }
+}
+
+// Random (version 4) id for rows created on this device
+pub fn new_id() -> Uuid {
+    let mut bytes = [0u8; 16];
+    OsRng.fill_bytes(&mut bytes);
+    sqlx::types::uuid::Builder::from_random_bytes(bytes).into_uuid()
+}
+
+// Record an offline change and show it in the cached state straight away
+pub async fn queue(
+    app: &tauri::AppHandle,
+    session: Session,
+    mutation: Mutation,
+) -> Result<(), AppError> {
+    let mirror = app.state::<Mirror>();
+    let company_id = session.data.company.id.clone();
+
+    // Remember what the booking looked like so replay can tell if it changed elsewhere
+    let base = match mutation.booking_id() {
+        Some(booking_id) => {
+            let base = mirror
+                .booking_base(&company_id, booking_id)
+                .await?
+                .ok_or_else(|| format!("No booking found with ID: {}", booking_id))?;
+            Some(serde_json::to_string(&base).map_err(|e| e.to_string())?)
+        }
+        None => None,
+    };
+    let local_id = match &mutation {
+        Mutation::AddCustomer { local_id, .. } => Some(local_id.clone()),
+        _ => None,
+    };
+
+    sqlx::query(
+        "INSERT INTO outbox (company_id, operator_id, command, payload, base, local_id)
+         VALUES (?, ?, ?, ?, ?, ?)",
+    )
+    .bind(&company_id)
+    .bind(session.acting_person_id())
+    .bind(mutation.command())
+    .bind(app.state::<Vault>().seal(&mutation)?)
+    .bind(base)
+    .bind(local_id)
+    .execute(&mirror.pool)
+    .await
+    .map_err(|e| format!("Failed to save the change for later: {}", e))?;
+    Span::session(mutation.command(), &session).info(format_args!("Queued while offline"));
+
+    let mut session = session;
+    apply_locally(&mut session.data, &mutation);
+    save_auth(app, session)?;
+
+    let pending = pending_count(&mirror, &company_id).await?;
+    app.emit("outbox-updated", pending)
+        .map_err(|e| AppError::Message(format!("Failed to emit event: {}", e)))
+}
+
+fn set_booking_status(data: &mut AuthData, booking_id: &str, status: BookingStatus) {
+    let bookings = data.bookings.iter_mut().flatten();
+    for booking in bookings.filter(|booking| booking.id == booking_id) {
+        booking.status.name = status.as_str().to_string();
+    }
+}
+
+// Optimistic copy of the change in the cached state, replaced by the real data after sync
+fn apply_locally(data: &mut AuthData, mutation: &Mutation) {
+    match mutation {
+        Mutation::CancelBooking { booking_id } => {
+            set_booking_status(data, booking_id, BookingStatus::Cancelled)
+        }
+        Mutation::Checkout { checkout, .. } => {
+            if let Some(booking_id) = &checkout.booking_id {
+                set_booking_status(data, booking_id, BookingStatus::Completed);
+            }
+        }
+        Mutation::AddCustomer { local_id, customer } => {
+            let mut contact_method = vec![ContactMethod {
+                id: format!("{}-phone", local_id),
+                r#type: "phone".to_string(),
+                value: customer.phone.clone(),
+                is_primary: true,
+            }];
+            if let Some(email) = &customer.email {
+                contact_method.push(ContactMethod {
+                    id: format!("{}-email", local_id),
+                    r#type: "email".to_string(),
+                    value: email.clone(),
+                    is_primary: true,
+                });
+            }
+
+            data.roles
+                .customer
+                .get_or_insert_with(Vec::new)
+                .push(Customer {
+                    id: local_id.clone(),
+                    personal_information: PersonalInfo {
+                        first_name: customer.first_name.clone(),
+                        last_name: customer.last_name.clone(),
+                        date_of_birth: customer.date_of_birth.clone(),
+                        gender: customer.gender.clone(),
+                    },
+                    notes: customer.notes.clone(),
+                    profile_image: None,
+                    contact_method: Some(contact_method),
+                    address: customer.address.clone(),
+                });
+        }
+    }
+}
+
+async fn pending_count(mirror: &Mirror, company_id: &str) -> Result<i64, AppError> {
+    sqlx::query_scalar("SELECT COUNT(*) FROM outbox WHERE company_id = ? AND status = 'pending'")
+        .bind(company_id)
+        .fetch_one(&mirror.pool)
+        .await
+        .map_err(|e| AppError::Message(format!("Offline database error: {}", e)))
+}
+
+// Outbox entries by status across all companies, for diagnostics
+pub async fn status_counts(mirror: &Mirror) -> Result<HashMap<String, i64>, AppError> {
+    let counts: Vec<(String, i64)> =
+        sqlx::query_as("SELECT status, COUNT(*) FROM outbox GROUP BY status")
+            .fetch_all(&mirror.pool)
+            .await
+            .map_err(|e| format!("Offline database error: {}", e))?;
+    Ok(counts.into_iter().collect())
+}
+
+#[derive(FromRow)]
+struct Entry {
+    id: i64,
+    company_id: String,
+    operator_id: String,
+    payload: String,
+    base: Option<String>,
+    local_id: Option<String>,
+}
+
+enum Outcome {
+    Synced(Option<String>),
+    // The server changed underneath the offline edit; nothing was applied
+    Conflict(String),
+    Failed(String),
+}
+
+#[derive(Serialize, Clone, Default, Debug)]
+pub struct SyncSummary {
+    pub synced: usize,
+    pub conflicts: usize,
+    pub failed: usize,
+}
+
+// Companies whose queued changes the session may replay
+pub fn companies(session: &Session) -> Vec<String> {
+    let mut companies: Vec<String> = session
+        .data
+        .companies
+        .iter()
+        .map(|c| c.id.clone())
+        .collect();
+    if companies.is_empty() {
+        companies.push(session.data.company.id.clone());
+    }
+    companies
+}
+
+// Replay pending changes in the order they were made. If the connection drops again the
+// rest stay pending for the next attempt.
+pub async fn replay(app: &tauri::AppHandle, companies: &[String]) -> Result<SyncSummary, AppError> {
+    let pool = app.state::<PgPool>();
+    let mirror = app.state::<Mirror>();
+    let vault = app.state::<Vault>();
+    let statuses = app.state::<Statuses>();
+
+    let entries: Vec<Entry> = sqlx::query_as(
+        "SELECT id, company_id, operator_id, payload, base, local_id FROM outbox
+         WHERE status = 'pending' ORDER BY id",
+    )
+    .fetch_all(&mirror.pool)
+    .await
+    .map_err(|e| format!("Offline database error: {}", e))?;
+
+    let mut summary = SyncSummary::default();
+    if entries.is_empty() {
+        return Ok(summary);
+    }
+
+    // Local customer ids from earlier runs, mapped to the server id when they made it
+    let mut ids: HashMap<String, Option<String>> = sqlx::query_as(
+        "SELECT local_id, CASE WHEN status = 'synced' THEN remote_id END FROM outbox
+         WHERE local_id IS NOT NULL AND status <> 'pending'",
+    )
+    .fetch_all(&mirror.pool)
+    .await
+    .map_err(|e| format!("Offline database error: {}", e))?
+    .into_iter()
+    .collect();
+
+    for entry in entries {
+        if !companies.contains(&entry.company_id) {
+            continue;
+        }
+
+        let outcome = match vault.open::<Mutation>(&entry.payload) {
+            Ok(Opened::Sealed(mutation)) => {
+                match apply_remote(&*pool, &statuses, &entry, mutation, &ids).await {
+                    Ok(outcome) => outcome,
+                    // A dropped connection isn't the change's fault; leave it and the rest pending
+                    Err(AppError::Offline) => break,
+                    Err(e) => Outcome::Failed(e.to_string()),
+                }
+            }
+            _ => Outcome::Failed("The saved change could not be read".to_string()),
+        };
+
+        let span = Span::new("replay").company(&entry.company_id);
+        match &outcome {
+            Outcome::Synced(_) => span.info(format_args!("Outbox entry {} synced", entry.id)),
+            Outcome::Conflict(detail) | Outcome::Failed(detail) => span.warn(format_args!(
+                "Outbox entry {} not applied: {}",
+                entry.id, detail
+            )),
+        }
+
+        let (status, remote_id, detail) = match outcome {
+            Outcome::Synced(remote_id) => {
+                summary.synced += 1;
+                ("synced", remote_id, None)
+            }
+            Outcome::Conflict(detail) => {
+                summary.conflicts += 1;
+                ("conflict", None, Some(detail))
+            }
+            Outcome::Failed(detail) => {
+                summary.failed += 1;
+                ("failed", None, Some(detail))
+            }
+        };
+        if let Some(local_id) = entry.local_id {
+            ids.insert(local_id, remote_id.clone());
+        }
+
+        sqlx::query(
+            "UPDATE outbox SET status = ?, remote_id = ?, detail = ?,
+                 synced_at = strftime('%Y-%m-%dT%H:%M:%SZ', 'now')
+             WHERE id = ?",
+        )
+        .bind(status)
+        .bind(remote_id)
+        .bind(detail)
+        .bind(entry.id)
+        .execute(&mirror.pool)
+        .await
+        .map_err(|e| format!("Offline database error: {}", e))?;
+    }
+
+    app.emit("sync-finished", summary.clone())
+        .map_err(|e| format!("Failed to emit event: {}", e))?;
+
+    Ok(summary)
+}
+
+// Timestamps in the cached state are JSON strings; accept them with or without an offset
+fn parse_time(value: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(value)
+        .map(|time| time.with_timezone(&Utc))
+        .ok()
+        .or_else(|| {
+            NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S%.f")
+                .ok()
+                .map(|time| time.and_utc())
+        })
+}
+
+// Describe how the booking on the server differs from what the offline change was made
+// against, or None if it is still the same
+async fn booking_conflict<R: BookingRepo>(
+    repo: &R,
+    company_id: &str,
+    booking_id: &str,
+    base: Option<&BookingBase>,
+    allow_status: &str,
+) -> Result<Option<String>, AppError> {
+    let current = repo.booking(company_id, booking_id).await?;
+
+    let (current, base) = match (current, base) {
+        (None, _) => return Ok(Some("The booking no longer exists".to_string())),
+        (Some(_), None) => return Ok(None),
+        (Some(current), Some(base)) => (current, base),
+    };
+
+    let status = current.status.unwrap_or_default();
+    if status != base.status && status != allow_status {
+        return Ok(Some(format!(
+            "The booking was changed from {} to {} on another device",
+            base.status, status
+        )));
+    }
+    let moved = parse_time(&base.start_time).is_some_and(|start| start != current.start_time)
+        || parse_time(&base.end_time).is_some_and(|end| end != current.end_time);
+    if moved {
+        return Ok(Some(
+            "The booking was rescheduled on another device".to_string(),
+        ));
+    }
+
+    Ok(None)
+}
+
+// Apply one change to Postgres. Database errors come back as `Err` so the caller can tell a
+// lost connection apart from a change the server rejected.
+async fn apply_remote<R: BookingRepo + PaymentRepo + CustomerRepo>(
+    repo: &R,
+    statuses: &Statuses,
+    entry: &Entry,
+    mutation: Mutation,
+    ids: &HashMap<String, Option<String>>,
+) -> Result<Outcome, AppError> {
+    let base: Option<BookingBase> = entry
+        .base
+        .as_deref()
+        .and_then(|base| serde_json::from_str(base).ok());
+
+    match mutation {
+        Mutation::CancelBooking { booking_id } => {
+            let conflict = booking_conflict(
+                repo,
+                &entry.company_id,
+                &booking_id,
+                base.as_ref(),
+                BookingStatus::Cancelled.as_str(),
+            )
+            .await?;
+            if let Some(detail) = conflict {
+                return Ok(Outcome::Conflict(detail));
+            }
+            cancel_company_booking(repo, statuses, &entry.company_id, &booking_id).await?;
+            Ok(Outcome::Synced(None))
+        }
+        Mutation::AddCustomer { customer, .. } => {
+            let customer_id = create_customer(repo, &entry.company_id, &customer).await?;
+            Ok(Outcome::Synced(Some(customer_id.to_string())))
+        }
+        Mutation::Checkout {
+            payment_id,
+            mut checkout,
+        } => {
+            // Already recorded by an earlier replay, together with its links and the booking's
+            // completion, which are only ever written with it
+            if repo.payment_exists(payment_id).await? {
+                return Ok(Outcome::Synced(None));
+            }
+
+            // Payments for a customer added offline go to the id the server gave them
+            match ids.get(&checkout.customer_id) {
+                Some(Some(remote_id)) => checkout.customer_id = remote_id.clone(),
+                Some(None) => {
+                    return Ok(Outcome::Failed(
+                        "The customer added offline for this payment did not sync".to_string(),
+                    ))
+                }
+                None => (),
+            }
+
+            if let Some(booking_id) = &checkout.booking_id {
+                let conflict =
+                    booking_conflict(repo, &entry.company_id, booking_id, base.as_ref(), "")
+                        .await?;
+                if let Some(detail) = conflict {
+                    return Ok(Outcome::Conflict(format!(
+                        "{}, the payment was not recorded",
+                        detail
+                    )));
+                }
+            }
+
+            record_payment(
+                repo,
+                statuses,
+                &entry.company_id,
+                &entry.operator_id,
+                payment_id,
+                &checkout,
+            )
+            .await?;
+            Ok(Outcome::Synced(None))
+        }
+    }
+}
+
+#[derive(Serialize, FromRow)]
+pub struct SyncIssue {
+    pub id: i64,
+    pub command: String,
+    pub status: String,
+    pub detail: Option<String>,
+    pub created_at: String,
+}
+
+#[derive(Serialize)]
+pub struct SyncReport {
+    pub pending: i64,
+    // Changes that conflicted or failed and haven't been dismissed yet
+    pub issues: Vec<SyncIssue>,
+}
+
+#[tauri::command]
+pub async fn get_sync_report(
+    mirror: State<'_, Mirror>,
+    state: State<'_, AuthState>,
+) -> Result<SyncReport, AppError> {
+    let company_id = state.authorize(Permission::ViewState)?.data.company.id;
+
+    let issues = sqlx::query_as(
+        "SELECT id, command, status, detail, created_at FROM outbox
+         WHERE company_id = ? AND status IN ('conflict', 'failed')
+         ORDER BY id",
+    )
+    .bind(&company_id)
+    .fetch_all(&mirror.pool)
+    .await
+    .map_err(|e| format!("Offline database error: {}", e))?;
+
+    Ok(SyncReport {
+        pending: pending_count(&mirror, &company_id).await?,
+        issues,
+    })
+}
+
+// Acknowledge a conflict or failure once it has been dealt with by hand
+#[tauri::command]
+pub async fn dismiss_sync_issue(
+    id: i64,
+    mirror: State<'_, Mirror>,
+    state: State<'_, AuthState>,
+) -> Result<(), AppError> {
+    let company_id = state.authorize(Permission::ViewState)?.data.company.id;
+
+    let dismissed = sqlx::query(
+        "UPDATE outbox SET status = 'dismissed'
+         WHERE id = ? AND company_id = ? AND status IN ('conflict', 'failed')",
+    )
+    .bind(id)
+    .bind(&company_id)
+    .execute(&mirror.pool)
+    .await
+    .map_err(|e| format!("Offline database error: {}", e))?;
+
+    if dismissed.rows_affected() == 0 {
+        return Err("Sync issue not found".to_string().into());
+    }
+    Ok(())
+}
+
+// Replay the outbox now instead of waiting for the next reconnect
+#[tauri::command]
+pub async fn sync_now(
+    state: State<'_, AuthState>,
+    app: tauri::AppHandle,
+) -> Result<SyncSummary, AppError> {
+    let session = state.authorize(Permission::ViewState)?;
+    let summary = replay(&app, &companies(&session)).await?;
+
+    refresh_session(&app.state::<PgPool>(), &app, session).await?;
+    Ok(summary)
+}
@@ -0,0 +1,165 @@
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use sha2::{Digest, Sha256};
+
+use crate::error::AppError;
+
+// Outcome of checking a password against the hash stored in `public.login.password_hash`
+#[derive(Debug, PartialEq, Eq)]
+pub enum Verification {
+    // Password matches a current Argon2id hash
+    Valid,
+    // Password matches, but the stored hash is a legacy unsalted SHA-256 and must be replaced
+    ValidNeedsRehash,
+    Invalid,
+}
+
+// Passwords shorter than this are refused whenever a new one is set
+const MIN_PASSWORD_LEN: usize = 8;
+
+pub fn validate_new_password(password: &str) -> Result<(), AppError> {
+    if password.chars().count() < MIN_PASSWORD_LEN {
+        return Err(AppError::Invalid(format!(
+            "Password must be at least {} characters",
+            MIN_PASSWORD_LEN
+        )));
+    }
+    Ok(())
+}
+
+// Hash a password with Argon2id and a random per-user salt, returning a PHC string
+// (`$argon2id$v=19$...`) that fits in `public.login.password_hash`.
+pub fn hash_password(password: &str) -> Result<String, AppError> {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| AppError::Message(format!("Failed to hash password: {}", e)))
+}
+
+// Check a password against a stored hash. Accepts both Argon2 PHC strings and the
+// legacy hex-encoded SHA-256 hashes written before per-user salts were introduced. A stored
+// hash that is neither is an error rather than a wrong password.
+pub fn verify_password(password: &str, stored_hash: &str) -> Result<Verification, AppError> {
+    if is_legacy_hash(stored_hash) {
+        let candidate = hex::encode(Sha256::digest(password.as_bytes()));
+        return Ok(
+            if constant_time_eq(candidate.as_bytes(), stored_hash.to_lowercase().as_bytes()) {
+                Verification::ValidNeedsRehash
+            } else {
+                Verification::Invalid
+            },
+        );
+    }
+
+    let parsed = PasswordHash::new(stored_hash)
+        .map_err(|e| format!("Stored password hash is malformed: {}", e))?;
+    if parsed.hash.is_none() {
+        return Err(AppError::Message(
+            "Stored password hash is malformed: no hash output".to_string(),
+        ));
+    }
+
+    match Argon2::default().verify_password(password.as_bytes(), &parsed) {
+        Ok(()) => Ok(Verification::Valid),
+        Err(argon2::password_hash::Error::Password) => Ok(Verification::Invalid),
+        Err(e) => Err(AppError::Message(format!(
+            "Failed to verify password: {}",
+            e
+        ))),
+    }
+}
+
+// An Argon2id hash with the default parameters that no password is checked against for real.
+// Verifying against it when the username is unknown costs as much as a real check, so the
+// response time doesn't tell whether an account exists.
+const DUMMY_HASH: &str =
+    "$argon2id$v=19$m=19456,t=2,p=1$qTcHIHFIoRwH1fkiESb+yA$gela5EQBoyjm7Kk/yIBdyijf8/OSe/ljVUBv6fzzmm0";
+
+pub fn verify_unknown_user(password: &str) {
+    let _ = verify_password(password, DUMMY_HASH);
+}
+
+// Legacy hashes are the bare 64-character hex digest produced by `Sha256`
+fn is_legacy_hash(stored_hash: &str) -> bool {
+    stored_hash.len() == 64 && stored_hash.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn argon2_round_trip_is_valid() {
+        let hash = hash_password("correct horse").unwrap();
+        assert!(hash.starts_with("$argon2id$"));
+        assert_eq!(
+            verify_password("correct horse", &hash).unwrap(),
+            Verification::Valid
+        );
+    }
+
+    #[test]
+    fn legacy_sha256_needs_rehash() {
+        let legacy = hex::encode(Sha256::digest(b"correct horse"));
+        assert_eq!(
+            verify_password("correct horse", &legacy).unwrap(),
+            Verification::ValidNeedsRehash
+        );
+        assert_eq!(
+            verify_password("correct horse", &legacy.to_uppercase()).unwrap(),
+            Verification::ValidNeedsRehash
+        );
+    }
+
+    #[test]
+    fn wrong_password_is_invalid() {
+        let hash = hash_password("correct horse").unwrap();
+        assert_eq!(
+            verify_password("battery staple", &hash).unwrap(),
+            Verification::Invalid
+        );
+
+        let legacy = hex::encode(Sha256::digest(b"correct horse"));
+        assert_eq!(
+            verify_password("battery staple", &legacy).unwrap(),
+            Verification::Invalid
+        );
+    }
+
+    #[test]
+    fn malformed_hash_is_an_error() {
+        for stored in ["", "not a hash", "$argon2id$v=19$garbage", "abc123"] {
+            assert!(
+                verify_password("correct horse", stored).is_err(),
+                "{:?}",
+                stored
+            );
+        }
+    }
+
+    #[test]
+    fn dummy_hash_is_a_current_argon2_hash() {
+        let parsed = PasswordHash::new(DUMMY_HASH).unwrap();
+        let current = PasswordHash::new(&hash_password("correct horse").unwrap())
+            .unwrap()
+            .params;
+        assert_eq!(parsed.params, current);
+        assert_eq!(
+            verify_password("correct horse", DUMMY_HASH).unwrap(),
+            Verification::Invalid
+        );
+    }
+
+    #[test]
+    fn short_passwords_are_refused() {
+        assert!(validate_new_password("seven77").is_err());
+        assert!(validate_new_password("eight888").is_ok());
+    }
+}
@@ -0,0 +1,148 @@
+use std::fmt;
+
+// Roles that can sign in to the desktop app. Customers live in `public.role` too but
+// never get a session. Ordered from most to least privileged.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    Owner,
+    Admin,
+    Staff,
+}
+
+impl Role {
+    // Parse a `public.role.role_name`, ignoring roles without desktop access
+    pub fn from_role_name(role_name: &str) -> Option<Role> {
+        match role_name {
+            "owner" => Some(Role::Owner),
+            "admin" => Some(Role::Admin),
+            "staff" => Some(Role::Staff),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Role::Owner => "owner",
+            Role::Admin => "admin",
+            Role::Staff => "staff",
+        }
+    }
+
+    // The permission matrix enforced by every command
+    pub fn can(self, permission: Permission) -> bool {
+        match self {
+            Role::Owner => true,
+            // Two-factor enrolment is for owner accounts only
+            Role::Admin => permission != Permission::ManageTwoFactor,
+            Role::Staff => matches!(
+                permission,
+                Permission::ViewState
+                    | Permission::CreateBooking
+                    | Permission::CancelBooking
+                    | Permission::RescheduleBooking
+                    | Permission::UpdateBookingStatus
+                    | Permission::Checkout
+                    | Permission::AddCustomer
+                    | Permission::EditCustomer
+            ),
+        }
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(serde::Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Permission {
+    ViewState,
+    CreateBooking,
+    CancelBooking,
+    RescheduleBooking,
+    UpdateBookingStatus,
+    Checkout,
+    AddCustomer,
+    EditCustomer,
+    DeleteCustomer,
+    ManageCampaigns,
+    ManageSettings,
+    ManageStaff,
+    ManageTwoFactor,
+}
+
+impl fmt::Display for Permission {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Permission::ViewState => "view company data",
+            Permission::CreateBooking => "create bookings",
+            Permission::CancelBooking => "cancel bookings",
+            Permission::RescheduleBooking => "reschedule bookings",
+            Permission::UpdateBookingStatus => "update booking statuses",
+            Permission::Checkout => "take payments",
+            Permission::AddCustomer => "add customers",
+            Permission::EditCustomer => "edit customers",
+            Permission::DeleteCustomer => "delete customers",
+            Permission::ManageCampaigns => "manage campaigns",
+            Permission::ManageSettings => "change settings",
+            Permission::ManageStaff => "manage staff",
+            Permission::ManageTwoFactor => "manage two-factor authentication",
+        };
+        f.write_str(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROLES: [Role; 3] = [Role::Owner, Role::Admin, Role::Staff];
+
+    const PERMISSIONS: [Permission; 13] = [
+        Permission::ViewState,
+        Permission::CreateBooking,
+        Permission::CancelBooking,
+        Permission::RescheduleBooking,
+        Permission::UpdateBookingStatus,
+        Permission::Checkout,
+        Permission::AddCustomer,
+        Permission::EditCustomer,
+        Permission::DeleteCustomer,
+        Permission::ManageCampaigns,
+        Permission::ManageSettings,
+        Permission::ManageStaff,
+        Permission::ManageTwoFactor,
+    ];
+
+    // Whether owner, admin and staff may perform each permission. The match is exhaustive,
+    // so a new permission doesn't compile until its row is added here.
+    fn expected(permission: Permission) -> [bool; 3] {
+        match permission {
+            Permission::ViewState => [true, true, true],
+            Permission::CreateBooking => [true, true, true],
+            Permission::CancelBooking => [true, true, true],
+            Permission::RescheduleBooking => [true, true, true],
+            Permission::UpdateBookingStatus => [true, true, true],
+            Permission::Checkout => [true, true, true],
+            Permission::AddCustomer => [true, true, true],
+            Permission::EditCustomer => [true, true, true],
+            Permission::DeleteCustomer => [true, true, false],
+            Permission::ManageCampaigns => [true, true, false],
+            Permission::ManageSettings => [true, true, false],
+            Permission::ManageStaff => [true, true, false],
+            Permission::ManageTwoFactor => [true, false, false],
+        }
+    }
+
+    #[test]
+    fn every_role_and_permission_is_pinned() {
+        for permission in PERMISSIONS {
+            for (role, allowed) in ROLES.into_iter().zip(expected(permission)) {
+                assert_eq!(role.can(permission), allowed, "{} to {}", role, permission);
+            }
+        }
+    }
+}
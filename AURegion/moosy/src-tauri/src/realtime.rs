@@ -0,0 +1,204 @@
+use sqlx::postgres::{PgListener, PgPool};
+use std::time::Duration;
+use tauri::{Emitter, Manager};
+
+use crate::{
+    connection::Connection,
+    delta::{self, Delta},
+    error::AppError,
+    logging::Span,
+    AuthState,
+};
+
+// How often the listener looks up the active company while no notification arrives
+const POLL: Duration = Duration::from_secs(1);
+const RETRY: Duration = Duration::from_secs(5);
+
+// A change published by the triggers of `20261018123000_company_notifications.sql`. Only
+// ids and statuses come through; the rest is re-fetched with the session.
+#[derive(serde::Deserialize, Debug)]
+#[serde(tag = "entity", rename_all = "snake_case")]
+enum Change {
+    Booking {
+        op: Op,
+        id: String,
+        status_id: Option<String>,
+        old_status_id: Option<String>,
+        #[serde(default)]
+        rescheduled: bool,
+    },
+    Payment {
+        op: Op,
+        id: String,
+        status: Option<String>,
+    },
+    Customer {
+        op: Op,
+        id: String,
+    },
+}
+
+#[derive(serde::Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+enum Op {
+    Insert,
+    Update,
+    Delete,
+}
+
+// The notification channel of a company, as `public.company_channel` names it
+fn channel(company_id: &str) -> String {
+    format!("company_{}", company_id.replace('-', ""))
+}
+
+// The company to listen for: the active one, while signed in and online
+fn wanted(app: &tauri::AppHandle) -> Option<String> {
+    if !app.state::<Connection>().is_online() {
+        return None;
+    }
+    app.state::<AuthState>()
+        .get()
+        .map(|session| session.data.company.id)
+}
+
+// Background task that listens for changes to the active company made elsewhere, e.g. online
+// bookings, and turns them into state deltas plus granular events. It follows company
+// switches and subscribes again after the connection drops, catching up with a full refresh
+// for whatever was missed meanwhile.
+pub fn spawn_listener(app: tauri::AppHandle, database_url: String) {
+    tauri::async_runtime::spawn(async move {
+        let mut missed = false;
+        loop {
+            let company_id = match wanted(&app) {
+                Some(company_id) => company_id,
+                None => {
+                    tokio::time::sleep(POLL).await;
+                    continue;
+                }
+            };
+
+            // A connection of its own, so the pool keeps all of its connections for commands
+            let mut listener = match subscribe(&database_url, &company_id).await {
+                Ok(listener) => listener,
+                Err(e) => {
+                    log::warn!("Failed to subscribe to change notifications: {}", e);
+                    tokio::time::sleep(RETRY).await;
+                    continue;
+                }
+            };
+            let span = Span::new("realtime").company(&company_id);
+            span.debug(format_args!("Listening for changes"));
+
+            if missed {
+                missed = false;
+                catch_up(&app).await;
+            }
+
+            loop {
+                if wanted(&app).as_deref() != Some(&company_id) {
+                    break;
+                }
+                match tokio::time::timeout(POLL, listener.try_recv()).await {
+                    Err(_) => continue,
+                    Ok(Ok(Some(notification))) => {
+                        if let Err(e) = handle(&app, &company_id, notification.payload()).await {
+                            span.warn(format_args!("Failed to apply a change notification: {}", e));
+                        }
+                    }
+                    Ok(Ok(None)) | Ok(Err(_)) => {
+                        span.warn(format_args!(
+                            "Change notifications dropped, subscribing again"
+                        ));
+                        missed = true;
+                        break;
+                    }
+                }
+            }
+        }
+    });
+}
+
+async fn subscribe(database_url: &str, company_id: &str) -> Result<PgListener, sqlx::Error> {
+    let mut listener = PgListener::connect(database_url).await?;
+    listener.listen(&channel(company_id)).await?;
+    Ok(listener)
+}
+
+// Reload the whole company state, for changes that happened while unsubscribed
+async fn catch_up(app: &tauri::AppHandle) {
+    let session = match app.state::<AuthState>().get() {
+        Some(session) => session,
+        None => return,
+    };
+    let pool = app.state::<PgPool>();
+    if let Err(e) = crate::refresh_session(&pool, app, session).await {
+        log::warn!("Failed to catch up on missed changes: {}", e);
+    }
+}
+
+// Patch the cached state with the changed entity and emit the matching event
+async fn handle(app: &tauri::AppHandle, company_id: &str, payload: &str) -> Result<(), AppError> {
+    let change: Change = serde_json::from_str(payload)
+        .map_err(|e| format!("Unreadable notification {}: {}", payload, e))?;
+    let pool = app.state::<PgPool>();
+
+    let (event, body) = match change {
+        Change::Booking {
+            op: Op::Delete, id, ..
+        } => {
+            let change = Delta::BookingRemoved { id: id.clone() };
+            delta::publish(app, company_id, vec![change])?;
+            ("booking-deleted", serde_json::json!({ "id": id }))
+        }
+        Change::Booking {
+            op,
+            id,
+            status_id,
+            old_status_id,
+            rescheduled,
+        } => {
+            let change = delta::booking(&*pool, company_id, &id).await?;
+            let booking = match &change {
+                Delta::BookingUpserted { booking } => serde_json::to_value(booking),
+                _ => Ok(serde_json::json!({ "id": id })),
+            }
+            .map_err(|e| e.to_string())?;
+            delta::publish(app, company_id, vec![change])?;
+
+            if op == Op::Insert {
+                ("booking-created", booking)
+            } else if status_id != old_status_id {
+                let body = serde_json::json!({
+                    "booking": booking,
+                    "previous_status_id": old_status_id,
+                });
+                ("booking-status-changed", body)
+            } else if rescheduled {
+                ("booking-rescheduled", booking)
+            } else {
+                ("booking-updated", booking)
+            }
+        }
+        Change::Payment { op, id, status } => {
+            // Payments aren't cached, the frontend only hears about them
+            let event = match op {
+                Op::Insert => "payment-recorded",
+                _ => "payment-updated",
+            };
+            (event, serde_json::json!({ "id": id, "status": status }))
+        }
+        Change::Customer { op, id } => {
+            let change = delta::customer(&*pool, company_id, &id).await?;
+            let event = match (&change, op) {
+                (Delta::CustomerRemoved { .. }, _) => "customer-removed",
+                (_, Op::Insert) => "customer-created",
+                _ => "customer-updated",
+            };
+            delta::publish(app, company_id, vec![change])?;
+            (event, serde_json::json!({ "id": id }))
+        }
+    };
+
+    app.emit(event, body)
+        .map_err(|e| AppError::Message(format!("Failed to emit event: {}", e)))
+}
@@ -0,0 +1,763 @@
+use chrono::{
+    DateTime, Datelike, FixedOffset, NaiveDate, NaiveDateTime, NaiveTime, Offset, TimeDelta, Utc,
+};
+use sqlx::types::Uuid;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Mutex, MutexGuard},
+};
+
+use super::{
+    BookedSlot, BookingRepo, BookingSnapshot, CampaignRepo, CompanyRepo, CustomerRepo, NewBooking,
+    OpeningWindow, OperatorPin, OperatorRepo, PaymentRepo, ScheduleRepo, StaffBooking, StaffMember,
+    StatusChange,
+};
+use crate::{
+    error::AppError, lockout::LoginAttempts, outbox::new_id, status::BookingStatus, AuthData,
+    Booking, Checkout, Customer, CustomerInput,
+};
+
+struct StoredBooking {
+    company_id: String,
+    staff_id: Option<String>,
+    service_id: Option<String>,
+    status_id: String,
+    start_time: DateTime<Utc>,
+    end_time: DateTime<Utc>,
+}
+
+#[derive(Clone)]
+pub struct StoredPayment {
+    pub company_id: String,
+    pub operator_id: String,
+    pub checkout: Checkout,
+    // (linkable id, linkable type) pairs
+    pub links: Vec<(String, String)>,
+}
+
+struct StoredPin {
+    pin: OperatorPin,
+    last_failed_at: Option<DateTime<Utc>>,
+}
+
+struct StoredCustomer {
+    companies: HashSet<String>,
+    customer: CustomerInput,
+}
+
+#[derive(Default)]
+struct Data {
+    // Status id to name, like `public.status`
+    statuses: HashMap<String, String>,
+    bookings: HashMap<String, StoredBooking>,
+    payments: HashMap<Uuid, StoredPayment>,
+    customers: HashMap<Uuid, StoredCustomer>,
+    // Person id to their till PIN, like `public.login_pin`
+    pins: HashMap<String, StoredPin>,
+    // (company id, campaign id)
+    campaigns: HashSet<(Uuid, Uuid)>,
+    companies: HashMap<String, AuthData>,
+    // Session token to the companies it may load
+    tokens: HashMap<String, HashSet<String>>,
+}
+
+// Keeps everything in process and behaves like the Postgres schema for what the commands
+// rely on, so their logic can be exercised without a database
+#[derive(Default)]
+pub struct MemoryRepo(Mutex<Data>);
+
+// Booking times arrive as `timestamp` strings, with or without an offset
+fn parse_time(value: &str) -> Result<DateTime<Utc>, AppError> {
+    DateTime::parse_from_rfc3339(value)
+        .map(|time| time.with_timezone(&Utc))
+        .or_else(|_| {
+            NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S%.f").map(|time| time.and_utc())
+        })
+        .or_else(|_| {
+            NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S%.f").map(|time| time.and_utc())
+        })
+        .map_err(|_| AppError::Invalid(format!("Invalid time: {}", value)))
+}
+
+// Only `UTC` and fixed offsets such as `+10:00`; Postgres resolves the named zones
+fn fixed_zone(timezone: &str) -> Result<FixedOffset, AppError> {
+    if timezone.eq_ignore_ascii_case("UTC") {
+        return Ok(Utc.fix());
+    }
+    timezone
+        .parse()
+        .map_err(|_| AppError::Invalid(format!("Unsupported timezone: {}", timezone)))
+}
+
+fn parse_clock(value: &str) -> Result<NaiveTime, AppError> {
+    NaiveTime::parse_from_str(value, "%H:%M:%S")
+        .map_err(|_| AppError::Invalid(format!("Invalid time: {}", value)))
+}
+
+impl Data {
+    // Bookings of the company that hold their staff member's time, optionally of one of them
+    fn active_bookings<'a>(
+        &'a self,
+        company_id: &'a str,
+        staff_id: Option<&'a str>,
+    ) -> impl Iterator<Item = (&'a String, &'a StoredBooking)> + 'a {
+        self.bookings.iter().filter(move |(_, booking)| {
+            booking.company_id == company_id
+                && self.holds_slot(booking)
+                && staff_id.map_or(true, |staff_id| {
+                    booking.staff_id.as_deref() == Some(staff_id)
+                })
+        })
+    }
+
+    // Like `booking.holds_slot`, from the status name
+    fn holds_slot(&self, booking: &StoredBooking) -> bool {
+        self.statuses
+            .get(&booking.status_id)
+            .and_then(|name| BookingStatus::from_name(name))
+            .is_some_and(BookingStatus::occupies_slot)
+    }
+
+    fn status_id(&self, status: BookingStatus) -> Option<String> {
+        self.statuses
+            .iter()
+            .find(|(_, name)| name.as_str() == status.as_str())
+            .map(|(id, _)| id.clone())
+    }
+}
+
+impl MemoryRepo {
+    fn data(&self) -> MutexGuard<'_, Data> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn add_status(&self, id: &str, name: &str) {
+        self.data()
+            .statuses
+            .insert(id.to_string(), name.to_string());
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn add_booking(
+        &self,
+        company_id: &str,
+        booking_id: &str,
+        staff_id: Option<&str>,
+        service_id: Option<&str>,
+        status_id: &str,
+        start_time: &str,
+        end_time: &str,
+    ) -> Result<(), AppError> {
+        let booking = StoredBooking {
+            company_id: company_id.to_string(),
+            staff_id: staff_id.map(str::to_string),
+            service_id: service_id.map(str::to_string),
+            status_id: status_id.to_string(),
+            start_time: parse_time(start_time)?,
+            end_time: parse_time(end_time)?,
+        };
+        self.data().bookings.insert(booking_id.to_string(), booking);
+        Ok(())
+    }
+
+    pub fn add_pin(&self, person_id: &str, pin_hash: &str) {
+        let pin = OperatorPin {
+            pin_hash: pin_hash.to_string(),
+            attempts: LoginAttempts {
+                failed_attempts: 0,
+                locked_until: None,
+            },
+        };
+        self.data().pins.insert(
+            person_id.to_string(),
+            StoredPin {
+                pin,
+                last_failed_at: None,
+            },
+        );
+    }
+
+    // Company state that `token` is allowed to load
+    pub fn add_company(&self, token: &str, data: AuthData) {
+        let mut store = self.data();
+        store
+            .tokens
+            .entry(token.to_string())
+            .or_default()
+            .insert(data.company.id.clone());
+        store.companies.insert(data.company.id.clone(), data);
+    }
+
+    pub fn revoke_token(&self, token: &str) {
+        self.data().tokens.remove(token);
+    }
+
+    pub fn booking_status_id(&self, booking_id: &str) -> Option<String> {
+        self.data()
+            .bookings
+            .get(booking_id)
+            .map(|booking| booking.status_id.clone())
+    }
+
+    pub fn payments(&self) -> HashMap<Uuid, StoredPayment> {
+        self.data().payments.clone()
+    }
+
+    pub fn customer(&self, customer_id: Uuid) -> Option<CustomerInput> {
+        self.data()
+            .customers
+            .get(&customer_id)
+            .map(|stored| stored.customer.clone())
+    }
+}
+
+impl BookingRepo for MemoryRepo {
+    async fn booking(
+        &self,
+        company_id: &str,
+        booking_id: &str,
+    ) -> Result<Option<BookingSnapshot>, AppError> {
+        let data = self.data();
+        Ok(data
+            .bookings
+            .get(booking_id)
+            .filter(|booking| booking.company_id == company_id)
+            .map(|booking| BookingSnapshot {
+                status: data.statuses.get(&booking.status_id).cloned(),
+                start_time: booking.start_time,
+                end_time: booking.end_time,
+                staff_id: booking.staff_id.clone(),
+                service_id: booking.service_id.clone(),
+            }))
+    }
+
+    async fn statuses(&self) -> Result<Vec<(String, String)>, AppError> {
+        let data = self.data();
+        Ok(data
+            .statuses
+            .iter()
+            .map(|(id, name)| (name.clone(), id.clone()))
+            .collect())
+    }
+
+    async fn set_booking_status(
+        &self,
+        company_id: &str,
+        booking_id: &str,
+        from_status_id: &str,
+        status_id: &str,
+    ) -> Result<bool, AppError> {
+        match self.data().bookings.get_mut(booking_id) {
+            Some(booking)
+                if booking.company_id == company_id && booking.status_id == from_status_id =>
+            {
+                booking.status_id = status_id.to_string();
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn move_booking(
+        &self,
+        company_id: &str,
+        booking_id: &str,
+        start_time: DateTime<Utc>,
+        end_time: DateTime<Utc>,
+    ) -> Result<bool, AppError> {
+        let mut data = self.data();
+        let staff_id = match data.bookings.get(booking_id) {
+            Some(booking) if booking.company_id == company_id => booking.staff_id.clone(),
+            _ => return Ok(false),
+        };
+        if let Some(staff_id) = &staff_id {
+            let taken = data
+                .active_bookings(company_id, Some(staff_id))
+                .any(|(id, other)| {
+                    id != booking_id && other.start_time < end_time && start_time < other.end_time
+                });
+            if taken {
+                return Ok(false);
+            }
+        }
+        match data.bookings.get_mut(booking_id) {
+            Some(booking) => {
+                booking.start_time = start_time;
+                booking.end_time = end_time;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn insert_booking(
+        &self,
+        company_id: &str,
+        booking: &NewBooking,
+    ) -> Result<bool, AppError> {
+        let mut data = self.data();
+        let taken = data
+            .active_bookings(company_id, Some(&booking.staff_id))
+            .any(|(_, other)| {
+                other.start_time < booking.end_time && booking.start_time < other.end_time
+            });
+        if taken {
+            return Ok(false);
+        }
+
+        // New bookings start out pending, like the column default
+        let status_id = data.status_id(BookingStatus::Pending).unwrap_or_default();
+        data.bookings.insert(
+            booking.id.to_string(),
+            StoredBooking {
+                company_id: company_id.to_string(),
+                staff_id: Some(booking.staff_id.clone()),
+                service_id: Some(booking.service_id.clone()),
+                status_id,
+                start_time: booking.start_time,
+                end_time: booking.end_time,
+            },
+        );
+        Ok(true)
+    }
+}
+
+impl ScheduleRepo for MemoryRepo {
+    async fn opening_windows(
+        &self,
+        company_id: &str,
+        from: NaiveDate,
+        to: NaiveDate,
+    ) -> Result<Vec<OpeningWindow>, AppError> {
+        let data = self.data();
+        let timetable = match data.companies.get(company_id) {
+            Some(company) => &company.company.timetable,
+            None => return Ok(Vec::new()),
+        };
+
+        let mut windows = Vec::new();
+        for local_date in from.iter_days().take_while(|date| *date <= to) {
+            let day = local_date.weekday().num_days_from_sunday() as i32;
+            for slot in timetable.iter().filter(|slot| slot.day_of_week == day) {
+                let zone = fixed_zone(&slot.timezone)?;
+                let at = |time: &str| -> Result<DateTime<Utc>, AppError> {
+                    let local = local_date.and_time(parse_clock(time)?);
+                    Ok((local - zone).and_utc())
+                };
+                windows.push(OpeningWindow {
+                    local_date,
+                    opens_at: at(&slot.start_time)?,
+                    closes_at: at(&slot.end_time)?,
+                    timezone: slot.timezone.clone(),
+                    utc_offset: zone.local_minus_utc(),
+                });
+            }
+        }
+        windows.sort_by_key(|window| window.opens_at);
+        Ok(windows)
+    }
+
+    async fn booked_slots(
+        &self,
+        staff_id: &str,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<BookedSlot>, AppError> {
+        let data = self.data();
+        let mut slots: Vec<BookedSlot> = data
+            .bookings
+            .values()
+            .filter(|booking| {
+                booking.staff_id.as_deref() == Some(staff_id)
+                    && data.holds_slot(booking)
+                    && booking.start_time < to
+                    && from < booking.end_time
+            })
+            .map(|booking| BookedSlot {
+                booked_start: booking.start_time,
+                booked_end: booking.end_time,
+            })
+            .collect();
+        slots.sort_by_key(|slot| slot.booked_start);
+        Ok(slots)
+    }
+
+    async fn service_duration(
+        &self,
+        company_id: &str,
+        service_id: &str,
+    ) -> Result<Option<TimeDelta>, AppError> {
+        let data = self.data();
+        let service = data.companies.get(company_id).and_then(|company| {
+            company
+                .company
+                .services_by_catalogue
+                .iter()
+                .flatten()
+                .flat_map(|catalogue| catalogue.services.iter().flatten())
+                .find(|service| service.id == service_id)
+        });
+        match service {
+            Some(service) => {
+                let duration = parse_clock(&service.duration)? - NaiveTime::MIN;
+                Ok(Some(duration))
+            }
+            None => Ok(None),
+        }
+    }
+
+    async fn is_team_member(&self, company_id: &str, person_id: &str) -> Result<bool, AppError> {
+        let data = self.data();
+        Ok(data.companies.get(company_id).is_some_and(|company| {
+            let roles = &company.roles;
+            roles
+                .owner
+                .iter()
+                .chain(roles.admin.iter().flatten())
+                .chain(roles.staff.iter().flatten())
+                .any(|person| person.id == person_id)
+        }))
+    }
+
+    async fn available_staff(
+        &self,
+        company_id: &str,
+        start_time: DateTime<Utc>,
+        end_time: DateTime<Utc>,
+    ) -> Result<Option<Uuid>, AppError> {
+        let data = self.data();
+        let staff = match data.companies.get(company_id) {
+            Some(company) => company.roles.staff.iter().flatten(),
+            None => return Ok(None),
+        };
+        for person in staff {
+            let busy = data
+                .active_bookings(company_id, Some(&person.id))
+                .any(|(_, booking)| booking.start_time < end_time && start_time < booking.end_time);
+            if !busy {
+                return Ok(Uuid::parse_str(&person.id).ok());
+            }
+        }
+        Ok(None)
+    }
+
+    async fn overlapping_bookings(
+        &self,
+        company_id: &str,
+        staff_id: &str,
+        start_time: DateTime<Utc>,
+        end_time: DateTime<Utc>,
+        except_id: Option<&str>,
+    ) -> Result<Vec<StaffBooking>, AppError> {
+        let data = self.data();
+        let mut bookings: Vec<StaffBooking> = data
+            .active_bookings(company_id, Some(staff_id))
+            .filter(|(id, booking)| {
+                Some(id.as_str()) != except_id
+                    && booking.start_time < end_time
+                    && start_time < booking.end_time
+            })
+            .map(|(id, booking)| StaffBooking {
+                id: id.clone(),
+                start_time: booking.start_time,
+                end_time: booking.end_time,
+            })
+            .collect();
+        bookings.sort_by_key(|booking| booking.start_time);
+        Ok(bookings)
+    }
+
+    async fn bookable_staff(
+        &self,
+        company_id: &str,
+        staff_id: Option<&str>,
+    ) -> Result<Vec<StaffMember>, AppError> {
+        let data = self.data();
+        let roles = match data.companies.get(company_id) {
+            Some(company) => &company.roles,
+            None => return Ok(Vec::new()),
+        };
+        let people: Vec<_> = match staff_id {
+            Some(staff_id) => roles
+                .owner
+                .iter()
+                .chain(roles.admin.iter().flatten())
+                .chain(roles.staff.iter().flatten())
+                .filter(|person| person.id == staff_id)
+                .take(1)
+                .collect(),
+            None => roles.staff.iter().flatten().collect(),
+        };
+        let mut staff: Vec<StaffMember> = people
+            .into_iter()
+            .map(|person| StaffMember {
+                id: person.id.clone(),
+                first_name: person.personal_information.first_name.clone(),
+                last_name: person.personal_information.last_name.clone(),
+            })
+            .collect();
+        staff.sort_by(|a, b| {
+            (&a.first_name, &a.last_name, &a.id).cmp(&(&b.first_name, &b.last_name, &b.id))
+        });
+        Ok(staff)
+    }
+}
+
+impl PaymentRepo for MemoryRepo {
+    async fn payment_exists(&self, payment_id: Uuid) -> Result<bool, AppError> {
+        Ok(self.data().payments.contains_key(&payment_id))
+    }
+
+    async fn insert_payment(
+        &self,
+        company_id: &str,
+        operator_id: &str,
+        payment_id: Uuid,
+        checkout: &Checkout,
+        completion: Option<&StatusChange>,
+    ) -> Result<bool, AppError> {
+        let mut data = self.data();
+        if data.payments.contains_key(&payment_id) {
+            return Err(AppError::Duplicate {
+                constraint: Some("payments_pkey".to_string()),
+            });
+        }
+
+        if let (Some(booking_id), Some(change)) = (&checkout.booking_id, completion) {
+            match data.bookings.get_mut(booking_id) {
+                Some(booking)
+                    if booking.company_id == company_id
+                        && booking.status_id == change.from_status_id =>
+                {
+                    booking.status_id = change.status_id.clone();
+                }
+                _ => return Ok(false),
+            }
+        }
+
+        let links = checkout
+            .links()
+            .map(|(id, linkable_type)| (id.to_string(), linkable_type.to_string()))
+            .collect();
+
+        data.payments.insert(
+            payment_id,
+            StoredPayment {
+                company_id: company_id.to_string(),
+                operator_id: operator_id.to_string(),
+                checkout: checkout.clone(),
+                links,
+            },
+        );
+        Ok(true)
+    }
+}
+
+impl CustomerRepo for MemoryRepo {
+    async fn upsert_customer(
+        &self,
+        company_id: &str,
+        customer: &CustomerInput,
+    ) -> Result<Option<Uuid>, AppError> {
+        let mut data = self.data();
+        let existing = data.customers.iter_mut().find(|(_, stored)| {
+            stored.companies.contains(company_id) && stored.customer.phone == customer.phone
+        });
+
+        if let Some((id, stored)) = existing {
+            stored.customer = CustomerInput {
+                id: Some(id.to_string()),
+                ..customer.clone()
+            };
+            return Ok(Some(*id));
+        }
+
+        let id = new_id();
+        data.customers.insert(
+            id,
+            StoredCustomer {
+                companies: HashSet::from([company_id.to_string()]),
+                customer: CustomerInput {
+                    id: Some(id.to_string()),
+                    ..customer.clone()
+                },
+            },
+        );
+        Ok(Some(id))
+    }
+
+    async fn update_customer(&self, customer: &CustomerInput) -> Result<Option<Uuid>, AppError> {
+        let id = match customer.id.as_deref().map(Uuid::parse_str) {
+            Some(Ok(id)) => id,
+            _ => return Ok(None),
+        };
+        match self.data().customers.get_mut(&id) {
+            Some(stored) => {
+                stored.customer = customer.clone();
+                Ok(Some(id))
+            }
+            None => Ok(None),
+        }
+    }
+
+    async fn is_company_customer(
+        &self,
+        company_id: &str,
+        customer_id: &str,
+    ) -> Result<bool, AppError> {
+        let id = match Uuid::parse_str(customer_id) {
+            Ok(id) => id,
+            Err(_) => return Ok(false),
+        };
+        Ok(self
+            .data()
+            .customers
+            .get(&id)
+            .is_some_and(|stored| stored.companies.contains(company_id)))
+    }
+
+    async fn delete_customer(
+        &self,
+        company_id: Uuid,
+        customer_id: Uuid,
+    ) -> Result<Option<Uuid>, AppError> {
+        let mut data = self.data();
+        let belongs = data
+            .customers
+            .get(&customer_id)
+            .is_some_and(|stored| stored.companies.contains(&company_id.to_string()));
+        if !belongs {
+            return Ok(None);
+        }
+        data.customers.remove(&customer_id);
+        Ok(Some(customer_id))
+    }
+}
+
+impl CampaignRepo for MemoryRepo {
+    async fn campaign_linked(&self, company_id: Uuid, campaign_id: Uuid) -> Result<bool, AppError> {
+        Ok(self.data().campaigns.contains(&(company_id, campaign_id)))
+    }
+
+    async fn link_campaign(&self, company_id: Uuid, campaign_id: Uuid) -> Result<(), AppError> {
+        if !self.data().campaigns.insert((company_id, campaign_id)) {
+            return Err(AppError::Duplicate { constraint: None });
+        }
+        Ok(())
+    }
+
+    async fn unlink_campaign(&self, company_id: Uuid, campaign_id: Uuid) -> Result<(), AppError> {
+        self.data().campaigns.remove(&(company_id, campaign_id));
+        Ok(())
+    }
+}
+
+impl OperatorRepo for MemoryRepo {
+    async fn operator_pin(&self, person_id: &str) -> Result<Option<OperatorPin>, AppError> {
+        Ok(self
+            .data()
+            .pins
+            .get(person_id)
+            .map(|stored| stored.pin.clone()))
+    }
+
+    async fn count_pin_failure(
+        &self,
+        person_id: &str,
+        window_secs: i64,
+    ) -> Result<(i32, DateTime<Utc>), AppError> {
+        let mut data = self.data();
+        let stored = data
+            .pins
+            .get_mut(person_id)
+            .ok_or(AppError::NotFound("PIN"))?;
+        let now = Utc::now();
+        let in_window = stored
+            .last_failed_at
+            .is_some_and(|at| at > now - TimeDelta::seconds(window_secs));
+        stored.pin.attempts.failed_attempts = if in_window {
+            stored.pin.attempts.failed_attempts + 1
+        } else {
+            1
+        };
+        stored.last_failed_at = Some(now);
+        Ok((stored.pin.attempts.failed_attempts, now))
+    }
+
+    async fn lock_pin(&self, person_id: &str, until: DateTime<Utc>) -> Result<(), AppError> {
+        if let Some(stored) = self.data().pins.get_mut(person_id) {
+            let locked_until = &mut stored.pin.attempts.locked_until;
+            *locked_until = Some(locked_until.map_or(until, |current| current.max(until)));
+        }
+        Ok(())
+    }
+
+    async fn clear_pin_failures(&self, person_id: &str) -> Result<(), AppError> {
+        if let Some(stored) = self.data().pins.get_mut(person_id) {
+            stored.pin.attempts = LoginAttempts {
+                failed_attempts: 0,
+                locked_until: None,
+            };
+            stored.last_failed_at = None;
+        }
+        Ok(())
+    }
+}
+
+impl CompanyRepo for MemoryRepo {
+    async fn company_details(
+        &self,
+        token: &str,
+        company_id: &str,
+    ) -> Result<Option<AuthData>, AppError> {
+        let data = self.data();
+        let allowed = data
+            .tokens
+            .get(token)
+            .is_some_and(|companies| companies.contains(company_id));
+        if !allowed {
+            return Ok(None);
+        }
+        Ok(data.companies.get(company_id).cloned())
+    }
+
+    // Served from the seeded company state
+    async fn company_booking(
+        &self,
+        company_id: &str,
+        booking_id: &str,
+    ) -> Result<Option<Booking>, AppError> {
+        Ok(self
+            .data()
+            .companies
+            .get(company_id)
+            .and_then(|company| {
+                company
+                    .bookings
+                    .iter()
+                    .flatten()
+                    .find(|b| b.id == booking_id)
+            })
+            .cloned())
+    }
+
+    async fn company_customer(
+        &self,
+        company_id: &str,
+        customer_id: &str,
+    ) -> Result<Option<Customer>, AppError> {
+        Ok(self
+            .data()
+            .companies
+            .get(company_id)
+            .and_then(|company| {
+                company
+                    .roles
+                    .customer
+                    .iter()
+                    .flatten()
+                    .find(|c| c.id == customer_id)
+            })
+            .cloned())
+    }
+}
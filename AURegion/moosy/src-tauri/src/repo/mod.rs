@@ -0,0 +1,300 @@
+use chrono::{DateTime, NaiveDate, TimeDelta, Utc};
+use sqlx::types::Uuid;
+use std::future::Future;
+
+use crate::{
+    error::AppError, lockout::LoginAttempts, AuthData, Booking, Checkout, Customer, CustomerInput,
+};
+
+mod memory;
+mod postgres;
+
+pub use memory::MemoryRepo;
+
+// Data access for the till commands. `PgPool` is the real backend; `MemoryRepo` keeps
+// everything in process so the command logic can run without a database. Every method is
+// scoped to a company except where an id is globally unique.
+
+// A booking's current status name and times, as replay compares them, plus who and what it
+// is for
+#[derive(sqlx::FromRow, Clone, Debug)]
+pub struct BookingSnapshot {
+    pub status: Option<String>,
+    pub start_time: DateTime<Utc>,
+    pub end_time: DateTime<Utc>,
+    pub staff_id: Option<String>,
+    pub service_id: Option<String>,
+}
+
+// Status ids a booking moves between. It only moves while it still has `from_status_id`.
+#[derive(Clone, Debug)]
+pub struct StatusChange {
+    pub from_status_id: String,
+    pub status_id: String,
+}
+
+// A booking about to be inserted, with its staff member and times already settled
+#[derive(Clone, Debug)]
+pub struct NewBooking {
+    pub id: Uuid,
+    pub customer_id: String,
+    pub staff_id: String,
+    pub service_id: String,
+    pub start_time: DateTime<Utc>,
+    pub end_time: DateTime<Utc>,
+}
+
+// Opening hours of one local date, as UTC instants
+#[derive(sqlx::FromRow, Clone, Debug)]
+pub struct OpeningWindow {
+    pub local_date: NaiveDate,
+    pub opens_at: DateTime<Utc>,
+    pub closes_at: DateTime<Utc>,
+    // The timetable's zone and its UTC offset in seconds when the window opens
+    pub timezone: String,
+    pub utc_offset: i32,
+}
+
+// The times of a booking that holds its staff member's time
+#[derive(sqlx::FromRow, Clone, Copy, Debug)]
+pub struct BookedSlot {
+    pub booked_start: DateTime<Utc>,
+    pub booked_end: DateTime<Utc>,
+}
+
+// Another booking of the same staff member, as a reschedule conflict reports it
+#[derive(sqlx::FromRow, serde::Serialize, Clone, Debug)]
+pub struct StaffBooking {
+    pub id: String,
+    pub start_time: DateTime<Utc>,
+    pub end_time: DateTime<Utc>,
+}
+
+// Someone bookings can be made with
+#[derive(sqlx::FromRow, serde::Serialize, Clone, Debug)]
+pub struct StaffMember {
+    pub id: String,
+    pub first_name: String,
+    pub last_name: String,
+}
+
+pub trait BookingRepo {
+    fn booking(
+        &self,
+        company_id: &str,
+        booking_id: &str,
+    ) -> impl Future<Output = Result<Option<BookingSnapshot>, AppError>> + Send;
+
+    // (name, id) of every row in `public.status`
+    fn statuses(&self) -> impl Future<Output = Result<Vec<(String, String)>, AppError>> + Send;
+
+    // False when the company has no such booking or its status is no longer `from_status_id`
+    fn set_booking_status(
+        &self,
+        company_id: &str,
+        booking_id: &str,
+        from_status_id: &str,
+        status_id: &str,
+    ) -> impl Future<Output = Result<bool, AppError>> + Send;
+
+    // False when the company has no such booking, or its staff member got another booking
+    // overlapping the new times first
+    fn move_booking(
+        &self,
+        company_id: &str,
+        booking_id: &str,
+        start_time: DateTime<Utc>,
+        end_time: DateTime<Utc>,
+    ) -> impl Future<Output = Result<bool, AppError>> + Send;
+
+    // False when the staff member got a booking that holds their time overlapping it first
+    fn insert_booking(
+        &self,
+        company_id: &str,
+        booking: &NewBooking,
+    ) -> impl Future<Output = Result<bool, AppError>> + Send;
+}
+
+pub trait ScheduleRepo {
+    // Opening hours for every local date from `from` to `to`, inclusive
+    fn opening_windows(
+        &self,
+        company_id: &str,
+        from: NaiveDate,
+        to: NaiveDate,
+    ) -> impl Future<Output = Result<Vec<OpeningWindow>, AppError>> + Send;
+
+    // The staff member's bookings that hold their time overlapping `from..to`, at any
+    // company, as the `booking_staff_no_overlap` constraint sees them
+    fn booked_slots(
+        &self,
+        staff_id: &str,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> impl Future<Output = Result<Vec<BookedSlot>, AppError>> + Send;
+
+    // None when the company has no such service
+    fn service_duration(
+        &self,
+        company_id: &str,
+        service_id: &str,
+    ) -> impl Future<Output = Result<Option<TimeDelta>, AppError>> + Send;
+
+    // Owners, admins and staff can all take bookings
+    fn is_team_member(
+        &self,
+        company_id: &str,
+        person_id: &str,
+    ) -> impl Future<Output = Result<bool, AppError>> + Send;
+
+    // A staff member free for the whole slot, as `check_and_assign_staff` picks them
+    fn available_staff(
+        &self,
+        company_id: &str,
+        start_time: DateTime<Utc>,
+        end_time: DateTime<Utc>,
+    ) -> impl Future<Output = Result<Option<Uuid>, AppError>> + Send;
+
+    // The staff member's bookings that hold their time overlapping `start..end`, other than
+    // `except_id` when given
+    fn overlapping_bookings(
+        &self,
+        company_id: &str,
+        staff_id: &str,
+        start_time: DateTime<Utc>,
+        end_time: DateTime<Utc>,
+        except_id: Option<&str>,
+    ) -> impl Future<Output = Result<Vec<StaffBooking>, AppError>> + Send;
+
+    // The given team member, or when None every staff member `check_and_assign_staff` picks
+    // from, sorted by name
+    fn bookable_staff(
+        &self,
+        company_id: &str,
+        staff_id: Option<&str>,
+    ) -> impl Future<Output = Result<Vec<StaffMember>, AppError>> + Send;
+}
+
+pub trait PaymentRepo {
+    fn payment_exists(
+        &self,
+        payment_id: Uuid,
+    ) -> impl Future<Output = Result<bool, AppError>> + Send;
+
+    // Record the payment tied to its booking, services and discounts, and move the booking by
+    // `completion`, all or nothing. False, with nothing written, when the booking's status
+    // changed meanwhile.
+    fn insert_payment(
+        &self,
+        company_id: &str,
+        operator_id: &str,
+        payment_id: Uuid,
+        checkout: &Checkout,
+        completion: Option<&StatusChange>,
+    ) -> impl Future<Output = Result<bool, AppError>> + Send;
+}
+
+pub trait CustomerRepo {
+    // Create the customer, or update the company's customer with the same phone number
+    fn upsert_customer(
+        &self,
+        company_id: &str,
+        customer: &CustomerInput,
+    ) -> impl Future<Output = Result<Option<Uuid>, AppError>> + Send;
+
+    // Update by `customer.id`, None when there is no such person
+    fn update_customer(
+        &self,
+        customer: &CustomerInput,
+    ) -> impl Future<Output = Result<Option<Uuid>, AppError>> + Send;
+
+    fn is_company_customer(
+        &self,
+        company_id: &str,
+        customer_id: &str,
+    ) -> impl Future<Output = Result<bool, AppError>> + Send;
+
+    // None when the person isn't a customer of the company
+    fn delete_customer(
+        &self,
+        company_id: Uuid,
+        customer_id: Uuid,
+    ) -> impl Future<Output = Result<Option<Uuid>, AppError>> + Send;
+}
+
+pub trait CampaignRepo {
+    fn campaign_linked(
+        &self,
+        company_id: Uuid,
+        campaign_id: Uuid,
+    ) -> impl Future<Output = Result<bool, AppError>> + Send;
+
+    fn link_campaign(
+        &self,
+        company_id: Uuid,
+        campaign_id: Uuid,
+    ) -> impl Future<Output = Result<(), AppError>> + Send;
+
+    fn unlink_campaign(
+        &self,
+        company_id: Uuid,
+        campaign_id: Uuid,
+    ) -> impl Future<Output = Result<(), AppError>> + Send;
+}
+
+// A team member's till PIN and the wrong guesses counted against it
+#[derive(sqlx::FromRow, Clone, Debug)]
+pub struct OperatorPin {
+    pub pin_hash: String,
+    #[sqlx(flatten)]
+    pub attempts: LoginAttempts,
+}
+
+pub trait OperatorRepo {
+    fn operator_pin(
+        &self,
+        person_id: &str,
+    ) -> impl Future<Output = Result<Option<OperatorPin>, AppError>> + Send;
+
+    // Count a wrong PIN, starting over when the last one is older than `window_secs`. Returns
+    // the new count and when it was counted. The count is bumped in place, so guesses made in
+    // parallel all count.
+    fn count_pin_failure(
+        &self,
+        person_id: &str,
+        window_secs: i64,
+    ) -> impl Future<Output = Result<(i32, DateTime<Utc>), AppError>> + Send;
+
+    // Refuse the PIN until `until`, unless it is already locked for longer
+    fn lock_pin(
+        &self,
+        person_id: &str,
+        until: DateTime<Utc>,
+    ) -> impl Future<Output = Result<(), AppError>> + Send;
+
+    fn clear_pin_failures(&self, person_id: &str)
+        -> impl Future<Output = Result<(), AppError>> + Send;
+}
+
+pub trait CompanyRepo {
+    // The company state for a session token, None when the token is no longer good for it
+    fn company_details(
+        &self,
+        token: &str,
+        company_id: &str,
+    ) -> impl Future<Output = Result<Option<AuthData>, AppError>> + Send;
+
+    // A single booking or customer in the shape `company_details` has them, None when the
+    // company has no such booking or customer (any more)
+    fn company_booking(
+        &self,
+        company_id: &str,
+        booking_id: &str,
+    ) -> impl Future<Output = Result<Option<Booking>, AppError>> + Send;
+
+    fn company_customer(
+        &self,
+        company_id: &str,
+        customer_id: &str,
+    ) -> impl Future<Output = Result<Option<Customer>, AppError>> + Send;
+}
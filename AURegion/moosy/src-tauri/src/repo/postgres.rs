@@ -0,0 +1,569 @@
+use chrono::{DateTime, NaiveDate, TimeDelta, Utc};
+use serde_json::Value;
+use sqlx::{types::Uuid, PgPool};
+
+use super::{
+    BookedSlot, BookingRepo, BookingSnapshot, CampaignRepo, CompanyRepo, CustomerRepo, NewBooking,
+    OpeningWindow, OperatorPin, OperatorRepo, PaymentRepo, ScheduleRepo, StaffBooking, StaffMember,
+    StatusChange,
+};
+use crate::{error::AppError, AuthData, Booking, Checkout, Customer, CustomerInput};
+
+impl BookingRepo for PgPool {
+    async fn booking(
+        &self,
+        company_id: &str,
+        booking_id: &str,
+    ) -> Result<Option<BookingSnapshot>, AppError> {
+        Ok(sqlx::query_as(
+            "SELECT s.name AS status, b.start_time, b.end_time,
+                 b.staff_id::text AS staff_id, b.service_id::text AS service_id
+             FROM public.booking b LEFT JOIN public.status s ON s.id = b.status_id
+             WHERE b.id = $1::uuid AND b.company_id = $2::uuid",
+        )
+        .bind(booking_id)
+        .bind(company_id)
+        .fetch_optional(self)
+        .await?)
+    }
+
+    async fn statuses(&self) -> Result<Vec<(String, String)>, AppError> {
+        Ok(sqlx::query_as("SELECT name, id::text FROM public.status")
+            .fetch_all(self)
+            .await?)
+    }
+
+    async fn set_booking_status(
+        &self,
+        company_id: &str,
+        booking_id: &str,
+        from_status_id: &str,
+        status_id: &str,
+    ) -> Result<bool, AppError> {
+        let result = sqlx::query(
+            "UPDATE booking SET status_id = $1::uuid
+             WHERE id = $2::uuid AND company_id = $3::uuid AND status_id = $4::uuid",
+        )
+        .bind(status_id)
+        .bind(booking_id)
+        .bind(company_id)
+        .bind(from_status_id)
+        .execute(self)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    // Same overlap check as `insert_booking`, leaving out the booking being moved
+    async fn move_booking(
+        &self,
+        company_id: &str,
+        booking_id: &str,
+        start_time: DateTime<Utc>,
+        end_time: DateTime<Utc>,
+    ) -> Result<bool, AppError> {
+        let result = sqlx::query(
+            "UPDATE public.booking b SET start_time = $1, end_time = $2
+             WHERE b.id = $3::uuid AND b.company_id = $4::uuid
+             AND NOT EXISTS (
+                 SELECT 1 FROM public.booking other
+                 WHERE other.staff_id = b.staff_id AND other.id <> b.id
+                 AND other.status_id IN (SELECT id FROM public.status WHERE name IN ('pending', 'confirmed', 'in_progress'))
+                 AND tstzrange(other.start_time, other.end_time) && tstzrange($1, $2)
+             )",
+        )
+        .bind(start_time)
+        .bind(end_time)
+        .bind(booking_id)
+        .bind(company_id)
+        .execute(self)
+        .await;
+
+        match result {
+            Ok(result) => Ok(result.rows_affected() > 0),
+            // Another booking took the time between the check above and this update's commit
+            Err(e) => match AppError::from(e) {
+                AppError::Conflict(_) => Ok(false),
+                e => Err(e),
+            },
+        }
+    }
+
+    // The overlap check is repeated in the insert itself, so a booking made elsewhere since
+    // the caller checked isn't double-booked. One committed at the same moment is caught by
+    // `booking_staff_no_overlap` and comes back as a conflict.
+    async fn insert_booking(
+        &self,
+        company_id: &str,
+        booking: &NewBooking,
+    ) -> Result<bool, AppError> {
+        let result = sqlx::query(
+            "INSERT INTO public.booking (id, company_id, customer_id, staff_id, service_id, start_time, end_time)
+             SELECT $1, $2::uuid, $3::uuid, $4::uuid, $5::uuid, $6, $7
+             WHERE NOT EXISTS (
+                 SELECT 1 FROM public.booking b
+                 WHERE b.staff_id = $4::uuid
+                 AND b.status_id IN (SELECT id FROM public.status WHERE name IN ('pending', 'confirmed', 'in_progress'))
+                 AND tstzrange(b.start_time, b.end_time) && tstzrange($6, $7)
+             )",
+        )
+        .bind(booking.id)
+        .bind(company_id)
+        .bind(&booking.customer_id)
+        .bind(&booking.staff_id)
+        .bind(&booking.service_id)
+        .bind(booking.start_time)
+        .bind(booking.end_time)
+        .execute(self)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+impl ScheduleRepo for PgPool {
+    async fn opening_windows(
+        &self,
+        company_id: &str,
+        from: NaiveDate,
+        to: NaiveDate,
+    ) -> Result<Vec<OpeningWindow>, AppError> {
+        Ok(sqlx::query_as(
+            "SELECT local_date, opens_at, closes_at, timezone, utc_offset
+             FROM public.get_opening_windows($1::uuid, $2, $3)",
+        )
+        .bind(company_id)
+        .bind(from)
+        .bind(to)
+        .fetch_all(self)
+        .await?)
+    }
+
+    async fn booked_slots(
+        &self,
+        staff_id: &str,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<BookedSlot>, AppError> {
+        Ok(sqlx::query_as(
+            "SELECT b.start_time AS booked_start, b.end_time AS booked_end
+             FROM public.booking b
+             WHERE b.staff_id = $1::uuid AND b.holds_slot
+             AND tstzrange(b.start_time, b.end_time) && tstzrange($2, $3)
+             ORDER BY b.start_time",
+        )
+        .bind(staff_id)
+        .bind(from)
+        .bind(to)
+        .fetch_all(self)
+        .await?)
+    }
+
+    async fn service_duration(
+        &self,
+        company_id: &str,
+        service_id: &str,
+    ) -> Result<Option<TimeDelta>, AppError> {
+        let seconds: Option<i64> = sqlx::query_scalar(
+            "SELECT EXTRACT(EPOCH FROM duration)::bigint FROM public.services
+             WHERE id = $1::uuid AND company_id = $2::uuid",
+        )
+        .bind(service_id)
+        .bind(company_id)
+        .fetch_optional(self)
+        .await?;
+        Ok(seconds.map(TimeDelta::seconds))
+    }
+
+    async fn is_team_member(&self, company_id: &str, person_id: &str) -> Result<bool, AppError> {
+        Ok(sqlx::query_scalar(
+            "SELECT EXISTS (
+                 SELECT 1 FROM public.role
+                 WHERE company_id = $1::uuid AND person_id = $2::uuid
+                 AND role_name IN ('owner', 'admin', 'staff')
+             )",
+        )
+        .bind(company_id)
+        .bind(person_id)
+        .fetch_one(self)
+        .await?)
+    }
+
+    async fn available_staff(
+        &self,
+        company_id: &str,
+        start_time: DateTime<Utc>,
+        end_time: DateTime<Utc>,
+    ) -> Result<Option<Uuid>, AppError> {
+        Ok(
+            sqlx::query_scalar("SELECT public.check_and_assign_staff($1::uuid, $2, $3)")
+                .bind(company_id)
+                .bind(start_time)
+                .bind(end_time)
+                .fetch_one(self)
+                .await?,
+        )
+    }
+
+    async fn overlapping_bookings(
+        &self,
+        company_id: &str,
+        staff_id: &str,
+        start_time: DateTime<Utc>,
+        end_time: DateTime<Utc>,
+        except_id: Option<&str>,
+    ) -> Result<Vec<StaffBooking>, AppError> {
+        Ok(sqlx::query_as(
+            "SELECT b.id::text AS id, b.start_time, b.end_time
+             FROM public.booking b
+             WHERE b.company_id = $1::uuid AND b.staff_id = $2::uuid
+             AND ($5::uuid IS NULL OR b.id <> $5::uuid)
+             AND b.status_id IN (SELECT id FROM public.status WHERE name IN ('pending', 'confirmed', 'in_progress'))
+             AND tstzrange(b.start_time, b.end_time) && tstzrange($3, $4)
+             ORDER BY b.start_time",
+        )
+        .bind(company_id)
+        .bind(staff_id)
+        .bind(start_time)
+        .bind(end_time)
+        .bind(except_id)
+        .fetch_all(self)
+        .await?)
+    }
+
+    async fn bookable_staff(
+        &self,
+        company_id: &str,
+        staff_id: Option<&str>,
+    ) -> Result<Vec<StaffMember>, AppError> {
+        Ok(sqlx::query_as(
+            "SELECT p.id::text AS id, pi.first_name, pi.last_name
+             FROM public.people p
+             JOIN public.personal_information pi ON pi.person_id = p.id
+             WHERE EXISTS (
+                 SELECT 1 FROM public.role r
+                 WHERE r.person_id = p.id AND r.company_id = $1::uuid
+                 AND CASE WHEN $2::uuid IS NULL THEN r.role_name = 'staff'
+                     ELSE p.id = $2::uuid AND r.role_name IN ('owner', 'admin', 'staff') END
+             )
+             ORDER BY pi.first_name, pi.last_name, p.id",
+        )
+        .bind(company_id)
+        .bind(staff_id)
+        .fetch_all(self)
+        .await?)
+    }
+}
+
+impl PaymentRepo for PgPool {
+    async fn payment_exists(&self, payment_id: Uuid) -> Result<bool, AppError> {
+        Ok(
+            sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM public.payments WHERE id = $1)")
+                .bind(payment_id)
+                .fetch_one(self)
+                .await?,
+        )
+    }
+
+    async fn insert_payment(
+        &self,
+        company_id: &str,
+        operator_id: &str,
+        payment_id: Uuid,
+        checkout: &Checkout,
+        completion: Option<&StatusChange>,
+    ) -> Result<bool, AppError> {
+        // Dropping the transaction on an early return rolls it back
+        let mut tx = self.begin().await?;
+
+        sqlx::query(
+            "INSERT INTO public.payments (id, amount, currency_id, payment_method, person_id, company_id, status, operator_id)
+             VALUES ($1, $2, $3::uuid, $4, $5::uuid, $6::uuid, $7, $8::uuid)",
+        )
+        .bind(payment_id)
+        .bind(checkout.amount)
+        .bind(&checkout.currency_id)
+        .bind(&checkout.method)
+        .bind(&checkout.customer_id)
+        .bind(company_id)
+        .bind(&checkout.status)
+        .bind(operator_id)
+        .execute(&mut *tx)
+        .await?;
+
+        if let (Some(booking_id), Some(change)) = (&checkout.booking_id, completion) {
+            let updated = sqlx::query(
+                "UPDATE booking SET status_id = $1::uuid
+                 WHERE id = $2::uuid AND company_id = $3::uuid AND status_id = $4::uuid",
+            )
+            .bind(&change.status_id)
+            .bind(booking_id)
+            .bind(company_id)
+            .bind(&change.from_status_id)
+            .execute(&mut *tx)
+            .await?;
+            if updated.rows_affected() == 0 {
+                return Ok(false);
+            }
+        }
+
+        for (linkable_id, linkable_type) in checkout.links() {
+            sqlx::query(
+                "INSERT INTO public.payment_linkable (payment_id, linkable_id, linkable_type)
+                 VALUES ($1, $2::uuid, $3)",
+            )
+            .bind(payment_id)
+            .bind(linkable_id)
+            .bind(linkable_type)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+        Ok(true)
+    }
+}
+
+impl CustomerRepo for PgPool {
+    async fn upsert_customer(
+        &self,
+        company_id: &str,
+        customer: &CustomerInput,
+    ) -> Result<Option<Uuid>, AppError> {
+        Ok(sqlx::query_scalar(
+            "SELECT public.create_or_update_customer($1, $2::uuid, $3, $4, $5::date, $6, $7, $8, $9, $10, $11, $12, $13)",
+        )
+        .bind(&customer.phone)
+        .bind(company_id)
+        .bind(&customer.first_name)
+        .bind(&customer.last_name)
+        .bind(&customer.date_of_birth)
+        .bind(&customer.gender)
+        .bind(&customer.email)
+        .bind(customer.address.as_ref().map(|a| a.street.clone()))
+        .bind(customer.address.as_ref().map(|a| a.city.clone()))
+        .bind(customer.address.as_ref().map(|a| a.state.clone()))
+        .bind(customer.address.as_ref().map(|a| a.postal_code.clone())) // p_postal_code in the SQL function
+        .bind(customer.address.as_ref().map(|a| a.country.clone()))
+        .bind(&customer.notes) // p_note in the SQL function
+        .fetch_one(self)
+        .await?)
+    }
+
+    async fn update_customer(&self, customer: &CustomerInput) -> Result<Option<Uuid>, AppError> {
+        Ok(sqlx::query_scalar(
+            "SELECT public.update_person_by_id($1::uuid, $2, $3, $4::date, $5, $6, $7, $8, $9, $10, $11, $12)",
+        )
+        .bind(&customer.id)
+        .bind(&customer.first_name)
+        .bind(&customer.last_name)
+        .bind(&customer.date_of_birth)
+        .bind(&customer.gender)
+        .bind(&customer.email)
+        .bind(customer.address.as_ref().map(|a| a.street.clone()))
+        .bind(customer.address.as_ref().map(|a| a.city.clone()))
+        .bind(customer.address.as_ref().map(|a| a.state.clone()))
+        .bind(customer.address.as_ref().map(|a| a.postal_code.clone())) // p_postal_code in the SQL function
+        .bind(customer.address.as_ref().map(|a| a.country.clone()))
+        .bind(&customer.notes) // p_note in the SQL function
+        .fetch_one(self)
+        .await?)
+    }
+
+    async fn is_company_customer(
+        &self,
+        company_id: &str,
+        customer_id: &str,
+    ) -> Result<bool, AppError> {
+        Ok(sqlx::query_scalar(
+            "SELECT EXISTS (
+                 SELECT 1 FROM public.role
+                 WHERE person_id = $1::uuid AND company_id = $2::uuid AND role_name = 'customer'
+             )",
+        )
+        .bind(customer_id)
+        .bind(company_id)
+        .fetch_one(self)
+        .await?)
+    }
+
+    async fn delete_customer(
+        &self,
+        company_id: Uuid,
+        customer_id: Uuid,
+    ) -> Result<Option<Uuid>, AppError> {
+        let deleted = sqlx::query!(
+            "DELETE FROM people WHERE id = $1
+             AND EXISTS (
+                 SELECT 1 FROM public.role
+                 WHERE person_id = $1 AND company_id = $2 AND role_name = 'customer'
+             )
+             RETURNING id",
+            customer_id,
+            company_id
+        )
+        .fetch_optional(self)
+        .await?;
+
+        Ok(deleted.map(|record| record.id))
+    }
+}
+
+impl CampaignRepo for PgPool {
+    async fn campaign_linked(&self, company_id: Uuid, campaign_id: Uuid) -> Result<bool, AppError> {
+        let link = sqlx::query!(
+            "SELECT id FROM public.campaign_linkable
+             WHERE campaign_id = $1::uuid
+             AND linkable_id = $2::uuid
+             AND linkable_type = 'company'",
+            campaign_id,
+            company_id
+        )
+        .fetch_optional(self)
+        .await?;
+
+        Ok(link.is_some())
+    }
+
+    async fn link_campaign(&self, company_id: Uuid, campaign_id: Uuid) -> Result<(), AppError> {
+        sqlx::query!(
+            "INSERT INTO public.campaign_linkable
+             (campaign_id, linkable_id, linkable_type)
+             VALUES ($1::uuid, $2::uuid, 'company')",
+            campaign_id,
+            company_id
+        )
+        .execute(self)
+        .await?;
+        Ok(())
+    }
+
+    async fn unlink_campaign(&self, company_id: Uuid, campaign_id: Uuid) -> Result<(), AppError> {
+        sqlx::query!(
+            "DELETE FROM public.campaign_linkable
+             WHERE campaign_id = $1::uuid
+             AND linkable_id = $2::uuid
+             AND linkable_type = 'company'",
+            campaign_id,
+            company_id
+        )
+        .execute(self)
+        .await?;
+        Ok(())
+    }
+}
+
+impl OperatorRepo for PgPool {
+    async fn operator_pin(&self, person_id: &str) -> Result<Option<OperatorPin>, AppError> {
+        Ok(sqlx::query_as(
+            "SELECT pin_hash, failed_attempts, locked_until
+             FROM public.login_pin WHERE person_id = $1::uuid",
+        )
+        .bind(person_id)
+        .fetch_optional(self)
+        .await?)
+    }
+
+    async fn count_pin_failure(
+        &self,
+        person_id: &str,
+        window_secs: i64,
+    ) -> Result<(i32, DateTime<Utc>), AppError> {
+        Ok(sqlx::query_as(
+            "UPDATE public.login_pin
+             SET failed_attempts = CASE
+                     WHEN last_failed_at > NOW() - make_interval(secs => $1) THEN failed_attempts + 1
+                     ELSE 1
+                 END,
+                 last_failed_at = NOW()
+             WHERE person_id = $2::uuid
+             RETURNING failed_attempts, last_failed_at",
+        )
+        .bind(window_secs as f64)
+        .bind(person_id)
+        .fetch_one(self)
+        .await?)
+    }
+
+    async fn lock_pin(&self, person_id: &str, until: DateTime<Utc>) -> Result<(), AppError> {
+        sqlx::query(
+            "UPDATE public.login_pin SET locked_until = GREATEST(locked_until, $1)
+             WHERE person_id = $2::uuid",
+        )
+        .bind(until)
+        .bind(person_id)
+        .execute(self)
+        .await?;
+        Ok(())
+    }
+
+    async fn clear_pin_failures(&self, person_id: &str) -> Result<(), AppError> {
+        sqlx::query(
+            "UPDATE public.login_pin
+             SET failed_attempts = 0, last_failed_at = NULL, locked_until = NULL
+             WHERE person_id = $1::uuid",
+        )
+        .bind(person_id)
+        .execute(self)
+        .await?;
+        Ok(())
+    }
+}
+
+impl CompanyRepo for PgPool {
+    // The database only honours tokens that are active, unexpired and belong to a member of
+    // the company, and returns NULL otherwise
+    async fn company_details(
+        &self,
+        token: &str,
+        company_id: &str,
+    ) -> Result<Option<AuthData>, AppError> {
+        let result: Option<Value> =
+            sqlx::query_scalar("SELECT public.get_company_details_by_token($1, $2::uuid)")
+                .bind(token)
+                .bind(company_id)
+                .fetch_one(self)
+                .await?;
+
+        result
+            .map(serde_json::from_value)
+            .transpose()
+            .map_err(|e| AppError::Message(format!("Failed to parse authentication data: {}", e)))
+    }
+
+    async fn company_booking(
+        &self,
+        company_id: &str,
+        booking_id: &str,
+    ) -> Result<Option<Booking>, AppError> {
+        let result: Option<Value> =
+            sqlx::query_scalar("SELECT public.get_company_booking($1::uuid, $2::uuid)")
+                .bind(company_id)
+                .bind(booking_id)
+                .fetch_one(self)
+                .await?;
+
+        result
+            .map(serde_json::from_value)
+            .transpose()
+            .map_err(|e| AppError::Message(format!("Failed to parse booking: {}", e)))
+    }
+
+    async fn company_customer(
+        &self,
+        company_id: &str,
+        customer_id: &str,
+    ) -> Result<Option<Customer>, AppError> {
+        let result: Option<Value> =
+            sqlx::query_scalar("SELECT public.get_company_customer($1::uuid, $2::uuid)")
+                .bind(company_id)
+                .bind(customer_id)
+                .fetch_one(self)
+                .await?;
+
+        result
+            .map(serde_json::from_value)
+            .transpose()
+            .map_err(|e| AppError::Message(format!("Failed to parse customer: {}", e)))
+    }
+}
@@ -0,0 +1,120 @@
+use chrono::{DateTime, Days, FixedOffset, Offset, TimeDelta, Utc};
+
+use crate::{
+    error::AppError,
+    repo::{BookedSlot, OpeningWindow, ScheduleRepo, StaffBooking},
+};
+
+// Why a booking can't go at the times asked for, one entry per reason
+#[derive(serde::Serialize, Clone, Debug)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ScheduleConflict {
+    // No opening window holds the whole booking. The hours open that local day, if any.
+    OutsideOpeningHours { opening_hours: Vec<OpeningHours> },
+    // The staff member already has these bookings then
+    StaffBooked { bookings: Vec<StaffBooking> },
+}
+
+#[derive(serde::Serialize, Clone, Debug)]
+pub struct OpeningHours {
+    pub opens_at: DateTime<FixedOffset>,
+    pub closes_at: DateTime<FixedOffset>,
+}
+
+// Booking times are zoned, so the instant is never ambiguous, e.g. `2026-10-19T09:30:00+11:00`
+pub fn parse_instant(value: &str) -> Result<DateTime<Utc>, AppError> {
+    DateTime::parse_from_rfc3339(value)
+        .map(|time| time.with_timezone(&Utc))
+        .map_err(|_| {
+            AppError::Invalid(format!(
+                "Invalid time {}, expected a date and time with its UTC offset",
+                value
+            ))
+        })
+}
+
+pub fn overlaps(slot: &BookedSlot, start: DateTime<Utc>, end: DateTime<Utc>) -> bool {
+    slot.booked_start < end && start < slot.booked_end
+}
+
+// The opening window the whole of `start..end` falls in, if any
+pub fn opening_window(
+    windows: &[OpeningWindow],
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+) -> Option<&OpeningWindow> {
+    windows
+        .iter()
+        .find(|window| window.opens_at <= start && end <= window.closes_at)
+}
+
+// Opening windows around `start..end`. Local dates can be a day either side of the UTC ones,
+// so one extra day is loaded on each side.
+pub async fn windows_around<R: ScheduleRepo>(
+    repo: &R,
+    company_id: &str,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+) -> Result<Vec<OpeningWindow>, AppError> {
+    let from = start.date_naive() - Days::new(1);
+    let to = end.date_naive() + Days::new(1);
+    repo.opening_windows(company_id, from, to).await
+}
+
+// The first of the staff member's bookings that overlaps `start..end`, including one that
+// started before it
+pub async fn staff_conflict<R: ScheduleRepo>(
+    repo: &R,
+    company_id: &str,
+    staff_id: &str,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+) -> Result<Option<StaffBooking>, AppError> {
+    let booked = repo
+        .overlapping_bookings(company_id, staff_id, start, end, None)
+        .await?;
+    Ok(booked.into_iter().next())
+}
+
+// Start times from when `window` opens, every `step`, that start no earlier than `earliest`,
+// end by closing time and overlap none of `booked`
+pub fn free_starts(
+    window: &OpeningWindow,
+    duration: TimeDelta,
+    step: TimeDelta,
+    earliest: DateTime<Utc>,
+    booked: &[BookedSlot],
+) -> Vec<DateTime<Utc>> {
+    let mut starts = Vec::new();
+    let mut start = window.opens_at;
+    while start + duration <= window.closes_at {
+        let end = start + duration;
+        if start >= earliest && !booked.iter().any(|slot| overlaps(slot, start, end)) {
+            starts.push(start);
+        }
+        start += step;
+    }
+    starts
+}
+
+// `time` in the window's own zone, at the offset it opened with
+pub fn local_time(window: &OpeningWindow, time: DateTime<Utc>) -> DateTime<FixedOffset> {
+    let zone = FixedOffset::east_opt(window.utc_offset).unwrap_or_else(|| Utc.fix());
+    time.with_timezone(&zone)
+}
+
+// The opening hours on the local date `time` falls on
+pub fn hours_on(windows: &[OpeningWindow], time: DateTime<Utc>) -> Vec<OpeningHours> {
+    windows
+        .iter()
+        .filter(|window| local_time(window, time).date_naive() == window.local_date)
+        .map(|window| OpeningHours {
+            opens_at: local_time(window, window.opens_at),
+            closes_at: local_time(window, window.closes_at),
+        })
+        .collect()
+}
+
+pub fn describe(time: DateTime<Utc>) -> String {
+    time.format("%Y-%m-%d %H:%M UTC").to_string()
+}
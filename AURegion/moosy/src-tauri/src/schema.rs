@@ -0,0 +1,174 @@
+use futures::future::BoxFuture;
+use sqlx::{
+    error::BoxDynError,
+    migrate::{Migration, MigrationSource, Migrator},
+    PgPool,
+};
+use std::{borrow::Cow, fmt};
+
+use crate::error::AppError;
+
+// The Supabase migrations, built into the app. They can set up a fresh Postgres, and the
+// newest one is the schema version this build expects.
+pub static MIGRATOR: Migrator = sqlx::migrate!("../../migrations/supabase/migrations");
+
+// The database's schema doesn't match what this build was written against
+#[derive(serde::Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SchemaMismatch {
+    // Newest migration applied to the database, None when it has no migration history
+    pub found: Option<i64>,
+    pub expected: i64,
+}
+
+impl fmt::Display for SchemaMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.found {
+            None => write!(
+                f,
+                "The database has no migration history, so it can't be checked against this app (schema {})",
+                self.expected
+            ),
+            Some(found) if found < self.expected => write!(
+                f,
+                "The database schema ({}) is older than this app needs ({}), apply the pending migrations",
+                found, self.expected
+            ),
+            Some(found) => write!(
+                f,
+                "The database schema ({}) is newer than this app supports ({}), update the app",
+                found, self.expected
+            ),
+        }
+    }
+}
+
+pub fn expected_version() -> i64 {
+    MIGRATOR.iter().map(|m| m.version).max().unwrap_or(0)
+}
+
+// A database set up by the Supabase CLI records its migrations there rather than in
+// sqlx's `_sqlx_migrations`
+async fn supabase_managed(pool: &PgPool) -> Result<bool, AppError> {
+    Ok(sqlx::query_scalar(
+        "SELECT to_regclass('supabase_migrations.schema_migrations') IS NOT NULL",
+    )
+    .fetch_one(pool)
+    .await?)
+}
+
+// The newest migration applied, from whichever history the database keeps
+pub async fn installed_version(pool: &PgPool) -> Result<Option<i64>, AppError> {
+    if supabase_managed(pool).await? {
+        return Ok(sqlx::query_scalar(
+            "SELECT MAX(version::bigint) FROM supabase_migrations.schema_migrations",
+        )
+        .fetch_one(pool)
+        .await?);
+    }
+
+    let sqlx_managed: bool =
+        sqlx::query_scalar("SELECT to_regclass('public._sqlx_migrations') IS NOT NULL")
+            .fetch_one(pool)
+            .await?;
+    if !sqlx_managed {
+        return Ok(None);
+    }
+    Ok(
+        sqlx::query_scalar("SELECT MAX(version) FROM public._sqlx_migrations WHERE success")
+            .fetch_one(pool)
+            .await?,
+    )
+}
+
+// Refuse to work against a schema that is older or newer than this build
+pub async fn check(pool: &PgPool) -> Result<(), AppError> {
+    let found = installed_version(pool).await?;
+    let expected = expected_version();
+    if found == Some(expected) {
+        Ok(())
+    } else {
+        Err(AppError::SchemaMismatch(SchemaMismatch { found, expected }))
+    }
+}
+
+// Migrations that only work on Supabase: they schedule pg_cron jobs, need the http extension
+// or, in the case of 20250404104150, don't parse. A local Postgres records them without
+// running them, and 20261018170000 sets up what they left behind on either.
+const SUPABASE_ONLY: &[i64] = &[
+    20250404104150,
+    20250404104524,
+    20250404115101,
+    20250405023548,
+    20250405024233,
+    20250405024318,
+    20250405024404,
+    20250405024628,
+    20250405025458,
+    20250405025948,
+];
+
+// The embedded migrations as a local Postgres runs them
+#[derive(Debug)]
+struct LocalMigrations;
+
+impl MigrationSource<'static> for LocalMigrations {
+    fn resolve(self) -> BoxFuture<'static, Result<Vec<Migration>, BoxDynError>> {
+        let migrations = MIGRATOR
+            .iter()
+            .map(|migration| {
+                if !SUPABASE_ONLY.contains(&migration.version) {
+                    return migration.clone();
+                }
+                Migration::new(
+                    migration.version,
+                    migration.description.clone(),
+                    migration.migration_type,
+                    Cow::Borrowed("SELECT 1"),
+                    migration.no_tx,
+                )
+            })
+            .collect();
+        Box::pin(async move { Ok(migrations) })
+    }
+}
+
+// Apply the pending embedded migrations. Only for databases this app set up itself; a
+// Supabase project gets its migrations through the Supabase CLI.
+pub async fn migrate(pool: &PgPool) -> Result<(), AppError> {
+    if supabase_managed(pool).await? {
+        return Err(AppError::Invalid(
+            "This database is managed by the Supabase CLI, apply migrations with `supabase db push`"
+                .to_string(),
+        ));
+    }
+    let failed = |e: sqlx::migrate::MigrateError| {
+        AppError::Message(format!("Failed to migrate the database: {}", e))
+    };
+    Migrator::new(LocalMigrations)
+        .await
+        .map_err(failed)?
+        .run(pool)
+        .await
+        .map_err(failed)?;
+    log::info!("Database migrated to schema {}", expected_version());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn local_migrations_only_skip_the_supabase_ones() {
+        let local = Migrator::new(LocalMigrations).await.unwrap();
+        assert_eq!(local.iter().count(), MIGRATOR.iter().count());
+        for (local, embedded) in local.iter().zip(MIGRATOR.iter()) {
+            assert_eq!(local.version, embedded.version);
+            let skipped = SUPABASE_ONLY.contains(&local.version);
+            assert_eq!(local.sql == embedded.sql, !skipped, "{}", local.version);
+        }
+        for version in SUPABASE_ONLY {
+            assert!(MIGRATOR.version_exists(*version), "{}", version);
+        }
+    }
+}
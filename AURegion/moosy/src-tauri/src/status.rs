@@ -0,0 +1,193 @@
+use std::{collections::HashMap, fmt, sync::RwLock};
+
+use crate::{
+    error::AppError,
+    repo::{BookingRepo, StatusChange},
+};
+
+// A booking's status, by its `public.status` name
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum BookingStatus {
+    Pending,
+    Confirmed,
+    InProgress,
+    Completed,
+    Cancelled,
+    NoShow,
+}
+
+impl BookingStatus {
+    pub fn from_name(name: &str) -> Option<BookingStatus> {
+        match name {
+            "pending" => Some(BookingStatus::Pending),
+            "confirmed" => Some(BookingStatus::Confirmed),
+            "in_progress" => Some(BookingStatus::InProgress),
+            "completed" => Some(BookingStatus::Completed),
+            "cancelled" => Some(BookingStatus::Cancelled),
+            "no_show" => Some(BookingStatus::NoShow),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            BookingStatus::Pending => "pending",
+            BookingStatus::Confirmed => "confirmed",
+            BookingStatus::InProgress => "in_progress",
+            BookingStatus::Completed => "completed",
+            BookingStatus::Cancelled => "cancelled",
+            BookingStatus::NoShow => "no_show",
+        }
+    }
+
+    // Where a booking can go from here. A pending booking can be paid for straight away;
+    // completed, cancelled and no-show are final.
+    pub fn next(self) -> &'static [BookingStatus] {
+        use BookingStatus::*;
+        match self {
+            Pending => &[Confirmed, InProgress, Completed, Cancelled, NoShow],
+            Confirmed => &[InProgress, Completed, Cancelled, NoShow],
+            InProgress => &[Completed],
+            Completed | Cancelled | NoShow => &[],
+        }
+    }
+
+    pub fn can_become(self, status: BookingStatus) -> bool {
+        self.next().contains(&status)
+    }
+
+    // Still holds the staff member's time, as the overlap checks in SQL count it
+    pub fn occupies_slot(self) -> bool {
+        matches!(
+            self,
+            BookingStatus::Pending | BookingStatus::Confirmed | BookingStatus::InProgress
+        )
+    }
+}
+
+impl fmt::Display for BookingStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            BookingStatus::InProgress => "in progress",
+            BookingStatus::NoShow => "no-show",
+            status => status.as_str(),
+        })
+    }
+}
+
+// Status ids by name, loaded from `public.status` the first time one is needed. The ids
+// differ between databases, so none are written into the app.
+#[derive(Default)]
+pub struct Statuses(RwLock<HashMap<BookingStatus, String>>);
+
+impl Statuses {
+    pub async fn id<R: BookingRepo>(
+        &self,
+        repo: &R,
+        status: BookingStatus,
+    ) -> Result<String, AppError> {
+        let cached = self
+            .0
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(&status)
+            .cloned();
+        if let Some(id) = cached {
+            return Ok(id);
+        }
+
+        let loaded: HashMap<BookingStatus, String> = repo
+            .statuses()
+            .await?
+            .into_iter()
+            .filter_map(|(name, id)| Some((BookingStatus::from_name(&name)?, id)))
+            .collect();
+        let id = loaded.get(&status).cloned();
+        *self.0.write().unwrap_or_else(|e| e.into_inner()) = loaded;
+        id.ok_or_else(|| {
+            AppError::Message(format!("The database has no {} status", status.as_str()))
+        })
+    }
+}
+
+// The booking's current status, if it may become `status`. None when the company has no
+// such booking.
+pub async fn check_transition<R: BookingRepo>(
+    repo: &R,
+    company_id: &str,
+    booking_id: &str,
+    status: BookingStatus,
+) -> Result<Option<BookingStatus>, AppError> {
+    let booking = match repo.booking(company_id, booking_id).await? {
+        Some(booking) => booking,
+        None => return Ok(None),
+    };
+    let current = booking
+        .status
+        .as_deref()
+        .and_then(BookingStatus::from_name)
+        .ok_or_else(|| AppError::Invalid("The booking has no known status".to_string()))?;
+    // Asking again for the status it has is fine, e.g. a cancellation replayed after sync
+    if current != status && !current.can_become(status) {
+        return Err(AppError::Conflict(format!(
+            "A {} booking can't be marked {}",
+            current, status
+        )));
+    }
+    Ok(Some(current))
+}
+
+// The status ids to move a booking to `status` by, if its current status allows it. None
+// when the company has no such booking.
+pub async fn plan_transition<R: BookingRepo>(
+    repo: &R,
+    statuses: &Statuses,
+    company_id: &str,
+    booking_id: &str,
+    status: BookingStatus,
+) -> Result<Option<StatusChange>, AppError> {
+    let current = match check_transition(repo, company_id, booking_id, status).await? {
+        Some(current) => current,
+        None => return Ok(None),
+    };
+    Ok(Some(StatusChange {
+        from_status_id: statuses.id(repo, current).await?,
+        status_id: statuses.id(repo, status).await?,
+    }))
+}
+
+pub fn changed_meanwhile() -> AppError {
+    AppError::Conflict("The booking was changed meanwhile, please try again".to_string())
+}
+
+// Move a booking to `status` if its current one allows it, false when the company has no
+// such booking. The update only applies while the status is still the one checked.
+pub async fn transition<R: BookingRepo>(
+    repo: &R,
+    statuses: &Statuses,
+    company_id: &str,
+    booking_id: &str,
+    status: BookingStatus,
+) -> Result<bool, AppError> {
+    let change = match plan_transition(repo, statuses, company_id, booking_id, status).await? {
+        Some(change) => change,
+        None => return Ok(false),
+    };
+    if change.from_status_id == change.status_id {
+        return Ok(true);
+    }
+
+    let updated = repo
+        .set_booking_status(
+            company_id,
+            booking_id,
+            &change.from_status_id,
+            &change.status_id,
+        )
+        .await?;
+    if !updated {
+        return Err(changed_meanwhile());
+    }
+    Ok(true)
+}
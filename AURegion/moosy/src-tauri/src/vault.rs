@@ -0,0 +1,205 @@
+use argon2::{
+    password_hash::rand_core::{OsRng, RngCore},
+    Argon2,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use sha2::{Digest, Sha256};
+use tauri::Manager;
+
+use crate::{crypto, error::AppError};
+
+// Bumped if the envelope or key derivation ever changes
+const ENVELOPE_VERSION: u32 = 1;
+// Binds sealed files to this use so ciphertext can't be moved between stores
+const AAD: &[u8] = b"moosy-local-cache-v1";
+
+// What actually lands on disk: authenticated ciphertext of the serialized value
+#[derive(serde::Serialize, serde::Deserialize)]
+struct Envelope {
+    version: u32,
+    ciphertext: String,
+}
+
+// Outcome of reading a local cache file
+#[derive(Debug, PartialEq)]
+pub enum Opened<T> {
+    Sealed(T),
+    // Written before encryption was introduced; the caller should re-save it sealed
+    Plaintext(T),
+}
+
+// Encrypts the local caches (auth.json and friends) with a per-device key. The key comes
+// from a random device secret kept in the config dir, stretched with the optional
+// cache passphrase from the config.
+//
+// device.key is a plain file in the same user profile as the caches, not in the OS
+// keychain. Without a passphrase the encryption therefore only protects a cache file that
+// is copied or leaked on its own; anyone who can read the whole profile can also read the
+// key. Terminals that need more should set `auth_cache_passphrase`.
+pub struct Vault {
+    key: [u8; crypto::KEY_LEN],
+    // Plaintext caches are only migrated by the run that created the device key, i.e. the
+    // first run of a version with encryption. After that a plaintext file is rejected, so
+    // one can't be slipped in next to the sealed ones.
+    accepts_plaintext: bool,
+}
+
+impl Vault {
+    pub fn load(app: &tauri::AppHandle, passphrase: Option<&str>) -> Result<Vault, AppError> {
+        let (secret, created) = device_secret(app)?;
+        Vault::new(&secret, passphrase, created)
+    }
+
+    fn new(
+        secret: &[u8],
+        passphrase: Option<&str>,
+        accepts_plaintext: bool,
+    ) -> Result<Vault, AppError> {
+        let mut key = [0u8; crypto::KEY_LEN];
+        match passphrase {
+            Some(passphrase) if !passphrase.is_empty() => Argon2::default()
+                .hash_password_into(passphrase.as_bytes(), secret, &mut key)
+                .map_err(|e| format!("Failed to derive cache key: {}", e))?,
+            _ => key.copy_from_slice(&Sha256::digest([AAD, secret].concat())),
+        }
+
+        Ok(Vault {
+            key,
+            accepts_plaintext,
+        })
+    }
+
+    pub fn seal<T: Serialize>(&self, value: &T) -> Result<String, AppError> {
+        let plaintext =
+            serde_json::to_vec(value).map_err(|e| format!("Failed to serialize data: {}", e))?;
+        let sealed = crypto::seal(&self.key, &plaintext, AAD)?;
+
+        serde_json::to_string(&Envelope {
+            version: ENVELOPE_VERSION,
+            ciphertext: hex::encode(sealed),
+        })
+        .map_err(|e| AppError::Message(format!("Failed to serialize data: {}", e)))
+    }
+
+    // Decrypt and verify a sealed file. Plaintext JSON from older versions is only accepted
+    // while migrating, see `accepts_plaintext`; anything else that fails to verify is rejected.
+    pub fn open<T: DeserializeOwned>(&self, content: &str) -> Result<Opened<T>, AppError> {
+        let envelope: Envelope = match serde_json::from_str(content) {
+            Ok(envelope) => envelope,
+            Err(_) if !self.accepts_plaintext => {
+                return Err(AppError::Message(
+                    "Local cache is not sealed with this device's key".to_string(),
+                ))
+            }
+            Err(_) => {
+                return serde_json::from_str(content)
+                    .map(Opened::Plaintext)
+                    .map_err(|_| {
+                        AppError::Message("Local cache is neither sealed nor readable".to_string())
+                    })
+            }
+        };
+
+        if envelope.version != ENVELOPE_VERSION {
+            return Err(AppError::Message(format!(
+                "Unsupported local cache version {}",
+                envelope.version
+            )));
+        }
+
+        let sealed = hex::decode(&envelope.ciphertext)
+            .map_err(|_| "Local cache failed its integrity check".to_string())?;
+        let plaintext = crypto::open(&self.key, &sealed, AAD)
+            .map_err(|_| "Local cache failed its integrity check".to_string())?;
+
+        serde_json::from_slice(&plaintext)
+            .map(Opened::Sealed)
+            .map_err(|e| AppError::Message(format!("Failed to parse local cache: {}", e)))
+    }
+}
+
+// Random secret generated on first run and kept next to the other per-device settings.
+// Also says whether this call created it.
+fn device_secret(app: &tauri::AppHandle) -> Result<(Vec<u8>, bool), AppError> {
+    let dir = app.path().app_config_dir().map_err(|e| e.to_string())?;
+    let path = dir.join("device.key");
+
+    if let Ok(content) = std::fs::read_to_string(&path) {
+        return hex::decode(content.trim())
+            .map(|secret| (secret, false))
+            .map_err(|_| AppError::Message("Device key is corrupt".to_string()));
+    }
+
+    let mut secret = vec![0u8; crypto::KEY_LEN];
+    OsRng.fill_bytes(&mut secret);
+
+    std::fs::create_dir_all(&dir)
+        .map_err(|e| format!("Failed to create app config directory: {}", e))?;
+    std::fs::write(&path, hex::encode(&secret))
+        .map_err(|e| format!("Failed to write device key: {}", e))?;
+
+    // Keep the key readable by the current user only
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600))
+            .map_err(|e| format!("Failed to protect device key: {}", e))?;
+    }
+
+    Ok((secret, true))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: [u8; crypto::KEY_LEN] = [7; crypto::KEY_LEN];
+
+    fn vault(accepts_plaintext: bool) -> Vault {
+        Vault::new(&SECRET, None, accepts_plaintext).unwrap()
+    }
+
+    #[test]
+    fn seal_and_open_round_trip() {
+        let vault = vault(false);
+        let sealed = vault.seal(&vec!["token", "company"]).unwrap();
+        assert!(!sealed.contains("token"));
+        assert_eq!(
+            vault.open::<Vec<String>>(&sealed).unwrap(),
+            Opened::Sealed(vec!["token".to_string(), "company".to_string()])
+        );
+    }
+
+    #[test]
+    fn passphrase_changes_the_key() {
+        let sealed = vault(false).seal(&1).unwrap();
+        let other = Vault::new(&SECRET, Some("till passphrase"), false).unwrap();
+        assert!(other.open::<i32>(&sealed).is_err());
+    }
+
+    #[test]
+    fn tampered_ciphertext_is_rejected() {
+        let vault = vault(false);
+        let mut envelope: Envelope = serde_json::from_str(&vault.seal(&"token").unwrap()).unwrap();
+        let mut sealed = hex::decode(&envelope.ciphertext).unwrap();
+        let last = sealed.len() - 1;
+        sealed[last] ^= 1;
+        envelope.ciphertext = hex::encode(sealed);
+
+        let tampered = serde_json::to_string(&envelope).unwrap();
+        assert!(vault.open::<String>(&tampered).is_err());
+    }
+
+    #[test]
+    fn plaintext_is_migrated_on_the_first_run() {
+        assert_eq!(
+            vault(true).open::<Vec<i32>>("[1, 2]").unwrap(),
+            Opened::Plaintext(vec![1, 2])
+        );
+    }
+
+    #[test]
+    fn plaintext_is_rejected_after_migration() {
+        assert!(vault(false).open::<Vec<i32>>("[1, 2]").is_err());
+    }
+}